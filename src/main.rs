@@ -1,4 +1,4 @@
-use tauri::{State, Manager, Emitter, tray::TrayIconBuilder, menu::{MenuBuilder, MenuItemBuilder, SubmenuBuilder, CheckMenuItemBuilder}};
+use tauri::{State, Manager, Emitter, tray::TrayIconBuilder, menu::{MenuBuilder, MenuItemBuilder, SubmenuBuilder, CheckMenuItemBuilder, PredefinedMenuItem}};
 use image;
 use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
 use serde::{Deserialize, Serialize};
@@ -9,24 +9,171 @@ use base64::{Engine as _, engine::general_purpose};
 use arboard::Clipboard;
 use std::fs;
 use std::path::PathBuf;
+use log::{debug, error, info, warn};
+
+// 内存中的日志环形缓冲区，设置页面通过 get_recent_logs 查看最近的日志
+const LOG_BUFFER_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub message: String,
+}
+
+struct RingBufferLogger {
+    entries: std::sync::Mutex<std::collections::VecDeque<LogEntry>>,
+}
+
+impl log::Log for RingBufferLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        // 只记录本应用的日志，忽略依赖库（tauri、reqwest等）的输出
+        if !self.enabled(record.metadata()) || !record.target().starts_with("mathimage") {
+            return;
+        }
+
+        let message = record.args().to_string();
+        // 保留控制台输出
+        println!("[{}] {}", record.level(), message);
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= LOG_BUFFER_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(LogEntry {
+            timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            level: record.level().to_string(),
+            message,
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: RingBufferLogger = RingBufferLogger {
+    entries: std::sync::Mutex::new(std::collections::VecDeque::new()),
+};
+
+fn init_logging() {
+    if let Err(e) = log::set_logger(&LOGGER) {
+        println!("Failed to initialize logger: {}", e);
+        return;
+    }
+    log::set_max_level(log::LevelFilter::Info);
+}
+
+// 日志级别：off / error / warn / info / debug / trace（不区分大小写）
+fn parse_log_level(level: &str) -> Result<log::LevelFilter, String> {
+    level.trim().parse::<log::LevelFilter>()
+        .map_err(|_| format!("Unknown log level '{}', expected one of: off, error, warn, info, debug, trace", level))
+}
+
+fn apply_log_level(level: &str) {
+    match parse_log_level(level) {
+        Ok(filter) => log::set_max_level(filter),
+        Err(e) => warn!("{}, keeping current level", e),
+    }
+}
+
+#[tauri::command]
+async fn get_recent_logs(limit: Option<usize>) -> Result<Vec<LogEntry>, String> {
+    let entries = LOGGER.entries.lock().unwrap_or_else(|e| e.into_inner());
+    let limit = limit.unwrap_or(200).min(entries.len());
+    Ok(entries.iter().skip(entries.len() - limit).cloned().collect())
+}
+
+const KEYRING_SERVICE: &str = "mathimage";
+const KEYRING_PLACEHOLDER: &str = "__stored_in_keychain__";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiConfig {
     pub base_url: String,
     pub api_key: String,
     pub model: String,
+    // Additional endpoints tried in order when base_url is failing
+    #[serde(default)]
+    pub fallback_base_urls: Vec<String>,
+    // 模型列表的完整URL，部分网关的模型列表和chat不在同一路径下；不设置时使用 {base_url}/models
+    #[serde(default)]
+    pub models_url: Option<String>,
+    // 跳过TLS证书校验（自签名证书的内网服务），只影响该Profile的请求，默认关闭
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    // 不使用系统代理直接连接（内网服务经代理无法访问时）
+    #[serde(default)]
+    pub bypass_proxy: bool,
+}
+
+impl ApiConfig {
+    // 钥匙串读取失败时内存中保留占位符，按未配置处理，不能当作key发送出去
+    fn usable_api_key(&self) -> &str {
+        if self.api_key == KEYRING_PLACEHOLDER {
+            ""
+        } else {
+            &self.api_key
+        }
+    }
+
+    fn models_endpoint(&self) -> String {
+        resolve_models_url(&self.base_url, self.models_url.as_deref())
+    }
+}
+
+fn resolve_models_url(base_url: &str, models_url: Option<&str>) -> String {
+    match models_url.map(str::trim).filter(|url| !url.is_empty()) {
+        Some(url) => url.to_string(),
+        None => format!("{}/models", base_url),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PromptMode {
     Predefined(String),
     UserInput,
+    // 模板prompt，截图时替换 {date} {time} {profile_name} {model} {clipboard}
+    Template(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OutputMode {
     Clipboard,
     Dialog,
+    // 通过模拟键盘输入把结果打到当前焦点窗口
+    Type,
+    // 带 Copy / Retry / Edit prompt 按钮的结果对话框
+    ActionDialog,
+    // 追加到剪贴板已有文本之后，用于连续截取多个区域
+    ClipboardAppend,
+    // 剪贴板同时放入截图和识别文本，由粘贴目标选择需要的格式
+    ClipboardImageText,
+    // 复制到剪贴板并显示不阻塞的系统通知（"Copied (N chars)" + 预览）
+    ClipboardToast,
+    // 写入Profile的output_dir，文件名由filename_template生成
+    File,
+    // 把结果以JSON POST到webhook，失败时可选地退回到剪贴板
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: std::collections::HashMap<String, String>,
+        #[serde(default = "default_webhook_fallback")]
+        fallback_to_clipboard: bool,
+    },
+}
+
+fn default_webhook_fallback() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    result: &'a str,
+    model: &'a str,
+    profile: &'a str,
+    timestamp: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,41 +184,511 @@ pub struct Profile {
     pub prompt_mode: PromptMode,
     pub output_mode: OutputMode,
     // 移除hotkey字段 - 热键应该是全局的，不属于单个profile
+    // 命名prompt预设 (name, text)，可从托盘菜单快速切换
+    #[serde(default)]
+    pub prompt_presets: Vec<(String, String)>,
+    // 选中的预设索引，None表示使用prompt_mode
+    #[serde(default)]
+    pub active_preset: Option<usize>,
+    // 发送前的图像预处理（默认不处理）
+    #[serde(default)]
+    pub preprocess: PreprocessOptions,
+    // 区域截图时向四周扩展的像素数（限制在屏幕范围内）
+    #[serde(default)]
+    pub capture_padding: u32,
+    // 托盘模型列表的过滤字符串（不区分大小写的子串匹配）
+    #[serde(default)]
+    pub model_filter: String,
+    // 结果中数学公式的定界符风格，Keep表示不改写
+    #[serde(default)]
+    pub latex_delimiters: DelimiterStyle,
+    // 去掉模型包裹在整个结果外面的 ```markdown ... ``` 代码块
+    #[serde(default)]
+    pub strip_code_fences: bool,
+    // 只输出公式内容（去掉定界符和正文），适合只需要LaTeX的Profile
+    #[serde(default)]
+    pub extract_latex_only: bool,
+    // 本地限流：每分钟最多发送的请求数，0表示不限制
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    // 托盘模型列表的允许/屏蔽名单，支持 * 和 ? 通配符（不区分大小写）
+    // 允许名单非空时只显示匹配的模型；屏蔽名单优先
+    #[serde(default)]
+    pub model_allowlist: Vec<String>,
+    #[serde(default)]
+    pub model_denylist: Vec<String>,
+    // 只保留这些提供方（/models 的 owned_by 字段，不区分大小写）的模型，为空表示不过滤
+    #[serde(default)]
+    pub model_owners: Vec<String>,
+    // 最近从托盘选择的模型（最新的在前），在模型子菜单中置顶
+    #[serde(default)]
+    pub recent_models: Vec<String>,
+    // 热键截图方式：框选区域或点击选择整个窗口
+    #[serde(default)]
+    pub capture_mode: CaptureMode,
+    // OutputMode::File: 输出目录（为空时使用 文稿/MathImage）和文件名模板，模板支持 {date} {time} {profile}
+    #[serde(default)]
+    pub output_dir: PathBuf,
+    #[serde(default = "default_filename_template")]
+    pub filename_template: String,
+    // 推理模型流式返回的 reasoning_content（思考过程）：true 以 <think> 块放在结果前面，false 丢弃只保留答案
+    #[serde(default)]
+    pub include_reasoning: bool,
+    // 固定的system消息，放在每次截图的user消息之前（如统一LaTeX输出格式）
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    // 任一边超过tile_threshold_px的截图（如整页教材）切成重叠的分块逐个分析，再按阅读顺序拼接结果
+    #[serde(default)]
+    pub tile_large_images: bool,
+    // 分块阈值和单个分块的最大边长（像素）
+    #[serde(default = "default_tile_threshold_px")]
+    pub tile_threshold_px: u32,
+    // 把每次分析的请求payload和原始响应写入 <配置目录>/requests/<时间>.log，API key会被脱敏
+    #[serde(default)]
+    pub request_log: bool,
+    // 请求失败（重试和备用endpoint都用尽）后改用该Profile的配置重新分析一次
+    #[serde(default)]
+    pub fallback_profile_id: Option<String>,
+    // 离线模式：不调用API，用本地tesseract识别文字（不支持LaTeX，需要以tesseract feature编译）
+    #[serde(default)]
+    pub local_ocr: bool,
+    // tesseract语言，如 eng、chi_sim、eng+chi_sim
+    #[serde(default = "default_local_ocr_language")]
+    pub local_ocr_language: String,
+    // 有序的输出后处理步骤，按顺序应用；为空时由 strip_code_fences/extract_latex_only/latex_delimiters 推导
+    #[serde(default)]
+    pub post_processing: Vec<PostStep>,
+    // 使用统计：通过全局热键触发分析的次数和最近一次使用时间，用于清理不再使用的Profile
+    #[serde(default)]
+    pub use_count: u64,
+    #[serde(default)]
+    pub last_used: Option<std::time::SystemTime>,
+    // few-shot示例：(用户输入, 期望输出) 对，作为不带图片的历史对话放在真正的请求之前
+    #[serde(default)]
+    pub few_shot: Vec<(String, String)>,
+    // 服务端不支持多轮对话时，把few-shot示例拼接到system prompt中而不是作为单独的消息
+    #[serde(default)]
+    pub few_shot_inline: bool,
+    // OpenAI image_url的detail参数：小而清晰的截图用Low更省token，密集的整页用High更准确
+    #[serde(default)]
+    pub image_detail: ImageDetail,
+    // 按模型替换基础prompt：(模型id子串, prompt)，不区分大小写，按顺序取第一个匹配；都不匹配时使用prompt_mode中的prompt
+    #[serde(default)]
+    pub model_prompt_overrides: Vec<(String, String)>,
+}
+
+fn default_filename_template() -> String {
+    "mathimage_{date}_{time}.md".to_string()
+}
+
+fn default_local_ocr_language() -> String {
+    "eng".to_string()
+}
+
+fn default_tile_threshold_px() -> u32 {
+    2048
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum CaptureMode {
+    #[default]
+    Region,
+    Window,
+    // 应用自己的透明覆盖窗口框选区域，失败时退回到系统截图
+    Overlay,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum DelimiterStyle {
+    #[default]
+    Keep,
+    // $...$ 和 $$...$$
+    Dollar,
+    // \(...\) 和 \[...\]
+    Paren,
+    // $...$，行间公式的 $$ 单独成行（Obsidian/Typora等KaTeX渲染器要求）
+    Katex,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ImageDetail {
+    // 不发送detail，由服务商决定（OpenAI默认为auto）
+    #[default]
+    Auto,
+    Low,
+    High,
+}
+
+impl ImageDetail {
+    fn as_param(self) -> Option<&'static str> {
+        match self {
+            ImageDetail::Auto => None,
+            ImageDetail::Low => Some("low"),
+            ImageDetail::High => Some("high"),
+        }
+    }
+}
+
+// 输出后处理的单个步骤，Profile的post_processing按顺序应用
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PostStep {
+    // 去掉包裹整个结果的代码块
+    StripCodeFences,
+    // 只保留公式内容；没有公式时原样保留
+    ExtractLatex,
+    // 把公式定界符改写为指定风格
+    LatexDelimiters(DelimiterStyle),
+    // 去掉首尾空白
+    Trim,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PreprocessOptions {
+    pub grayscale: bool,
+    // 对比度调整，0.0表示不变，正值增强，负值减弱
+    pub contrast: f32,
+}
+
+impl PreprocessOptions {
+    fn is_noop(&self) -> bool {
+        !self.grayscale && self.contrast == 0.0
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub global_hotkey: String,
     pub switch_profile_hotkey: String,
+    // 切换到上一个Profile的热键，空字符串表示不注册
+    #[serde(default)]
+    pub switch_previous_profile_hotkey: String,
+    // 用下一个Profile重新分析最近一次截图的热键，空字符串表示不注册
+    #[serde(default)]
+    pub rerun_last_hotkey: String,
+    // 临时停用所有全局热键（保留已配置的组合键），在托盘中切换
+    #[serde(default = "default_hotkeys_enabled")]
+    pub hotkeys_enabled: bool,
+    // 缺失时为空，加载后由ensure_profiles补上默认Profile
+    #[serde(default)]
     pub profiles: Vec<Profile>,
     pub active_profile_id: Option<String>,
     pub sound_enabled: bool,
+    // 调试模式：返回原始错误信息而不是脱敏后的信息
+    #[serde(default)]
+    pub debug_mode: bool,
+    // OutputMode::Type: 等待目标窗口重新获得焦点的时间，以及超过多少字符改用剪贴板
+    #[serde(default = "default_typing_delay_ms")]
+    pub typing_delay_ms: u64,
+    #[serde(default = "default_typing_max_chars")]
+    pub typing_max_chars: usize,
+    // 设置窗口的位置和大小，重新打开时恢复
+    #[serde(default)]
+    pub window_state: Option<WindowState>,
+    // OutputMode::ClipboardAppend: 已有内容与新结果之间的分隔符
+    #[serde(default = "default_clipboard_append_separator")]
+    pub clipboard_append_separator: String,
+    // 全局热键防抖：距上次触发不足该时间的触发被忽略
+    #[serde(default = "default_hotkey_debounce_ms")]
+    pub hotkey_debounce_ms: u64,
+    // 自定义提示音文件，为空或文件不存在时使用系统默认音效
+    #[serde(default)]
+    pub success_sound_path: String,
+    #[serde(default)]
+    pub error_sound_path: String,
+    // 超过本地限流时：true 等待令牌后继续发送，false 直接返回错误
+    #[serde(default = "default_rate_limit_queue")]
+    pub rate_limit_queue: bool,
+    // 交互式截图的最长等待时间，超时后结束screencapture进程
+    #[serde(default = "default_capture_timeout_secs")]
+    pub capture_timeout_secs: u64,
+    // 离线模拟模式：不发送网络请求，返回固定的markdown结果（也可用环境变量 MATHIMAGE_MOCK=1 开启）
+    #[serde(default)]
+    pub mock_mode: bool,
+    // 截图存档目录（不设置则不保存），以及最多保留的文件数（超出时删除最旧的）
+    #[serde(default)]
+    pub save_captures_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub save_captures_max_files: Option<usize>,
+    // 日志级别，默认info；调试时设为debug可看到菜单状态等详细诊断信息
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    // 切换Profile后自动在后台重新加载该服务商的模型列表
+    #[serde(default)]
+    pub auto_refresh_models_on_switch: bool,
+    // 发送前图片data URL的最大字节数，0表示不限制
+    #[serde(default = "default_max_payload_bytes")]
+    pub max_payload_bytes: usize,
+    // 超过max_payload_bytes时：true 缩小并重新压缩后发送，false 返回错误提示选择更小的区域
+    #[serde(default = "default_shrink_oversized_images")]
+    pub shrink_oversized_images: bool,
+    // OutputMode::Clipboard: 流式接收时定期把已收到的内容写入剪贴板，结束后写入完整结果
+    #[serde(default)]
+    pub stream_to_clipboard: bool,
+    // 分析成功后显示系统通知（结果预览和所用模型），适合输出到webhook等不会立即粘贴的场景
+    #[serde(default)]
+    pub notify_on_success: bool,
+    // 截图后先显示预览窗口（Send / Recapture / Cancel），确认后才发送请求
+    #[serde(default)]
+    pub preview_before_send: bool,
+    // 分析结果缓存的最大条数（按图片+prompt+模型），重复截图同一区域时直接返回缓存结果；0表示不缓存
+    #[serde(default)]
+    pub analysis_cache_size: usize,
+    // 缓存结果的有效期（秒）
+    #[serde(default = "default_analysis_cache_ttl_secs")]
+    pub analysis_cache_ttl_secs: u64,
+    // true 按编码后的原始字节计算缓存key（更快），false 按解码后的像素计算（重新编码不影响命中）
+    #[serde(default)]
+    pub analysis_cache_exact_bytes: bool,
+    // 结果以这些文本开头（不区分大小写）时视为模型拒绝回答，按错误处理而不是覆盖剪贴板
+    #[serde(default = "default_refusal_patterns")]
+    pub refusal_patterns: Vec<String>,
+    // HTTP请求成功但模型返回空内容时，自动重新发送同一请求（与网络失败的重试分开计数）
+    #[serde(default)]
+    pub retry_on_empty: bool,
+    // retry_on_empty时最多重新发送的次数
+    #[serde(default = "default_empty_retry_max")]
+    pub empty_retry_max: u32,
+    // 启动时开启本地HTTP接口（POST /capture、POST /switch-profile），只监听127.0.0.1，修改后重启生效
+    #[serde(default)]
+    pub remote_server_enabled: bool,
+    // 本地HTTP接口的端口
+    #[serde(default = "default_remote_server_port")]
+    pub remote_server_port: u16,
+    // 本地HTTP接口的访问token（Authorization: Bearer <token>），为空时开启接口会自动生成
+    #[serde(default)]
+    pub remote_server_token: String,
+    // 活跃Profile没有选择模型时，从已加载的模型列表中自动选择一个（优先看起来支持图片的模型）并保存
+    // 默认关闭，保持提示用户先选择模型的行为
+    #[serde(default)]
+    pub auto_select_first_model: bool,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        // 创建默认Profile
-        let default_profile = Profile {
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowState {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+fn default_typing_delay_ms() -> u64 {
+    300
+}
+
+fn default_typing_max_chars() -> usize {
+    2000
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_max_payload_bytes() -> usize {
+    5 * 1024 * 1024
+}
+
+fn default_shrink_oversized_images() -> bool {
+    true
+}
+
+fn default_analysis_cache_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_remote_server_port() -> u16 {
+    47823
+}
+
+fn default_empty_retry_max() -> u32 {
+    2
+}
+
+fn default_refusal_patterns() -> Vec<String> {
+    [
+        "I'm sorry, but I can't",
+        "I'm sorry, I can't",
+        "I cannot assist with",
+        "I can't assist with",
+        "I can't help with",
+        "I'm unable to help with",
+        "抱歉，我无法",
+    ].iter().map(|p| p.to_string()).collect()
+}
+
+fn default_capture_timeout_secs() -> u64 {
+    60
+}
+
+fn default_rate_limit_queue() -> bool {
+    true
+}
+
+fn default_hotkeys_enabled() -> bool {
+    true
+}
+
+fn default_hotkey_debounce_ms() -> u64 {
+    500
+}
+
+fn default_clipboard_append_separator() -> String {
+    "\n\n".to_string()
+}
+
+impl Profile {
+    // 创建带默认设置的新Profile
+    fn new(name: String) -> Self {
+        Self {
             id: uuid::Uuid::new_v4().to_string(),
-            name: "默认配置".to_string(),
+            name,
             api_config: ApiConfig {
                 base_url: "http://210.126.8.197:11434/v1".to_string(),
                 api_key: "".to_string(),
                 model: "".to_string(),
+                fallback_base_urls: Vec::new(),
+                models_url: None,
+                danger_accept_invalid_certs: false,
+                bypass_proxy: false,
             },
             prompt_mode: PromptMode::Predefined(
                 "识别公式和文字，返回使用pandoc语法的markdown排版内容。公式请用katex语法包裹，文字内容不要丢失。只返回内容不需要其他解释。".to_string()
             ),
             output_mode: OutputMode::Clipboard,
-        };
+            prompt_presets: Vec::new(),
+            active_preset: None,
+            preprocess: PreprocessOptions::default(),
+            capture_padding: 0,
+            model_filter: String::new(),
+            latex_delimiters: DelimiterStyle::Keep,
+            strip_code_fences: false,
+            extract_latex_only: false,
+            requests_per_minute: 0,
+            model_allowlist: Vec::new(),
+            model_denylist: Vec::new(),
+            model_owners: Vec::new(),
+            recent_models: Vec::new(),
+            capture_mode: CaptureMode::Region,
+            output_dir: PathBuf::new(),
+            filename_template: default_filename_template(),
+            include_reasoning: false,
+            system_prompt: None,
+            tile_large_images: false,
+            tile_threshold_px: default_tile_threshold_px(),
+            request_log: false,
+            fallback_profile_id: None,
+            local_ocr: false,
+            local_ocr_language: default_local_ocr_language(),
+            post_processing: Vec::new(),
+            use_count: 0,
+            last_used: None,
+            few_shot: Vec::new(),
+            few_shot_inline: false,
+            image_detail: ImageDetail::Auto,
+            model_prompt_overrides: Vec::new(),
+        }
+    }
+
+    // 当前生效的prompt模式：选中的预设优先于prompt_mode；
+    // 没有选中预设时，匹配当前模型的model_prompt_overrides替换基础prompt的文本
+    fn effective_prompt_mode(&self) -> PromptMode {
+        if let Some((_, text)) = self.active_preset.and_then(|i| self.prompt_presets.get(i)) {
+            return PromptMode::Predefined(text.clone());
+        }
+        match (&self.prompt_mode, self.model_prompt_override()) {
+            (PromptMode::Predefined(_), Some(prompt)) => PromptMode::Predefined(prompt.to_string()),
+            (PromptMode::Template(_), Some(prompt)) => PromptMode::Template(prompt.to_string()),
+            (prompt_mode, _) => prompt_mode.clone(),
+        }
+    }
+
+    // 第一个id子串匹配当前模型的prompt覆盖
+    fn model_prompt_override(&self) -> Option<&str> {
+        let model = self.api_config.model.to_lowercase();
+        if model.is_empty() {
+            return None;
+        }
+        self.model_prompt_overrides.iter()
+            .find(|(pattern, _)| !pattern.trim().is_empty() && model.contains(&pattern.trim().to_lowercase()))
+            .map(|(_, prompt)| prompt.as_str())
+    }
+
+    fn active_preset_name(&self) -> String {
+        self.active_preset
+            .and_then(|i| self.prompt_presets.get(i))
+            .map(|(name, _)| name.clone())
+            .unwrap_or_else(|| "Default".to_string())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // 创建默认Profile
+        let default_profile = Profile::new("默认配置".to_string());
 
         Self {
             global_hotkey: "cmd+shift+m".to_string(),
             switch_profile_hotkey: "cmd+shift+p".to_string(),
+            switch_previous_profile_hotkey: String::new(),
+            rerun_last_hotkey: String::new(),
+            hotkeys_enabled: true,
             profiles: vec![default_profile.clone()],
             active_profile_id: Some(default_profile.id),
             sound_enabled: true,
+            debug_mode: false,
+            typing_delay_ms: default_typing_delay_ms(),
+            typing_max_chars: default_typing_max_chars(),
+            window_state: None,
+            clipboard_append_separator: default_clipboard_append_separator(),
+            hotkey_debounce_ms: default_hotkey_debounce_ms(),
+            success_sound_path: String::new(),
+            error_sound_path: String::new(),
+            rate_limit_queue: default_rate_limit_queue(),
+            capture_timeout_secs: default_capture_timeout_secs(),
+            mock_mode: false,
+            save_captures_dir: None,
+            save_captures_max_files: None,
+            log_level: default_log_level(),
+            auto_refresh_models_on_switch: false,
+            max_payload_bytes: default_max_payload_bytes(),
+            shrink_oversized_images: default_shrink_oversized_images(),
+            stream_to_clipboard: false,
+            notify_on_success: false,
+            preview_before_send: false,
+            analysis_cache_size: 0,
+            analysis_cache_ttl_secs: default_analysis_cache_ttl_secs(),
+            analysis_cache_exact_bytes: false,
+            refusal_patterns: default_refusal_patterns(),
+            retry_on_empty: false,
+            empty_retry_max: default_empty_retry_max(),
+            remote_server_enabled: false,
+            remote_server_port: default_remote_server_port(),
+            remote_server_token: String::new(),
+            auto_select_first_model: false,
+        }
+    }
+}
+
+impl Config {
+    // 修复手动编辑或损坏导致的Profile缺失：没有Profile时加入默认Profile并设为活跃，
+    // 活跃Profile id无效时改为第一个Profile。返回是否做了修改
+    fn ensure_profiles(&mut self) -> bool {
+        let mut repaired = false;
+        if self.profiles.is_empty() {
+            let default_profile = Profile::new("默认配置".to_string());
+            warn!("⚠️ Config has no profiles, adding default profile '{}'", default_profile.name);
+            self.active_profile_id = Some(default_profile.id.clone());
+            self.profiles.push(default_profile);
+            repaired = true;
+        }
+        let active_valid = self.active_profile_id.as_ref()
+            .map_or(false, |id| self.profiles.iter().any(|p| &p.id == id));
+        if !active_valid {
+            let first = &self.profiles[0];
+            warn!("⚠️ Active profile is missing or invalid, switching to '{}'", first.name);
+            self.active_profile_id = Some(first.id.clone());
+            repaired = true;
         }
+        repaired
     }
 }
 
@@ -79,12 +696,59 @@ impl Default for Config {
 pub struct ModelInfo {
     pub id: String,
     pub object: String,
+    // 模型提供方，网关聚合多个服务商时用于区分
+    #[serde(default)]
+    pub owned_by: Option<String>,
+    // 以下字段只有部分服务商会返回，没有时为None
+    // 上下文窗口大小（token数）
+    #[serde(default)]
+    pub context_length: Option<u64>,
+    // 服务商声明的能力，如 vision、tools
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+    // 是否支持图片输入，由capabilities或输入模态推断；无法判断时为None
+    #[serde(default)]
+    pub supports_vision: Option<bool>,
+}
+
+// owners为空时不过滤；设置了owners时没有owned_by的模型也会被过滤掉
+fn owner_allowed(owned_by: Option<&str>, owners: &[String]) -> bool {
+    owners.is_empty()
+        || owned_by.map_or(false, |owner| owners.iter().any(|o| o.eq_ignore_ascii_case(owner.trim())))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigUpdates {
     pub active_profile_id: Option<String>,
     pub sound_enabled: Option<bool>,
+    pub debug_mode: Option<bool>,
+    pub clipboard_append_separator: Option<String>,
+    pub hotkey_debounce_ms: Option<u64>,
+    pub success_sound_path: Option<String>,
+    pub error_sound_path: Option<String>,
+    pub rate_limit_queue: Option<bool>,
+    pub capture_timeout_secs: Option<u64>,
+    pub mock_mode: Option<bool>,
+    // Some(None) 表示关闭截图存档
+    pub save_captures_dir: Option<Option<PathBuf>>,
+    pub save_captures_max_files: Option<Option<usize>>,
+    pub log_level: Option<String>,
+    pub auto_refresh_models_on_switch: Option<bool>,
+    pub max_payload_bytes: Option<usize>,
+    pub shrink_oversized_images: Option<bool>,
+    pub stream_to_clipboard: Option<bool>,
+    pub notify_on_success: Option<bool>,
+    pub preview_before_send: Option<bool>,
+    pub analysis_cache_size: Option<usize>,
+    pub analysis_cache_ttl_secs: Option<u64>,
+    pub analysis_cache_exact_bytes: Option<bool>,
+    pub refusal_patterns: Option<Vec<String>>,
+    pub retry_on_empty: Option<bool>,
+    pub empty_retry_max: Option<u32>,
+    pub remote_server_enabled: Option<bool>,
+    pub remote_server_port: Option<u16>,
+    pub remote_server_token: Option<String>,
+    pub auto_select_first_model: Option<bool>,
     pub global_hotkey: Option<String>,
     pub switch_profile_hotkey: Option<String>,
 }
@@ -95,8 +759,218 @@ pub struct ProfileConfigUpdate {
     pub base_url: Option<String>,
     pub api_key: Option<String>,
     pub model: Option<String>,
+    pub fallback_base_urls: Option<Vec<String>>,
+    // Some(None) 表示恢复为 {base_url}/models
+    pub models_url: Option<Option<String>>,
+    pub danger_accept_invalid_certs: Option<bool>,
+    pub bypass_proxy: Option<bool>,
     pub prompt_mode: Option<PromptMode>,
     pub output_mode: Option<OutputMode>,
+    pub prompt_presets: Option<Vec<(String, String)>>,
+    pub active_preset: Option<Option<usize>>,
+    pub preprocess: Option<PreprocessOptions>,
+    pub capture_padding: Option<u32>,
+    pub latex_delimiters: Option<DelimiterStyle>,
+    pub strip_code_fences: Option<bool>,
+    pub extract_latex_only: Option<bool>,
+    pub requests_per_minute: Option<u32>,
+    pub model_allowlist: Option<Vec<String>>,
+    pub model_denylist: Option<Vec<String>>,
+    pub model_owners: Option<Vec<String>>,
+    pub capture_mode: Option<CaptureMode>,
+    pub output_dir: Option<PathBuf>,
+    pub filename_template: Option<String>,
+    pub include_reasoning: Option<bool>,
+    pub system_prompt: Option<Option<String>>,
+    pub tile_large_images: Option<bool>,
+    pub tile_threshold_px: Option<u32>,
+    pub request_log: Option<bool>,
+    pub fallback_profile_id: Option<Option<String>>,
+    pub local_ocr: Option<bool>,
+    pub local_ocr_language: Option<String>,
+    pub post_processing: Option<Vec<PostStep>>,
+    pub few_shot: Option<Vec<(String, String)>>,
+    pub few_shot_inline: Option<bool>,
+    pub image_detail: Option<ImageDetail>,
+    pub model_prompt_overrides: Option<Vec<(String, String)>>,
+}
+
+// 熔断器：连续失败达到阈值后跳过该endpoint，冷却后半开试探
+const CIRCUIT_FAILURE_THRESHOLD: u32 = 3;
+const CIRCUIT_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreaker {
+    consecutive_failures: u32,
+    opened_at: Option<std::time::Instant>,
+}
+
+// 分析结果的LRU缓存，最近使用的条目在末尾
+#[derive(Debug, Default)]
+pub struct AnalysisCache {
+    entries: std::collections::VecDeque<(u64, String, std::time::Instant)>,
+}
+
+impl AnalysisCache {
+    // 命中且未过期时把条目移到末尾并返回结果，过期条目直接丢弃
+    fn get(&mut self, key: u64, ttl: std::time::Duration) -> Option<String> {
+        let index = self.entries.iter().position(|(k, _, _)| *k == key)?;
+        let entry = self.entries.remove(index)?;
+        if entry.2.elapsed() > ttl {
+            return None;
+        }
+        let result = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(result)
+    }
+
+    fn insert(&mut self, key: u64, result: String, capacity: usize) {
+        self.entries.retain(|(k, _, _)| *k != key);
+        self.entries.push_back((key, result, std::time::Instant::now()));
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    fn clear(&mut self) -> usize {
+        let count = self.entries.len();
+        self.entries.clear();
+        count
+    }
+}
+
+// 缓存key：图片内容 + system prompt + prompt + 模型 + endpoint
+// exact_bytes为false时对解码后的像素求hash，同一画面重新编码（如PNG压缩参数不同）也能命中
+fn analysis_cache_key(images: &[String], system_prompt: &str, prompt: &str, model: &str, base_url: &str, exact_bytes: bool) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    images.len().hash(&mut hasher);
+    for image_data in images {
+        let base64_part = image_data.split_once(',').map(|(_, data)| data).unwrap_or(image_data);
+        let decoded = if exact_bytes {
+            None
+        } else {
+            general_purpose::STANDARD.decode(base64_part).ok()
+                .and_then(|bytes| image::load_from_memory(&bytes).ok())
+                .map(|img| img.to_rgba8())
+        };
+        match decoded {
+            Some(pixels) => {
+                pixels.dimensions().hash(&mut hasher);
+                pixels.as_raw().hash(&mut hasher);
+            }
+            None => base64_part.hash(&mut hasher),
+        }
+    }
+    system_prompt.hash(&mut hasher);
+    prompt.hash(&mut hasher);
+    model.hash(&mut hasher);
+    base_url.hash(&mut hasher);
+    hasher.finish()
+}
+
+// 令牌桶：容量为每分钟请求数，按 rpm/60 每秒匀速补充
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: std::time::Instant,
+}
+
+impl TokenBucket {
+    fn new(requests_per_minute: u32) -> Self {
+        Self {
+            tokens: requests_per_minute as f64,
+            last_refill: std::time::Instant::now(),
+        }
+    }
+
+    // 取一个令牌；不足时返回需要等待的时间
+    fn try_acquire(&mut self, requests_per_minute: u32) -> Result<(), std::time::Duration> {
+        let capacity = requests_per_minute as f64;
+        let refill_per_sec = capacity / 60.0;
+        let now = std::time::Instant::now();
+
+        self.tokens = (self.tokens + now.duration_since(self.last_refill).as_secs_f64() * refill_per_sec).min(capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(std::time::Duration::from_secs_f64((1.0 - self.tokens) / refill_per_sec))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointHealth {
+    pub endpoint: String,
+    pub state: String,
+    pub consecutive_failures: u32,
+    pub cooldown_remaining_secs: u64,
+}
+
+// get_status 返回的整体状态，供设置页的状态面板和托盘摘要使用
+#[derive(Debug, Clone, Serialize)]
+pub struct AppStatus {
+    pub active_profile: Option<String>,
+    pub model: Option<String>,
+    // base_url和API key都已配置
+    pub credentials_configured: bool,
+    pub hotkeys_enabled: bool,
+    // 全局截图热键当前确实已向系统注册
+    pub hotkeys_registered: bool,
+    pub global_hotkey: Option<String>,
+    // 活跃Profile的base_url下缓存的模型数量
+    pub model_cache_count: usize,
+    pub last_error: Option<String>,
+    // 距离上一次分析完成的秒数，还没有分析过时为None
+    pub last_analysis_secs_ago: Option<u64>,
+    pub queued_captures: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileStats {
+    pub id: String,
+    pub name: String,
+    pub use_count: u64,
+    // Unix时间戳（秒），从未使用过时为None
+    pub last_used_secs: Option<u64>,
+}
+
+// 配置文件写入锁，避免并发保存或退出时截断config.json
+static CONFIG_SAVE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// 已写入钥匙串的API key（profile id -> key），保存配置时跳过没有变化的key，避免重复写入和系统授权弹窗
+static KEYCHAIN_SYNCED_KEYS: std::sync::Mutex<std::collections::BTreeMap<String, String>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+// 数据目录只解析一次，config.json和models.json始终使用同一个目录
+static CONFIG_DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
+// 覆盖数据目录的环境变量
+const CONFIG_DIR_ENV: &str = "MATHIMAGE_CONFIG_DIR";
+
+// 解析数据目录：环境变量 > 已有的旧目录~/.mathimage > 系统配置目录（XDG_CONFIG_HOME、Application Support等）
+fn resolve_config_dir(
+    env_override: Option<&str>,
+    platform_config_dir: Option<PathBuf>,
+    home_dir: Option<PathBuf>,
+) -> Option<PathBuf> {
+    if let Some(dir) = env_override.map(str::trim).filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+
+    // 旧版本的数据仍在~/.mathimage时继续使用它，避免丢失配置
+    let legacy_dir = home_dir.map(|home| home.join(".mathimage"));
+    if let Some(legacy) = &legacy_dir {
+        if legacy.join("config.json").exists() {
+            return legacy_dir;
+        }
+    }
+
+    platform_config_dir.map(|dir| dir.join("mathimage")).or(legacy_dir)
 }
 
 #[derive(Clone)]
@@ -104,8 +978,13 @@ pub struct AppState {
     config: Arc<Mutex<Config>>,
     current_global_hotkey: Arc<Mutex<Option<String>>>,
     current_switch_hotkey: Arc<Mutex<Option<String>>>,
+    current_previous_hotkey: Arc<Mutex<Option<String>>>,
+    current_rerun_hotkey: Arc<Mutex<Option<String>>>,
     http_client: reqwest::Client,
-    loaded_models: Arc<Mutex<Vec<String>>>,
+    // 跳过证书校验/不走代理的Profile使用的客户端，按 (danger_accept_invalid_certs, bypass_proxy) 懒创建
+    profile_http_clients: Arc<Mutex<std::collections::HashMap<(bool, bool), reqwest::Client>>>,
+    // 模型缓存，按base_url区分不同的服务商
+    loaded_models: Arc<Mutex<std::collections::HashMap<String, Vec<String>>>>,
     // Store references to CheckMenuItems for dynamic updates
     model_check_items: Arc<Mutex<std::collections::HashMap<String, tauri::menu::CheckMenuItem<tauri::Wry>>>>,
     // Store reference to the model submenu for title updates
@@ -114,62 +993,219 @@ pub struct AppState {
     profile_check_items: Arc<Mutex<std::collections::HashMap<String, tauri::menu::CheckMenuItem<tauri::Wry>>>>,
     // Store reference to the profile submenu for title updates
     profile_submenu: Arc<Mutex<Option<tauri::menu::Submenu<tauri::Wry>>>>,
+    // Store references to prompt preset CheckMenuItems (keyed by "default" or preset index) and their submenu
+    preset_check_items: Arc<Mutex<std::collections::HashMap<String, tauri::menu::CheckMenuItem<tauri::Wry>>>>,
+    preset_submenu: Arc<Mutex<Option<tauri::menu::Submenu<tauri::Wry>>>>,
     // Store references to hotkey and sound menu items to allow text updates without rebuilding tray
     global_hotkey_item: Arc<Mutex<Option<tauri::menu::MenuItem<tauri::Wry>>>>,
     switch_hotkey_item: Arc<Mutex<Option<tauri::menu::MenuItem<tauri::Wry>>>>,
     sound_item: Arc<Mutex<Option<tauri::menu::MenuItem<tauri::Wry>>>>,
+    hotkeys_item: Arc<Mutex<Option<tauri::menu::MenuItem<tauri::Wry>>>>,
+    // 托盘中显示最近一次分析结果的菜单项，以及对应的状态
+    last_status_item: Arc<Mutex<Option<tauri::menu::MenuItem<tauri::Wry>>>>,
+    last_status: Arc<Mutex<Option<LastStatus>>>,
+    // Per-endpoint circuit breakers, keyed by base_url
+    endpoint_health: Arc<Mutex<std::collections::HashMap<String, CircuitBreaker>>>,
+    // 最近一次截图的data URL，用于重新分析（仅保存在内存中）
+    last_capture: Arc<Mutex<Option<String>>>,
+    // 重新分析热键上一次使用的Profile，新截图时清空（下次从活跃Profile的下一个开始）
+    rerun_profile_id: Arc<Mutex<Option<String>>>,
+    // 本次分析流式写入剪贴板的最新部分结果，用于失败时判断是否恢复原内容
+    partial_clipboard_text: Arc<std::sync::Mutex<Option<String>>>,
+    // 进行中的分析任务，退出时取消（在同步的快捷键回调中使用，所以用std Mutex）
+    analysis_tasks: Arc<std::sync::Mutex<Vec<tauri::async_runtime::JoinHandle<()>>>>,
+    // 全局热键上次触发的时间，用于防抖
+    last_trigger: Arc<Mutex<Option<std::time::Instant>>>,
+    // 分析队列：tokio的Mutex按FIFO顺序唤醒等待者，分析中截取的新截图排队依次处理
+    analysis_queue: Arc<Mutex<()>>,
+    // 排队中和正在处理的截图数量
+    queued_captures: Arc<std::sync::atomic::AtomicUsize>,
+    // 按Profile id区分的本地限流令牌桶
+    rate_limiters: Arc<Mutex<std::collections::HashMap<String, TokenBucket>>>,
+    // 每次切换Profile递增，用于模型自动刷新的防抖
+    model_refresh_generation: Arc<std::sync::atomic::AtomicU64>,
+    // 等待覆盖窗口返回选区，None表示用户取消
+    region_picker: Arc<Mutex<Option<tokio::sync::oneshot::Sender<Option<SelectedRegion>>>>>,
+    // 等待预览窗口确认的截图和对应的回复通道
+    capture_preview: Arc<Mutex<Option<(String, tokio::sync::oneshot::Sender<PreviewDecision>)>>>,
+    analysis_cache: Arc<Mutex<AnalysisCache>>,
+}
+
+// setup中构建的托盘菜单项引用，通过AppState::store_menu_refs一次性保存
+struct TrayMenuRefs {
+    profile_submenu: tauri::menu::Submenu<tauri::Wry>,
+    profile_check_items: std::collections::HashMap<String, tauri::menu::CheckMenuItem<tauri::Wry>>,
+    model_submenu: tauri::menu::Submenu<tauri::Wry>,
+    model_check_items: std::collections::HashMap<String, tauri::menu::CheckMenuItem<tauri::Wry>>,
+    preset_submenu: tauri::menu::Submenu<tauri::Wry>,
+    preset_check_items: std::collections::HashMap<String, tauri::menu::CheckMenuItem<tauri::Wry>>,
+    global_hotkey_item: tauri::menu::MenuItem<tauri::Wry>,
+    switch_hotkey_item: tauri::menu::MenuItem<tauri::Wry>,
+    sound_item: tauri::menu::MenuItem<tauri::Wry>,
+    hotkeys_item: tauri::menu::MenuItem<tauri::Wry>,
+    last_status_item: tauri::menu::MenuItem<tauri::Wry>,
+}
+
+// 最近一次分析的结果，用于托盘中的 "Last: ..." 菜单项
+#[derive(Debug, Clone)]
+struct LastStatus {
+    // None表示成功，Some为简短的错误描述
+    error: Option<String>,
+    at: std::time::Instant,
+}
+
+// 所有HTTP客户端共用的连接池和超时设置
+fn http_client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(120))
+        .tcp_keepalive(std::time::Duration::from_secs(60))
+        .pool_idle_timeout(std::time::Duration::from_secs(90))
+        .pool_max_idle_per_host(10)
+        .http2_keep_alive_interval(std::time::Duration::from_secs(30))
+        .http2_keep_alive_timeout(std::time::Duration::from_secs(10))
+        .http2_keep_alive_while_idle(true)
 }
 
 impl AppState {
     fn new() -> Self {
-        let http_client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(120))
-            .tcp_keepalive(std::time::Duration::from_secs(60))
-            .pool_idle_timeout(std::time::Duration::from_secs(90))
-            .pool_max_idle_per_host(10)
-            .http2_keep_alive_interval(std::time::Duration::from_secs(30))
-            .http2_keep_alive_timeout(std::time::Duration::from_secs(10))
-            .http2_keep_alive_while_idle(true)
+        // 全局共享的HTTP客户端（reqwest::Client内部是Arc，clone只是共享同一个连接池）
+        let http_client = http_client_builder()
             .build()
             .expect("Failed to create HTTP client");
 
         // Load config from file or use default
         let config = Self::load_config().unwrap_or_else(|e| {
-            println!("Failed to load config: {}, using default", e);
+            error!("Failed to load config: {}, using default", e);
             Config::default()
         });
 
+        // 旧版扁平的models.json迁移到活跃Profile的base_url下
+        let active_base_url = config.profiles.iter()
+            .find(|p| Some(&p.id) == config.active_profile_id.as_ref())
+            .or_else(|| config.profiles.first())
+            .map(|p| p.api_config.base_url.clone())
+            .unwrap_or_default();
+
         Self {
             config: Arc::new(Mutex::new(config)),
             current_global_hotkey: Arc::new(Mutex::new(None)),
             current_switch_hotkey: Arc::new(Mutex::new(None)),
+            current_previous_hotkey: Arc::new(Mutex::new(None)),
+            current_rerun_hotkey: Arc::new(Mutex::new(None)),
             http_client,
+            profile_http_clients: Arc::new(Mutex::new(std::collections::HashMap::new())),
             loaded_models: Arc::new(Mutex::new({
                 // Try to load cached models on startup
-                Self::load_cached_models().unwrap_or_else(|e| {
-                    println!("Failed to load cached models: {}, starting with empty list", e);
-                    Vec::new()
+                Self::load_cached_models(&active_base_url).unwrap_or_else(|e| {
+                    error!("Failed to load cached models: {}, starting with empty list", e);
+                    std::collections::HashMap::new()
                 })
             })),
             model_check_items: Arc::new(Mutex::new(std::collections::HashMap::new())),
             model_submenu: Arc::new(Mutex::new(None)),
             profile_check_items: Arc::new(Mutex::new(std::collections::HashMap::new())),
             profile_submenu: Arc::new(Mutex::new(None)),
+            preset_check_items: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            preset_submenu: Arc::new(Mutex::new(None)),
             global_hotkey_item: Arc::new(Mutex::new(None)),
             switch_hotkey_item: Arc::new(Mutex::new(None)),
             sound_item: Arc::new(Mutex::new(None)),
+            hotkeys_item: Arc::new(Mutex::new(None)),
+            last_status_item: Arc::new(Mutex::new(None)),
+            last_status: Arc::new(Mutex::new(None)),
+            endpoint_health: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            last_capture: Arc::new(Mutex::new(None)),
+            rerun_profile_id: Arc::new(Mutex::new(None)),
+            partial_clipboard_text: Arc::new(std::sync::Mutex::new(None)),
+            analysis_tasks: Arc::new(std::sync::Mutex::new(Vec::new())),
+            last_trigger: Arc::new(Mutex::new(None)),
+            analysis_queue: Arc::new(Mutex::new(())),
+            queued_captures: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            rate_limiters: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            model_refresh_generation: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            region_picker: Arc::new(Mutex::new(None)),
+            capture_preview: Arc::new(Mutex::new(None)),
+            analysis_cache: Arc::new(Mutex::new(AnalysisCache::default())),
         }
     }
 
-    fn get_config_path() -> Result<PathBuf, String> {
-        let home_dir = dirs_next::home_dir().ok_or("Failed to get home directory")?;
-        let config_dir = home_dir.join(".mathimage");
-        
+    // Profile对应的HTTP客户端：默认共享全局客户端，跳过证书校验或不走代理时使用单独的客户端
+    async fn http_client_for(&self, api_config: &ApiConfig) -> Result<reqwest::Client, String> {
+        let key = (api_config.danger_accept_invalid_certs, api_config.bypass_proxy);
+        if key == (false, false) {
+            return Ok(self.http_client.clone());
+        }
+        if api_config.danger_accept_invalid_certs {
+            warn!("⚠️ TLS certificate verification is DISABLED for requests to {}", api_config.base_url);
+        }
+
+        let mut clients = self.profile_http_clients.lock().await;
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let mut builder = http_client_builder().danger_accept_invalid_certs(api_config.danger_accept_invalid_certs);
+        if api_config.bypass_proxy {
+            builder = builder.no_proxy();
+        }
+        let client = builder.build().map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    // 先获取所有锁再统一赋值，避免部分引用已更新、部分未更新
+    fn store_menu_refs(&self, refs: TrayMenuRefs) -> Result<(), String> {
+        let lock_err = |name: &str| format!("Failed to lock {} for menu reference storage", name);
+
+        let mut profile_submenu = self.profile_submenu.try_lock().map_err(|_| lock_err("profile_submenu"))?;
+        let mut profile_check_items = self.profile_check_items.try_lock().map_err(|_| lock_err("profile_check_items"))?;
+        let mut model_submenu = self.model_submenu.try_lock().map_err(|_| lock_err("model_submenu"))?;
+        let mut model_check_items = self.model_check_items.try_lock().map_err(|_| lock_err("model_check_items"))?;
+        let mut preset_submenu = self.preset_submenu.try_lock().map_err(|_| lock_err("preset_submenu"))?;
+        let mut preset_check_items = self.preset_check_items.try_lock().map_err(|_| lock_err("preset_check_items"))?;
+        let mut global_hotkey_item = self.global_hotkey_item.try_lock().map_err(|_| lock_err("global_hotkey_item"))?;
+        let mut switch_hotkey_item = self.switch_hotkey_item.try_lock().map_err(|_| lock_err("switch_hotkey_item"))?;
+        let mut sound_item = self.sound_item.try_lock().map_err(|_| lock_err("sound_item"))?;
+        let mut hotkeys_item = self.hotkeys_item.try_lock().map_err(|_| lock_err("hotkeys_item"))?;
+        let mut last_status_item = self.last_status_item.try_lock().map_err(|_| lock_err("last_status_item"))?;
+
+        *profile_submenu = Some(refs.profile_submenu);
+        *profile_check_items = refs.profile_check_items;
+        *model_submenu = Some(refs.model_submenu);
+        *model_check_items = refs.model_check_items;
+        *preset_submenu = Some(refs.preset_submenu);
+        *preset_check_items = refs.preset_check_items;
+        *global_hotkey_item = Some(refs.global_hotkey_item);
+        *switch_hotkey_item = Some(refs.switch_hotkey_item);
+        *sound_item = Some(refs.sound_item);
+        *hotkeys_item = Some(refs.hotkeys_item);
+        *last_status_item = Some(refs.last_status_item);
+
+        debug!("✅ Stored tray menu references ({} profiles, {} models, {} presets)",
+            profile_check_items.len(), model_check_items.len(), preset_check_items.len());
+        Ok(())
+    }
+
+    fn get_config_dir() -> Result<PathBuf, String> {
+        let config_dir = match CONFIG_DIR.get() {
+            Some(dir) => dir.clone(),
+            None => {
+                let env_override = std::env::var(CONFIG_DIR_ENV).ok();
+                let dir = resolve_config_dir(env_override.as_deref(), dirs_next::config_dir(), dirs_next::home_dir())
+                    .ok_or("Failed to determine config directory")?;
+                info!("Using config directory: {:?}", dir);
+                CONFIG_DIR.get_or_init(|| dir).clone()
+            }
+        };
+
         // Create config directory if it doesn't exist
         fs::create_dir_all(&config_dir)
             .map_err(|e| format!("Failed to create config directory: {}", e))?;
-        
-        Ok(config_dir.join("config.json"))
+
+        Ok(config_dir)
+    }
+
+    fn get_config_path() -> Result<PathBuf, String> {
+        Ok(Self::get_config_dir()?.join("config.json"))
     }
 
     fn load_config() -> Result<Config, String> {
@@ -182,15 +1218,106 @@ impl AppState {
         let config_data = fs::read_to_string(&config_path)
             .map_err(|e| format!("Failed to read config file: {}", e))?;
         
-        let config: Config = serde_json::from_str(&config_data)
-            .map_err(|e| format!("Failed to parse config file: {}", e))?;
+        let mut config: Config = match serde_json::from_str(&config_data) {
+            Ok(config) => config,
+            Err(e) => {
+                // 回退到默认配置前先备份损坏的文件，避免下次保存时被覆盖
+                let backup_path = config_path.with_file_name(format!(
+                    "config.json.bak-{}",
+                    chrono::Local::now().format("%Y%m%d_%H%M%S")
+                ));
+                match fs::copy(&config_path, &backup_path) {
+                    Ok(_) => warn!("⚠️ Backed up unparseable config to: {:?}", backup_path),
+                    Err(copy_err) => error!("Failed to back up unparseable config: {}", copy_err),
+                }
+                return Err(format!("Failed to parse config file: {}", e));
+            }
+        };
+
+        if config.ensure_profiles() {
+            info!("🔧 Repaired profiles in loaded config");
+        }
+
+        // 从系统钥匙串取回API key
+        Self::restore_api_keys_from_keychain(&mut config);
         
         Ok(config)
     }
 
-    fn save_loaded_models(models: &[String]) -> Result<(), String> {
-        let config_dir = Self::get_config_path()?.parent().unwrap().to_path_buf();
-        let models_file = config_dir.join("models.json");
+    // API keys are kept in the OS keychain (service "mathimage", account = profile id);
+    // config.json only stores a placeholder. If the keychain is unavailable the key
+    // stays in plaintext, as before.
+    fn keychain_entry(profile_id: &str) -> Result<keyring::Entry, keyring::Error> {
+        keyring::Entry::new(KEYRING_SERVICE, profile_id)
+    }
+
+    fn restore_api_keys_from_keychain(config: &mut Config) {
+        for profile in config.profiles.iter_mut() {
+            if profile.api_config.api_key != KEYRING_PLACEHOLDER {
+                continue;
+            }
+
+            match Self::keychain_entry(&profile.id).and_then(|entry| entry.get_password()) {
+                Ok(api_key) => {
+                    KEYCHAIN_SYNCED_KEYS.lock().unwrap_or_else(|e| e.into_inner())
+                        .insert(profile.id.clone(), api_key.clone());
+                    profile.api_config.api_key = api_key;
+                }
+                Err(e) => {
+                    // 保留占位符：清空的话下次保存会删除钥匙串中的key，一次临时读取失败就会永久丢失
+                    warn!("⚠️ Failed to read API key for profile '{}' from keychain, keeping it unresolved: {}", profile.name, e);
+                }
+            }
+        }
+    }
+
+    // 返回用于写入磁盘的配置副本，API key替换为占位符
+    fn store_api_keys_in_keychain(config: &Config) -> Config {
+        let mut disk_config = config.clone();
+
+        for profile in disk_config.profiles.iter_mut() {
+            let api_key = profile.api_config.api_key.clone();
+            if api_key == KEYRING_PLACEHOLDER {
+                continue;
+            }
+
+            let mut synced_keys = KEYCHAIN_SYNCED_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+            if !api_key.is_empty() && synced_keys.get(&profile.id) == Some(&api_key) {
+                profile.api_config.api_key = KEYRING_PLACEHOLDER.to_string();
+                continue;
+            }
+
+            let entry = match Self::keychain_entry(&profile.id) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("⚠️ Keychain unavailable, storing API key for profile '{}' in plaintext: {}", profile.name, e);
+                    continue;
+                }
+            };
+
+            if api_key.is_empty() {
+                // 用户主动清空了key（读取失败时保留的是占位符，不会走到这里）
+                let _ = entry.delete_password();
+                synced_keys.remove(&profile.id);
+                continue;
+            }
+
+            match entry.set_password(&api_key) {
+                Ok(()) => {
+                    synced_keys.insert(profile.id.clone(), api_key);
+                    profile.api_config.api_key = KEYRING_PLACEHOLDER.to_string();
+                }
+                Err(e) => {
+                    warn!("⚠️ Keychain unavailable, storing API key for profile '{}' in plaintext: {}", profile.name, e);
+                }
+            }
+        }
+
+        disk_config
+    }
+
+    fn save_loaded_models(models: &std::collections::HashMap<String, Vec<String>>) -> Result<(), String> {
+        let models_file = Self::get_config_dir()?.join("models.json");
         
         let json = serde_json::to_string_pretty(models)
             .map_err(|e| format!("Failed to serialize models: {}", e))?;
@@ -198,35 +1325,67 @@ impl AppState {
         std::fs::write(&models_file, json)
             .map_err(|e| format!("Failed to write models file: {}", e))?;
         
-        println!("Saved {} models to cache", models.len());
+        info!("Saved model cache for {} endpoints", models.len());
         Ok(())
     }
     
-    fn load_cached_models() -> Result<Vec<String>, String> {
-        let config_dir = Self::get_config_path()?.parent().unwrap().to_path_buf();
-        let models_file = config_dir.join("models.json");
+    fn load_cached_models(active_base_url: &str) -> Result<std::collections::HashMap<String, Vec<String>>, String> {
+        let models_file = Self::get_config_dir()?.join("models.json");
         
         if !models_file.exists() {
-            return Ok(Vec::new());
+            return Ok(std::collections::HashMap::new());
         }
         
         let content = std::fs::read_to_string(&models_file)
             .map_err(|e| format!("Failed to read models file: {}", e))?;
         
-        let models: Vec<String> = serde_json::from_str(&content)
+        if let Ok(models) = serde_json::from_str::<std::collections::HashMap<String, Vec<String>>>(&content) {
+            info!("Loaded model cache for {} endpoints", models.len());
+            return Ok(models);
+        }
+
+        // 旧格式：扁平的模型列表
+        let legacy_models: Vec<String> = serde_json::from_str(&content)
             .map_err(|e| format!("Failed to parse models file: {}", e))?;
-        
-        println!("Loaded {} models from cache", models.len());
+
+        info!("Migrating {} cached models to base_url '{}'", legacy_models.len(), active_base_url);
+        let mut models = std::collections::HashMap::new();
+        if !legacy_models.is_empty() && !active_base_url.is_empty() {
+            models.insert(active_base_url.to_string(), legacy_models);
+        }
+        if let Err(e) = Self::save_loaded_models(&models) {
+            error!("Failed to save migrated model cache: {}", e);
+        }
         Ok(models)
     }
 
+    // 清理已结束的任务，返回仍在运行的分析数量
+    fn running_analysis_count(&self) -> usize {
+        let mut tasks = self.analysis_tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|task| !task.inner().is_finished());
+        tasks.len()
+    }
+
+    // 活跃Profile的base_url对应的缓存模型
+    async fn get_models_for_active_profile(&self) -> Result<Vec<String>, String> {
+        let base_url = self.get_active_profile().await?.api_config.base_url;
+        let loaded_models = self.loaded_models.lock().await;
+        Ok(loaded_models.get(&base_url).cloned().unwrap_or_default())
+    }
+
     // 改进的配置保存方法 - 确保原子性操作
     async fn save_config_atomic(config: &Config) -> Result<(), String> {
+        // 串行化写入，退出时的flush会等待正在进行的保存完成
+        let _save_guard = CONFIG_SAVE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
         let config_path = Self::get_config_path()?;
         let temp_path = config_path.with_extension("tmp");
 
+        // API key写入钥匙串，磁盘上只保留占位符
+        let disk_config = Self::store_api_keys_in_keychain(config);
+
         // 先写入临时文件
-        let config_data = serde_json::to_string_pretty(config)
+        let config_data = serde_json::to_string_pretty(&disk_config)
             .map_err(|e| format!("Failed to serialize config: {}", e))?;
 
         fs::write(&temp_path, config_data)
@@ -236,7 +1395,7 @@ impl AppState {
         fs::rename(&temp_path, &config_path)
             .map_err(|e| format!("Failed to save config file: {}", e))?;
 
-        println!("Config saved atomically to: {:?}", config_path);
+        info!("Config saved atomically to: {:?}", config_path);
         Ok(())
     }
 
@@ -270,26 +1429,14 @@ impl AppState {
             }
             
             // 创建默认Profile
-            let new_profile = Profile {
-                id: uuid::Uuid::new_v4().to_string(),
-                name: name.clone(),
-                api_config: ApiConfig {
-                    base_url: "http://210.126.8.197:11434/v1".to_string(),
-                    api_key: "".to_string(),
-                    model: "".to_string(),
-                },
-                prompt_mode: PromptMode::Predefined(
-                    "识别公式和文字，返回使用pandoc语法的markdown排版内容。公式请用katex语法包裹，文字内容不要丢失。只返回内容不需要其他解释。".to_string()
-                ),
-                output_mode: OutputMode::Clipboard,
-            };
+            let new_profile = Profile::new(name.clone());
             
             let profile_id = new_profile.id.clone();
             result_profile_id = profile_id.clone();
             config.profiles.push(new_profile);
             config.active_profile_id = Some(profile_id.clone());
             
-            println!("   📝 Created and activated new profile: {} ({})", name, profile_id);
+            debug!("   📝 Created and activated new profile: {} ({})", name, profile_id);
             Ok(())
         }).await?;
         
@@ -302,6 +1449,15 @@ impl AppState {
             let active_id = config.active_profile_id.clone()
                 .ok_or("No active profile")?;
 
+            if let Some(Some(fallback_id)) = &updates.fallback_profile_id {
+                if fallback_id == &active_id {
+                    return Err("A profile cannot fall back to itself".to_string());
+                }
+                if !config.profiles.iter().any(|p| &p.id == fallback_id) {
+                    return Err(format!("Fallback profile '{}' not found", fallback_id));
+                }
+            }
+
             let profile = config.profiles.iter_mut()
                 .find(|p| p.id == active_id)
                 .ok_or("Active profile not found")?;
@@ -311,7 +1467,7 @@ impl AppState {
                 profile.name = name;
             }
             if let Some(base_url) = updates.base_url {
-                profile.api_config.base_url = base_url;
+                profile.api_config.base_url = normalize_base_url(&base_url)?;
             }
             if let Some(api_key) = updates.api_key {
                 profile.api_config.api_key = api_key;
@@ -319,14 +1475,121 @@ impl AppState {
             if let Some(model) = updates.model {
                 profile.api_config.model = model;
             }
+            if let Some(fallback_base_urls) = updates.fallback_base_urls {
+                profile.api_config.fallback_base_urls = fallback_base_urls.iter()
+                    .map(|url| normalize_base_url(url))
+                    .collect::<Result<Vec<String>, String>>()?;
+            }
+            if let Some(models_url) = updates.models_url {
+                profile.api_config.models_url = match models_url {
+                    Some(url) => Some(normalize_base_url(&url)?),
+                    None => None,
+                };
+            }
+            if let Some(danger_accept_invalid_certs) = updates.danger_accept_invalid_certs {
+                if danger_accept_invalid_certs && !profile.api_config.danger_accept_invalid_certs {
+                    warn!("⚠️ TLS certificate verification DISABLED for profile '{}' - only use this for trusted internal endpoints", profile.name);
+                }
+                profile.api_config.danger_accept_invalid_certs = danger_accept_invalid_certs;
+            }
+            if let Some(bypass_proxy) = updates.bypass_proxy {
+                profile.api_config.bypass_proxy = bypass_proxy;
+            }
             if let Some(prompt_mode) = updates.prompt_mode {
                 profile.prompt_mode = prompt_mode;
             }
             if let Some(output_mode) = updates.output_mode {
                 profile.output_mode = output_mode;
             }
+            if let Some(prompt_presets) = updates.prompt_presets {
+                profile.prompt_presets = prompt_presets;
+            }
+            if let Some(active_preset) = updates.active_preset {
+                profile.active_preset = active_preset;
+            }
+            if let Some(preprocess) = updates.preprocess {
+                profile.preprocess = preprocess;
+            }
+            if let Some(capture_padding) = updates.capture_padding {
+                profile.capture_padding = capture_padding;
+            }
+            if let Some(latex_delimiters) = updates.latex_delimiters {
+                profile.latex_delimiters = latex_delimiters;
+            }
+            if let Some(strip_code_fences) = updates.strip_code_fences {
+                profile.strip_code_fences = strip_code_fences;
+            }
+            if let Some(extract_latex_only) = updates.extract_latex_only {
+                profile.extract_latex_only = extract_latex_only;
+            }
+            if let Some(requests_per_minute) = updates.requests_per_minute {
+                profile.requests_per_minute = requests_per_minute;
+            }
+            if let Some(model_allowlist) = updates.model_allowlist {
+                profile.model_allowlist = model_allowlist;
+            }
+            if let Some(model_denylist) = updates.model_denylist {
+                profile.model_denylist = model_denylist;
+            }
+            if let Some(model_owners) = updates.model_owners {
+                profile.model_owners = model_owners;
+            }
+            if let Some(capture_mode) = updates.capture_mode {
+                profile.capture_mode = capture_mode;
+            }
+            if let Some(output_dir) = updates.output_dir {
+                profile.output_dir = output_dir;
+            }
+            if let Some(filename_template) = updates.filename_template {
+                // 用示例值解析一次，确保模板本身能生成合法的文件名
+                resolve_filename_template(&filename_template, &profile.name, chrono::Local::now())?;
+                profile.filename_template = filename_template;
+            }
+            if let Some(include_reasoning) = updates.include_reasoning {
+                profile.include_reasoning = include_reasoning;
+            }
+            if let Some(system_prompt) = updates.system_prompt {
+                profile.system_prompt = system_prompt;
+            }
+            if let Some(tile_large_images) = updates.tile_large_images {
+                profile.tile_large_images = tile_large_images;
+            }
+            if let Some(tile_threshold_px) = updates.tile_threshold_px {
+                profile.tile_threshold_px = tile_threshold_px;
+            }
+            if let Some(request_log) = updates.request_log {
+                profile.request_log = request_log;
+            }
+            if let Some(fallback_profile_id) = updates.fallback_profile_id {
+                profile.fallback_profile_id = fallback_profile_id;
+            }
+            if let Some(local_ocr) = updates.local_ocr {
+                profile.local_ocr = local_ocr;
+            }
+            if let Some(local_ocr_language) = updates.local_ocr_language {
+                profile.local_ocr_language = local_ocr_language;
+            }
+            if let Some(post_processing) = updates.post_processing {
+                profile.post_processing = post_processing;
+            }
+            if let Some(few_shot) = updates.few_shot {
+                profile.few_shot = few_shot;
+            }
+            if let Some(few_shot_inline) = updates.few_shot_inline {
+                profile.few_shot_inline = few_shot_inline;
+            }
+            if let Some(image_detail) = updates.image_detail {
+                profile.image_detail = image_detail;
+            }
+            if let Some(model_prompt_overrides) = updates.model_prompt_overrides {
+                profile.model_prompt_overrides = model_prompt_overrides;
+            }
+            // 预设列表变化后确保索引仍然有效
+            if profile.active_preset.map_or(false, |i| i >= profile.prompt_presets.len()) {
+                profile.active_preset = None;
+            }
             
-            println!("   📝 Updated active profile configuration");
+            debug!("   📝 Updated active profile configuration");
             Ok(())
         }).await
     }
@@ -341,6 +1604,120 @@ impl AppState {
             if let Some(sound_enabled) = updates.sound_enabled {
                 config.sound_enabled = sound_enabled;
             }
+
+            if let Some(debug_mode) = updates.debug_mode {
+                config.debug_mode = debug_mode;
+            }
+
+            if let Some(separator) = updates.clipboard_append_separator {
+                config.clipboard_append_separator = separator;
+            }
+
+            if let Some(debounce_ms) = updates.hotkey_debounce_ms {
+                config.hotkey_debounce_ms = debounce_ms;
+            }
+
+            if let Some(path) = updates.success_sound_path {
+                validate_sound_path(&path)?;
+                config.success_sound_path = path;
+            }
+
+            if let Some(path) = updates.error_sound_path {
+                validate_sound_path(&path)?;
+                config.error_sound_path = path;
+            }
+
+            if let Some(rate_limit_queue) = updates.rate_limit_queue {
+                config.rate_limit_queue = rate_limit_queue;
+            }
+
+            if let Some(capture_timeout_secs) = updates.capture_timeout_secs {
+                config.capture_timeout_secs = capture_timeout_secs.max(1);
+            }
+
+            if let Some(mock_mode) = updates.mock_mode {
+                config.mock_mode = mock_mode;
+            }
+
+            if let Some(save_captures_dir) = updates.save_captures_dir {
+                config.save_captures_dir = save_captures_dir;
+            }
+
+            if let Some(max_files) = updates.save_captures_max_files {
+                config.save_captures_max_files = max_files;
+            }
+
+            if let Some(log_level) = updates.log_level {
+                log::set_max_level(parse_log_level(&log_level)?);
+                config.log_level = log_level.trim().to_lowercase();
+            }
+
+            if let Some(auto_refresh) = updates.auto_refresh_models_on_switch {
+                config.auto_refresh_models_on_switch = auto_refresh;
+            }
+
+            if let Some(max_payload_bytes) = updates.max_payload_bytes {
+                config.max_payload_bytes = max_payload_bytes;
+            }
+
+            if let Some(shrink) = updates.shrink_oversized_images {
+                config.shrink_oversized_images = shrink;
+            }
+
+            if let Some(stream_to_clipboard) = updates.stream_to_clipboard {
+                config.stream_to_clipboard = stream_to_clipboard;
+            }
+
+            if let Some(notify_on_success) = updates.notify_on_success {
+                config.notify_on_success = notify_on_success;
+            }
+
+            if let Some(preview_before_send) = updates.preview_before_send {
+                config.preview_before_send = preview_before_send;
+            }
+
+            if let Some(analysis_cache_size) = updates.analysis_cache_size {
+                config.analysis_cache_size = analysis_cache_size;
+            }
+
+            if let Some(analysis_cache_ttl_secs) = updates.analysis_cache_ttl_secs {
+                config.analysis_cache_ttl_secs = analysis_cache_ttl_secs.max(1);
+            }
+
+            if let Some(analysis_cache_exact_bytes) = updates.analysis_cache_exact_bytes {
+                config.analysis_cache_exact_bytes = analysis_cache_exact_bytes;
+            }
+
+            if let Some(refusal_patterns) = updates.refusal_patterns {
+                config.refusal_patterns = refusal_patterns;
+            }
+
+            if let Some(retry_on_empty) = updates.retry_on_empty {
+                config.retry_on_empty = retry_on_empty;
+            }
+
+            if let Some(empty_retry_max) = updates.empty_retry_max {
+                config.empty_retry_max = empty_retry_max;
+            }
+
+            if let Some(remote_server_enabled) = updates.remote_server_enabled {
+                config.remote_server_enabled = remote_server_enabled;
+                if remote_server_enabled && config.remote_server_token.is_empty() {
+                    config.remote_server_token = generate_remote_server_token();
+                }
+            }
+
+            if let Some(remote_server_port) = updates.remote_server_port {
+                config.remote_server_port = remote_server_port;
+            }
+
+            if let Some(remote_server_token) = updates.remote_server_token {
+                config.remote_server_token = remote_server_token;
+            }
+
+            if let Some(auto_select_first_model) = updates.auto_select_first_model {
+                config.auto_select_first_model = auto_select_first_model;
+            }
             
             // 注意：热键更新应该独立处理，不在profile中
             if let Some(global_hotkey) = updates.global_hotkey {
@@ -351,7 +1728,7 @@ impl AppState {
                 config.switch_profile_hotkey = switch_hotkey;
             }
             
-            println!("   📝 Updated multiple settings atomically");
+            debug!("   📝 Updated multiple settings atomically");
             Ok(())
         }).await
     }
@@ -378,11 +1755,70 @@ impl AppState {
             }
 
             config.active_profile_id = Some(profile_id);
-            println!("Active profile updated");
+            info!("Active profile updated");
             Ok(())
         }).await
     }
 
+    // 本地限流：等待令牌或返回错误（取决于rate_limit_queue）
+    async fn acquire_rate_limit(&self, profile: &Profile) -> Result<(), String> {
+        if profile.requests_per_minute == 0 {
+            return Ok(());
+        }
+
+        let queue_when_limited = self.config.lock().await.rate_limit_queue;
+        loop {
+            let acquired = {
+                let mut limiters = self.rate_limiters.lock().await;
+                limiters.entry(profile.id.clone())
+                    .or_insert_with(|| TokenBucket::new(profile.requests_per_minute))
+                    .try_acquire(profile.requests_per_minute)
+            };
+
+            match acquired {
+                Ok(()) => return Ok(()),
+                Err(wait) if queue_when_limited => {
+                    info!("⏳ Rate limited locally, waiting {:.1}s", wait.as_secs_f64());
+                    tokio::time::sleep(wait).await;
+                }
+                Err(wait) => {
+                    return Err(format!("Rate limited locally, try again in {}s", wait.as_secs_f64().ceil() as u64));
+                }
+            }
+        }
+    }
+
+    // Closed or half-open (cooldown elapsed) endpoints may be tried
+    async fn endpoint_available(&self, endpoint: &str) -> bool {
+        let health = self.endpoint_health.lock().await;
+        match health.get(endpoint).and_then(|b| b.opened_at) {
+            Some(opened_at) => opened_at.elapsed() >= CIRCUIT_COOLDOWN,
+            None => true,
+        }
+    }
+
+    async fn record_endpoint_success(&self, endpoint: &str) {
+        let mut health = self.endpoint_health.lock().await;
+        if let Some(breaker) = health.get_mut(endpoint) {
+            if breaker.opened_at.is_some() {
+                info!("✅ Endpoint recovered, closing circuit: {}", endpoint);
+            }
+            *breaker = CircuitBreaker::default();
+        }
+    }
+
+    async fn record_endpoint_failure(&self, endpoint: &str) {
+        let mut health = self.endpoint_health.lock().await;
+        let breaker = health.entry(endpoint.to_string()).or_default();
+        breaker.consecutive_failures += 1;
+
+        // A failed half-open probe re-opens the circuit immediately
+        if breaker.opened_at.is_some() || breaker.consecutive_failures >= CIRCUIT_FAILURE_THRESHOLD {
+            breaker.opened_at = Some(std::time::Instant::now());
+            warn!("⚠️ Circuit opened for endpoint {} after {} consecutive failures", endpoint, breaker.consecutive_failures);
+        }
+    }
+
     async fn get_next_profile_id(&self) -> Result<String, String> {
         let config = self.config.lock().await;
 
@@ -405,16 +1841,42 @@ impl AppState {
         let next_index = (current_index + 1) % config.profiles.len();
         Ok(config.profiles[next_index].id.clone())
     }
+
+    async fn get_previous_profile_id(&self) -> Result<String, String> {
+        let config = self.config.lock().await;
+
+        if config.profiles.is_empty() {
+            return Err("No profiles available".to_string());
+        }
+
+        let current_index = if let Some(active_id) = &config.active_profile_id {
+            config.profiles.iter().position(|p| &p.id == active_id).unwrap_or(0)
+        } else {
+            0
+        };
+
+        // 获取上一个profile的索引（循环）
+        let previous_index = (current_index + config.profiles.len() - 1) % config.profiles.len();
+        Ok(config.profiles[previous_index].id.clone())
+    }
 }
 
 // Profile切换功能
 async fn switch_to_next_profile(app_handle: tauri::AppHandle) -> Result<(), String> {
-    let app_state = app_handle.state::<AppState>();
-
     // 获取下一个profile ID
-    let next_profile_id = app_state.get_next_profile_id().await?;
+    let next_profile_id = app_handle.state::<AppState>().get_next_profile_id().await?;
+    switch_to_profile(app_handle, next_profile_id).await
+}
+
+async fn switch_to_previous_profile(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let previous_profile_id = app_handle.state::<AppState>().get_previous_profile_id().await?;
+    switch_to_profile(app_handle, previous_profile_id).await
+}
 
-    // 切换到下一个profile
+async fn switch_to_profile(app_handle: tauri::AppHandle, next_profile_id: String) -> Result<(), String> {
+    let app_state = app_handle.state::<AppState>();
+
+    // 切换到目标profile
     app_state.set_active_profile(next_profile_id.clone()).await?;
 
     // 获取新的活跃profile信息
@@ -427,10 +1889,12 @@ async fn switch_to_next_profile(app_handle: tauri::AppHandle) -> Result<(), Stri
     update_profile_menu_selection(&app_handle, &next_profile_id).await?;
 
     // Update profile submenu title
-    println!("🔧 [DEBUG] Updating profile submenu title from switch hotkey...");
+    debug!("🔧 Updating profile submenu title from switch hotkey...");
     update_profile_submenu_title(&app_handle, &active_profile.name).await?;
+    refresh_profile_menus(&app_handle).await?;
+    schedule_model_refresh(&app_handle).await;
 
-    println!("Switched to profile: {} ({})", active_profile.name, active_profile.id);
+    info!("Switched to profile: {} ({})", active_profile.name, active_profile.id);
     Ok(())
 }
 
@@ -442,10 +1906,46 @@ async fn show_profile_switch_notification(app_handle: &tauri::AppHandle, profile
     app_handle.emit("profile-switched", &notification_text)
         .map_err(|e| format!("Failed to emit profile switch event: {}", e))?;
 
-    println!("Profile switch notification sent: {}", notification_text);
+    info!("Profile switch notification sent: {}", notification_text);
     Ok(())
 }
 
+// 通知正文中结果预览的最大字符数
+const NOTIFICATION_PREVIEW_CHARS: usize = 120;
+
+// 按字符截断，超出时以省略号结尾
+fn truncate_preview(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    format!("{}…", truncated.trim_end())
+}
+
+// OutputMode::ClipboardToast: 复制成功后的确认通知
+fn show_copied_notification(app_handle: &tauri::AppHandle, result: &str) -> Result<(), String> {
+    use tauri_plugin_notification::NotificationExt;
+
+    app_handle.notification()
+        .builder()
+        .title(format!("Copied ({} chars)", result.chars().count()))
+        .body(truncate_preview(result, NOTIFICATION_PREVIEW_CHARS))
+        .show()
+        .map_err(|e| format!("Failed to show notification: {}", e))
+}
+
+fn show_success_notification(app_handle: &tauri::AppHandle, model: &str, result: &str) -> Result<(), String> {
+    use tauri_plugin_notification::NotificationExt;
+
+    app_handle.notification()
+        .builder()
+        .title(format!("MathImage · {}", model))
+        .body(truncate_preview(result, NOTIFICATION_PREVIEW_CHARS))
+        .show()
+        .map_err(|e| format!("Failed to show notification: {}", e))
+}
+
 async fn update_profile_menu_selection(app_handle: &tauri::AppHandle, selected_profile_id: &str) -> Result<(), String> {
     let app_state = app_handle.state::<AppState>();
 
@@ -453,7 +1953,7 @@ async fn update_profile_menu_selection(app_handle: &tauri::AppHandle, selected_p
     let profile_check_items = app_state.profile_check_items.lock().await;
 
     if profile_check_items.is_empty() {
-        println!("No profile CheckMenuItem references found for update");
+        debug!("No profile CheckMenuItem references found for update");
         return Ok(());
     }
 
@@ -462,10 +1962,10 @@ async fn update_profile_menu_selection(app_handle: &tauri::AppHandle, selected_p
 
         match check_item.set_checked(should_be_checked) {
             Ok(()) => {
-                println!("Updated profile '{}' checked state to: {}", profile_id, should_be_checked);
+                debug!("Updated profile '{}' checked state to: {}", profile_id, should_be_checked);
             }
             Err(e) => {
-                println!("Failed to update profile '{}' checked state: {}", profile_id, e);
+                error!("Failed to update profile '{}' checked state: {}", profile_id, e);
             }
         }
     }
@@ -474,7 +1974,7 @@ async fn update_profile_menu_selection(app_handle: &tauri::AppHandle, selected_p
 }
 
 async fn update_profile_submenu_title(app_handle: &tauri::AppHandle, profile_name: &str) -> Result<(), String> {
-    println!("📝 [DEBUG] Updating profile submenu title to: '{}'", profile_name);
+    debug!("📝 Updating profile submenu title to: '{}'", profile_name);
     
     let state = app_handle.state::<AppState>();
     
@@ -483,1435 +1983,6517 @@ async fn update_profile_submenu_title(app_handle: &tauri::AppHandle, profile_nam
         Ok(submenu_ref) => {
             if let Some(submenu) = &*submenu_ref {
                 let new_title = format!("Profile: {}", profile_name);
-                println!("   📝 Attempting to update profile submenu title to: '{}'", new_title);
+                debug!("   📝 Attempting to update profile submenu title to: '{}'", new_title);
                 
                 match submenu.set_text(&new_title) {
                     Ok(()) => {
-                        println!("   ✅ Successfully updated profile submenu title to '{}'", new_title);
+                        debug!("   ✅ Successfully updated profile submenu title to '{}'", new_title);
                     }
                     Err(e) => {
-                        println!("   ❌ Failed to update profile submenu title: {}", e);
+                        error!("   ❌ Failed to update profile submenu title: {}", e);
                     }
                 }
             } else {
-                println!("   ⚠️  No profile submenu reference available for title update");
+                warn!("   ⚠️  No profile submenu reference available for title update");
             }
         }
         Err(e) => {
-            println!("   ❌ Failed to acquire profile submenu lock for title update: {}", e);
+            error!("   ❌ Failed to acquire profile submenu lock for title update: {}", e);
         }
     }
     
     Ok(())
 }
 
-async fn update_model_submenu_title(app_handle: &tauri::AppHandle, model_name: &str) -> Result<(), String> {
-    println!("📝 [DEBUG] Updating model submenu title to: '{}'", model_name);
-
+// 按config.profiles的顺序重新填充Profile子菜单
+async fn rebuild_profile_submenu(app_handle: &tauri::AppHandle) -> Result<(), String> {
     let state = app_handle.state::<AppState>();
-    match state.model_submenu.try_lock() {
-        Ok(submenu_ref) => {
-            if let Some(submenu) = &*submenu_ref {
-                let new_title = format!("Model: {}", model_name);
-                println!("   📝 Attempting to update model submenu title to: '{}'", new_title);
-                match submenu.set_text(&new_title) {
-                    Ok(()) => println!("   ✅ Successfully updated model submenu title"),
-                    Err(e) => println!("   ❌ Failed to update model submenu title: {}", e),
-                }
-            } else {
-                println!("   ⚠️  No model submenu reference available for title update");
-            }
+    let (profiles, active_id) = {
+        let config = state.config.lock().await;
+        (config.profiles.clone(), config.active_profile_id.clone())
+    };
+
+    let submenu = match state.profile_submenu.lock().await.clone() {
+        Some(submenu) => submenu,
+        None => {
+            warn!("   ⚠️  No profile submenu reference available for rebuild");
+            return Ok(());
+        }
+    };
+
+    let old_items = submenu.items()
+        .map_err(|e| format!("Failed to read profile submenu items: {}", e))?;
+    for item in old_items {
+        if let Err(e) = submenu.remove(&item) {
+            error!("   ❌ Failed to remove profile menu item: {}", e);
         }
-        Err(e) => println!("   ❌ Failed to acquire model submenu lock for title update: {}", e),
     }
 
+    let mut check_items = std::collections::HashMap::new();
+    for profile in &profiles {
+        let profile_item = CheckMenuItemBuilder::new(&profile.name)
+            .id(&format!("profile_{}", profile.id))
+            .checked(Some(&profile.id) == active_id.as_ref())
+            .build(app_handle)
+            .map_err(|e| format!("Failed to create profile menu item: {}", e))?;
+        submenu.append(&profile_item)
+            .map_err(|e| format!("Failed to append profile menu item: {}", e))?;
+        check_items.insert(profile.id.clone(), profile_item);
+    }
+
+    let mut profile_check_items = state.profile_check_items.lock().await;
+    *profile_check_items = check_items;
+
+    debug!("✅ Rebuilt profile submenu with {} profiles", profiles.len());
     Ok(())
 }
 
-async fn update_model_menu_selection(app_handle: &tauri::AppHandle, selected_model_id: &str) -> Result<(), String> {
-    let app_state = app_handle.state::<AppState>();
-    let items = app_state.model_check_items.lock().await;
-    if items.is_empty() {
-        println!("No model CheckMenuItem references found for update");
-        return Ok(());
-    }
-    for (model_id, check_item) in items.iter() {
-        let should_be_checked = model_id == selected_model_id;
-        if let Err(e) = check_item.set_checked(should_be_checked) {
-            println!("Failed to update model '{}' checked state: {}", model_id, e);
-        }
+// 为Profile的prompt预设创建CheckMenuItem，"Default"表示使用Profile自身的prompt模式
+fn build_preset_check_items(
+    app_handle: &tauri::AppHandle,
+    profile: &Profile,
+) -> tauri::Result<Vec<(String, tauri::menu::CheckMenuItem<tauri::Wry>)>> {
+    let mut items = Vec::new();
+
+    let default_item = CheckMenuItemBuilder::new("Default")
+        .id("preset_default")
+        .checked(profile.active_preset.is_none())
+        .build(app_handle)?;
+    items.push(("default".to_string(), default_item));
+
+    for (index, (name, _)) in profile.prompt_presets.iter().enumerate() {
+        let item = CheckMenuItemBuilder::new(name)
+            .id(&format!("preset_{}", index))
+            .checked(profile.active_preset == Some(index))
+            .build(app_handle)?;
+        items.push((index.to_string(), item));
     }
-    Ok(())
+
+    Ok(items)
 }
 
-async fn update_hotkey_menu_text(app_handle: &tauri::AppHandle, global_hotkey: &str, switch_hotkey: &str) -> Result<(), String> {
+// 切换Profile或修改预设后重新填充预设子菜单
+async fn rebuild_preset_submenu(app_handle: &tauri::AppHandle) -> Result<(), String> {
     let state = app_handle.state::<AppState>();
-    let formatted_global = format_hotkey_for_display(global_hotkey);
-    let formatted_switch = format_hotkey_for_display(switch_hotkey);
+    let active_profile = state.get_active_profile().await?;
 
-    if let Ok(item_guard) = state.global_hotkey_item.try_lock() {
-        if let Some(item) = &*item_guard {
-            if let Err(e) = item.set_text(&format!("Global: {}", formatted_global)) {
-                println!("Failed to update global hotkey item text: {}", e);
-            }
+    let submenu = match state.preset_submenu.lock().await.clone() {
+        Some(submenu) => submenu,
+        None => {
+            warn!("   ⚠️  No preset submenu reference available for rebuild");
+            return Ok(());
         }
-    }
+    };
 
-    if let Ok(item_guard) = state.switch_hotkey_item.try_lock() {
-        if let Some(item) = &*item_guard {
-            if let Err(e) = item.set_text(&format!("Switch: {}", formatted_switch)) {
-                println!("Failed to update switch hotkey item text: {}", e);
-            }
+    let old_items = submenu.items()
+        .map_err(|e| format!("Failed to read preset submenu items: {}", e))?;
+    for item in old_items {
+        if let Err(e) = submenu.remove(&item) {
+            error!("   ❌ Failed to remove preset menu item: {}", e);
         }
     }
 
-    Ok(())
-}
+    let new_items = build_preset_check_items(app_handle, &active_profile)
+        .map_err(|e| format!("Failed to create preset menu items: {}", e))?;
+    for (_, item) in &new_items {
+        submenu.append(item)
+            .map_err(|e| format!("Failed to append preset menu item: {}", e))?;
+    }
 
-async fn update_sound_menu_text(app_handle: &tauri::AppHandle, enabled: bool) -> Result<(), String> {
-    let state = app_handle.state::<AppState>();
-    let text = if enabled { "Enabled" } else { "Disabled" };
-    if let Ok(item_guard) = state.sound_item.try_lock() {
-        if let Some(item) = &*item_guard {
-            if let Err(e) = item.set_text(&format!("Sound: {}", text)) {
-                println!("Failed to update sound item text: {}", e);
-            }
-        }
+    if let Err(e) = submenu.set_text(&format!("Prompt: {}", active_profile.active_preset_name())) {
+        error!("   ❌ Failed to update preset submenu title: {}", e);
     }
+
+    let mut preset_check_items = state.preset_check_items.lock().await;
+    *preset_check_items = new_items.into_iter().collect();
+
+    debug!("✅ Rebuilt prompt preset submenu with {} presets", active_profile.prompt_presets.len());
     Ok(())
 }
 
-// Sanitize error messages to avoid information leakage
-fn sanitize_error(error: &str) -> String {
-    if error.contains("Connection refused") || error.contains("timeout") {
-        "Network connection failed".to_string()
-    } else if error.contains("401") || error.contains("403") {
-        "Authentication failed".to_string()
-    } else if error.contains("404") {
-        "Service not found".to_string()
-    } else if error.contains("500") || error.contains("502") || error.contains("503") {
-        "Server error".to_string()
+// 切换Profile后刷新依赖于Profile的子菜单：prompt预设、该base_url的模型列表和模型标题
+async fn refresh_profile_menus(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    rebuild_preset_submenu(app_handle).await?;
+    rebuild_model_submenu(app_handle).await?;
+
+    let active_profile = app_handle.state::<AppState>().get_active_profile().await?;
+    let model_display = if active_profile.api_config.model.is_empty() {
+        "Not Selected"
     } else {
-        "Request failed".to_string()
-    }
+        &active_profile.api_config.model
+    };
+    update_model_submenu_title(app_handle, model_display).await
 }
 
+// 切换Profile后在后台重新加载模型列表（auto_refresh_models_on_switch开启时）
+// 防抖：快速连续切换时只有最后一次切换在等待结束后真正发起请求
+const MODEL_REFRESH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(800);
 
+async fn schedule_model_refresh(app_handle: &tauri::AppHandle) {
+    let state = app_handle.state::<AppState>();
+    if !state.config.lock().await.auto_refresh_models_on_switch {
+        return;
+    }
 
-#[tauri::command]
-async fn get_config(state: State<'_, AppState>) -> Result<Config, String> {
-    let config = state.config.lock().await;
-    Ok(config.clone())
-}
+    let generation = state.model_refresh_generation.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(MODEL_REFRESH_DEBOUNCE).await;
 
-// 简化的Tauri命令 - 保持前端兼容
+        let state = app_handle.state::<AppState>();
+        if state.model_refresh_generation.load(std::sync::atomic::Ordering::SeqCst) != generation {
+            debug!("Skipping model refresh superseded by a newer profile switch");
+            return;
+        }
 
-#[tauri::command]
-async fn create_profile(state: State<'_, AppState>, profile: serde_json::Value) -> Result<String, String> {
-    println!("🔧 [DEBUG] Creating profile from frontend data...");
-    
-    // 从前端数据中提取profile名称
-    let name = profile.get("name")
-        .and_then(|v| v.as_str())
-        .ok_or("Profile name is required")?;
-    
-    // 使用简化的内部方法
-    let profile_id = state.create_new_profile(name.to_string()).await?;
-    println!("✅ [DEBUG] Profile created successfully: {} ({})", name, profile_id);
-    Ok(profile_id)
+        if let Err(e) = refresh_models_in_tray(app_handle.clone()).await {
+            warn!("⚠️ Failed to auto-refresh models after profile switch: {}", e);
+        }
+    });
 }
 
-#[tauri::command]
-async fn update_profile_config(state: State<'_, AppState>, profile_data: serde_json::Value) -> Result<(), String> {
-    println!("🔧 [DEBUG] Updating profile config (legacy compatibility)...");
-    
-    // 直接使用新的update_active_profile逻辑
-    update_active_profile(state, profile_data).await
-}
+async fn select_preset_in_tray(app_handle: tauri::AppHandle, preset_key: String) -> Result<(), String> {
+    debug!("🔍 Selecting prompt preset from tray: {}", preset_key);
 
-#[tauri::command]
-async fn update_active_profile(state: State<'_, AppState>, update_data: serde_json::Value) -> Result<(), String> {
-    println!("🔧 [DEBUG] Updating active profile configuration...");
-    
-    let mut updates = ProfileConfigUpdate::default();
-    
-    // 解析更新字段
-    if let Some(name) = update_data.get("profileName").and_then(|v| v.as_str()) {
-        if !name.trim().is_empty() {
-            updates.name = Some(name.trim().to_string());
+    let preset_index = if preset_key == "default" {
+        None
+    } else {
+        Some(preset_key.parse::<usize>().map_err(|_| format!("Invalid preset id '{}'", preset_key))?)
+    };
+
+    let state = app_handle.state::<AppState>();
+    state.update_and_save_config(|config| {
+        let active_id = config.active_profile_id.clone()
+            .ok_or("No active profile")?;
+
+        let profile = config.profiles.iter_mut()
+            .find(|p| p.id == active_id)
+            .ok_or("Active profile not found")?;
+
+        if preset_index.map_or(false, |i| i >= profile.prompt_presets.len()) {
+            return Err(format!("Prompt preset {} not found", preset_key));
         }
-    }
-    
-    if let Some(base_url) = update_data.get("apiBaseUrl").and_then(|v| v.as_str()) {
-        updates.base_url = Some(base_url.to_string());
-    }
-    
-    if let Some(api_key) = update_data.get("apiKey").and_then(|v| v.as_str()) {
-        updates.api_key = Some(api_key.to_string());
-    }
-    
-    if let Some(model) = update_data.get("model").and_then(|v| v.as_str()) {
-        updates.model = Some(model.to_string());
-    }
-    
-    // 解析prompt模式
-    if let Some(prompt_mode) = update_data.get("promptMode").and_then(|v| v.as_str()) {
-        match prompt_mode {
-            "user_input" => {
-                updates.prompt_mode = Some(PromptMode::UserInput);
-            }
-            "predefined" | _ => {
-                let prompt_text = update_data.get("prompt")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("识别公式和文字，返回使用pandoc语法的markdown排版内容。公式请用katex语法包裹，文字内容不要丢失。只返回内容不需要其他解释。");
-                updates.prompt_mode = Some(PromptMode::Predefined(prompt_text.to_string()));
+
+        profile.active_preset = preset_index;
+        debug!("   📝 Updated active prompt preset to: {:?}", preset_index);
+        Ok(())
+    }).await?;
+
+    let active_profile = state.get_active_profile().await?;
+
+    // RadioButton behavior for the preset items
+    {
+        let preset_check_items = state.preset_check_items.lock().await;
+        for (key, check_item) in preset_check_items.iter() {
+            if let Err(e) = check_item.set_checked(key == &preset_key) {
+                error!("Failed to update preset '{}' checked state: {}", key, e);
             }
         }
     }
-    
-    // 解析输出模式  
-    if let Some(output_mode) = update_data.get("outputMode").and_then(|v| v.as_str()) {
-        match output_mode {
-            "dialog" => {
-                updates.output_mode = Some(OutputMode::Dialog);
-            }
-            "clipboard" | _ => {
-                updates.output_mode = Some(OutputMode::Clipboard);
+
+    if let Ok(submenu_ref) = state.preset_submenu.try_lock() {
+        if let Some(submenu) = &*submenu_ref {
+            if let Err(e) = submenu.set_text(&format!("Prompt: {}", active_profile.active_preset_name())) {
+                error!("   ❌ Failed to update preset submenu title: {}", e);
             }
         }
     }
-    
-    state.update_active_profile_config(updates).await?;
-    
-    // 同时更新全局设置（如果提供）
-    if let Some(sound_enabled) = update_data.get("soundEnabled").and_then(|v| v.as_bool()) {
-        let global_updates = ConfigUpdates {
-            sound_enabled: Some(sound_enabled),
-            active_profile_id: None,
-            global_hotkey: None,
-            switch_profile_hotkey: None,
-        };
-        state.update_multiple_settings(global_updates).await?;
-    }
-    
-    println!("✅ [DEBUG] Active profile updated successfully");
+
+    debug!("✅ Prompt preset '{}' selected", active_profile.active_preset_name());
     Ok(())
 }
 
-#[tauri::command]
-async fn delete_profile(state: State<'_, AppState>, profile_id: String) -> Result<(), String> {
-    println!("🔧 [DEBUG] Deleting profile: {}", profile_id);
-    
-    state.update_and_save_config(|config| {
-        if config.profiles.len() <= 1 {
-            return Err("Cannot delete the last profile".to_string());
-        }
-
-        let profile_index = config.profiles.iter()
-            .position(|p| p.id == profile_id)
-            .ok_or("Profile not found")?;
-
-        let deleted_profile = config.profiles.remove(profile_index);
+async fn update_model_submenu_title(app_handle: &tauri::AppHandle, model_name: &str) -> Result<(), String> {
+    debug!("📝 Updating model submenu title to: '{}'", model_name);
 
-        // 如果删除的是活跃profile，切换到第一个profile
-        if config.active_profile_id.as_ref() == Some(&profile_id) {
-            config.active_profile_id = config.profiles.first().map(|p| p.id.clone());
-            println!("   📝 Switched active profile to: {:?}", config.active_profile_id);
+    let state = app_handle.state::<AppState>();
+    match state.model_submenu.try_lock() {
+        Ok(submenu_ref) => {
+            if let Some(submenu) = &*submenu_ref {
+                let new_title = format!("Model: {}", model_name);
+                debug!("   📝 Attempting to update model submenu title to: '{}'", new_title);
+                match submenu.set_text(&new_title) {
+                    Ok(()) => debug!("   ✅ Successfully updated model submenu title"),
+                    Err(e) => error!("   ❌ Failed to update model submenu title: {}", e),
+                }
+            } else {
+                warn!("   ⚠️  No model submenu reference available for title update");
+            }
         }
+        Err(e) => error!("   ❌ Failed to acquire model submenu lock for title update: {}", e),
+    }
 
-        println!("   📝 Deleted profile: {} ({})", deleted_profile.name, profile_id);
-        Ok(())
-    }).await?;
-
-    println!("✅ [DEBUG] Profile deleted successfully: {}", profile_id);
-    Ok(())
-}
-
-#[tauri::command]
-async fn set_active_profile(app_handle: tauri::AppHandle, state: State<'_, AppState>, profile_id: String) -> Result<(), String> {
-    state.set_active_profile(profile_id.clone()).await?;
-    
-    // Get the new active profile for submenu title update
-    let active_profile = state.get_active_profile().await?;
-    
-    // Update profile submenu title
-    println!("🔧 [DEBUG] Updating profile submenu title from Settings page...");
-    update_profile_submenu_title(&app_handle, &active_profile.name).await?;
-    
     Ok(())
 }
 
-#[tauri::command]
-async fn update_config(state: State<'_, AppState>, new_config: Config) -> Result<(), String> {
-    println!("🔧 [DEBUG] Updating entire configuration...");
-    
-    // 先原子性保存到文件
-    AppState::save_config_atomic(&new_config).await?;
-    
-    // 然后更新内存中的配置
-    let mut config = state.config.lock().await;
-    *config = new_config;
-    
-    println!("✅ [DEBUG] Configuration updated successfully");
-    Ok(())
+// 不区分大小写的子串过滤，空过滤串返回全部模型
+fn filter_models(models: &[String], filter: &str) -> Vec<String> {
+    let filter = filter.trim().to_lowercase();
+    if filter.is_empty() {
+        return models.to_vec();
+    }
+    models.iter()
+        .filter(|m| m.to_lowercase().contains(&filter))
+        .cloned()
+        .collect()
 }
 
-#[tauri::command]
-async fn get_models(base_url: String, api_key: String, state: State<'_, AppState>) -> Result<Vec<ModelInfo>, String> {
-    if api_key.is_empty() || base_url.is_empty() {
-        return Err("API key and base URL are required".to_string());
+// 简单通配符匹配：* 匹配任意字符串，? 匹配单个字符，不区分大小写
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
     }
+    pattern[p..].iter().all(|&c| c == '*')
+}
 
-    let url = format!("{}/models", base_url);
-
-    let response = state.http_client
-        .get(&url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .send()
-        .await
-        .map_err(|e| sanitize_error(&e.to_string()))?;
+// 应用允许/屏蔽名单
+fn apply_model_lists(models: &[String], allowlist: &[String], denylist: &[String]) -> Vec<String> {
+    models.iter()
+        .filter(|m| allowlist.is_empty() || allowlist.iter().any(|p| glob_matches(p, m)))
+        .filter(|m| !denylist.iter().any(|p| glob_matches(p, m)))
+        .cloned()
+        .collect()
+}
 
-    if !response.status().is_success() {
-        return Err(sanitize_error(&format!("Status: {}", response.status())));
+impl Profile {
+    // 托盘中显示的模型：先应用允许/屏蔽名单，再应用过滤字符串
+    fn tray_models(&self, models: &[String]) -> Vec<String> {
+        filter_models(&apply_model_lists(models, &self.model_allowlist, &self.model_denylist), &self.model_filter)
     }
+}
 
-    let response_text = response.text().await
-        .map_err(|_| "Failed to read response".to_string())?;
-
-    let json: serde_json::Value = serde_json::from_str(&response_text)
-        .map_err(|_| "Invalid response format".to_string())?;
+const MAX_RECENT_MODELS: usize = 5;
+
+// 拆分为最近使用的模型（按最近顺序，仅包含当前列表中存在的）和其余模型（保持原顺序）
+fn split_recent_models(models: &[String], recent: &[String]) -> (Vec<String>, Vec<String>) {
+    let recent_models: Vec<String> = recent.iter()
+        .filter(|m| models.contains(m))
+        .cloned()
+        .collect();
+    let other_models = models.iter()
+        .filter(|m| !recent_models.contains(m))
+        .cloned()
+        .collect();
+    (recent_models, other_models)
+}
 
-    if let Some(data) = json.get("data").and_then(|d| d.as_array()) {
-        let models: Vec<ModelInfo> = data.iter()
-            .filter_map(|model| {
-                if let (Some(id), Some(object)) = (
-                    model.get("id").and_then(|i| i.as_str()),
-                    model.get("object").and_then(|o| o.as_str())
-                ) {
-                    Some(ModelInfo {
-                        id: id.to_string(),
-                        object: object.to_string(),
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect();
-        Ok(models)
+fn model_filter_label(filter: &str) -> String {
+    if filter.trim().is_empty() {
+        "Filter models…".to_string()
     } else {
-        Err("Invalid response format".to_string())
+        format!("Filter models… ({})", filter.trim())
     }
 }
 
+// 按当前loaded_models重新填充模型子菜单
+async fn rebuild_model_submenu(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let active_profile = state.get_active_profile().await?;
+    let models = active_profile.tray_models(&state.get_models_for_active_profile().await?);
 
+    let submenu = match state.model_submenu.lock().await.clone() {
+        Some(submenu) => submenu,
+        None => {
+            warn!("   ⚠️  No model submenu reference available for rebuild");
+            return Ok(());
+        }
+    };
 
-#[tauri::command]
-async fn take_interactive_screenshot() -> Result<String, String> {
-    use std::process::Command;
-    use std::fs;
-
-    // Create temp file path with timestamp for uniqueness
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis();
-    let temp_path = format!("/tmp/mathimage_screenshot_{}.png", timestamp);
+    let old_items = submenu.items()
+        .map_err(|e| format!("Failed to read model submenu items: {}", e))?;
+    for item in old_items {
+        if let Err(e) = submenu.remove(&item) {
+            error!("   ❌ Failed to remove model menu item: {}", e);
+        }
+    }
 
-    // Use macOS screencapture with interactive selection
-    let output = Command::new("screencapture")
-        .arg("-i")  // Interactive selection
-        .arg("-r")  // Do not add drop shadow
-        .arg(&temp_path)
-        .output()
-        .map_err(|e| format!("Failed to execute screencapture: {}", e))?;
+    let check_items = fill_model_submenu(app_handle, &submenu, &active_profile, &models)?;
 
-    if !output.status.success() {
-        return Err("Screenshot was cancelled".to_string()); // 用户取消，不显示对话框
-    }
+    let mut model_check_items = state.model_check_items.lock().await;
+    *model_check_items = check_items;
 
-    // Check if file was created and has content
-    if !std::path::Path::new(&temp_path).exists() {
-        return Err("Screenshot was cancelled".to_string()); // 用户取消，不显示对话框
-    }
+    debug!("✅ Rebuilt model submenu with {} models", models.len());
+    Ok(())
+}
 
-    let metadata = fs::metadata(&temp_path)
-        .map_err(|_| "Screenshot was cancelled".to_string())?; // 用户取消，不显示对话框
+// 向空的模型子菜单追加Load Models、过滤项和模型列表，返回模型CheckMenuItem供后续更新勾选状态
+fn fill_model_submenu(
+    app_handle: &tauri::AppHandle,
+    submenu: &tauri::menu::Submenu<tauri::Wry>,
+    active_profile: &Profile,
+    models: &[String],
+) -> Result<std::collections::HashMap<String, tauri::menu::CheckMenuItem<tauri::Wry>>, String> {
+    // Always keep "Load Models" first
+    let load_models_item = MenuItemBuilder::new("Load Models").id("load_models").build(app_handle)
+        .map_err(|e| format!("Failed to create menu item: {}", e))?;
+    submenu.append(&load_models_item)
+        .map_err(|e| format!("Failed to append menu item: {}", e))?;
+
+    let filter_models_item = MenuItemBuilder::new(&model_filter_label(&active_profile.model_filter))
+        .id("filter_models")
+        .build(app_handle)
+        .map_err(|e| format!("Failed to create menu item: {}", e))?;
+    submenu.append(&filter_models_item)
+        .map_err(|e| format!("Failed to append menu item: {}", e))?;
+
+    let mut check_items = std::collections::HashMap::new();
+    if !models.is_empty() {
+        let separator = PredefinedMenuItem::separator(app_handle)
+            .map_err(|e| format!("Failed to create separator: {}", e))?;
+        submenu.append(&separator)
+            .map_err(|e| format!("Failed to append separator: {}", e))?;
+
+        let (recent_models, other_models) = split_recent_models(models, &active_profile.recent_models);
+        for (index, model_id) in recent_models.iter().chain(other_models.iter()).enumerate() {
+            // 最近使用的模型和其余模型之间用分隔线隔开
+            if index == recent_models.len() && index > 0 {
+                let separator = PredefinedMenuItem::separator(app_handle)
+                    .map_err(|e| format!("Failed to create separator: {}", e))?;
+                submenu.append(&separator)
+                    .map_err(|e| format!("Failed to append separator: {}", e))?;
+            }
 
-    if metadata.len() == 0 {
-        // Clean up empty file
-        let _ = fs::remove_file(&temp_path);
-        return Err("Screenshot was cancelled".to_string()); // 用户取消，不显示对话框
+            let model_item = CheckMenuItemBuilder::new(model_id)
+                .id(&format!("model_{}", model_id))
+                .checked(model_id == &active_profile.api_config.model)
+                .build(app_handle)
+                .map_err(|e| format!("Failed to create model menu item: {}", e))?;
+            submenu.append(&model_item)
+                .map_err(|e| format!("Failed to append model menu item: {}", e))?;
+            check_items.insert(model_id.clone(), model_item);
+        }
     }
 
-    // Read the image file with size limit (10MB max)
-    const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
-    if metadata.len() > MAX_FILE_SIZE {
-        let _ = fs::remove_file(&temp_path);
-        return Err("Screenshot file too large".to_string());
-    }
+    Ok(check_items)
+}
 
-    let image_data = fs::read(&temp_path)
-        .map_err(|e| format!("Failed to read screenshot file: {}", e))?;
+// 弹出输入框设置模型过滤串，保存到活跃Profile并重建模型子菜单
+async fn prompt_model_filter(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let current_filter = state.get_active_profile().await?.model_filter;
 
-    // Clean up temp file
-    let _ = fs::remove_file(&temp_path);
+    let filter = show_input_dialog(
+        app_handle.clone(),
+        "Show only models containing (leave empty to show all):".to_string(),
+        current_filter,
+    ).await?;
 
-    // Convert to base64
-    let base64_image = general_purpose::STANDARD.encode(&image_data);
-    println!("Interactive screenshot captured, size: {} bytes", image_data.len());
+    state.update_and_save_config(|config| {
+        let active_id = config.active_profile_id.clone()
+            .ok_or("No active profile")?;
+        let profile = config.profiles.iter_mut()
+            .find(|p| p.id == active_id)
+            .ok_or("Active profile not found")?;
 
-    Ok(format!("data:image/png;base64,{}", base64_image))
-}
+        profile.model_filter = filter.trim().to_string();
+        debug!("   📝 Updated model filter to: '{}'", profile.model_filter);
+        Ok(())
+    }).await?;
 
-#[tauri::command]
-async fn take_screenshot_region(x: Option<u32>, y: Option<u32>, width: Option<u32>, height: Option<u32>) -> Result<String, String> {
-    let screens = Screen::all().map_err(|_| "Failed to access screen".to_string())?;
+    rebuild_model_submenu(&app_handle).await
+}
 
-    if screens.is_empty() {
-        return Err("No screens found".to_string());
+async fn update_model_menu_selection(app_handle: &tauri::AppHandle, selected_model_id: &str) -> Result<(), String> {
+    let app_state = app_handle.state::<AppState>();
+    let items = app_state.model_check_items.lock().await;
+    if items.is_empty() {
+        debug!("No model CheckMenuItem references found for update");
+        return Ok(());
     }
-
-    let screen = &screens[0]; // Use primary screen
-
-    // Validate region size to prevent memory issues
-    if let (Some(_), Some(_), Some(w), Some(h)) = (x, y, width, height) {
-        const MAX_DIMENSION: u32 = 4096; // 4K max
-        if w > MAX_DIMENSION || h > MAX_DIMENSION {
-            return Err("Screenshot region too large".to_string());
+    for (model_id, check_item) in items.iter() {
+        let should_be_checked = model_id == selected_model_id;
+        if let Err(e) = check_item.set_checked(should_be_checked) {
+            error!("Failed to update model '{}' checked state: {}", model_id, e);
         }
+    }
+    Ok(())
+}
 
-        // Check memory requirement (4 bytes per pixel for RGBA)
-        const MAX_MEMORY: u64 = 64 * 1024 * 1024; // 64MB max
-        let memory_needed = (w as u64) * (h as u64) * 4;
-        if memory_needed > MAX_MEMORY {
-            return Err("Screenshot would require too much memory".to_string());
+async fn update_hotkey_menu_text(app_handle: &tauri::AppHandle, global_hotkey: &str, switch_hotkey: &str) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let formatted_global = format_hotkey_for_display(global_hotkey);
+    let formatted_switch = format_hotkey_for_display(switch_hotkey);
+
+    if let Ok(item_guard) = state.global_hotkey_item.try_lock() {
+        if let Some(item) = &*item_guard {
+            if let Err(e) = item.set_text(&format!("Global: {}", formatted_global)) {
+                error!("Failed to update global hotkey item text: {}", e);
+            }
         }
     }
 
-    let image = if let (Some(x), Some(y), Some(w), Some(h)) = (x, y, width, height) {
-        // Capture specific region
-        screen.capture_area(x as i32, y as i32, w, h)
-            .map_err(|_| "Failed to capture region".to_string())?
-    } else {
-        // Capture full screen
-        screen.capture().map_err(|_| "Failed to capture screen".to_string())?
-    };
+    if let Ok(item_guard) = state.switch_hotkey_item.try_lock() {
+        if let Some(item) = &*item_guard {
+            if let Err(e) = item.set_text(&format!("Switch: {}", formatted_switch)) {
+                error!("Failed to update switch hotkey item text: {}", e);
+            }
+        }
+    }
 
-    // Convert to base64 - screenshots::Image has rgba() method
-    let rgba_data = image.rgba();
-    let width = image.width();
-    let height = image.height();
+    Ok(())
+}
 
-    // Create image from raw RGBA data
-    let img = image::RgbaImage::from_raw(width, height, rgba_data.to_vec())
-        .ok_or("Failed to create image from RGBA data")?;
+async fn update_sound_menu_text(app_handle: &tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let text = if enabled { "Enabled" } else { "Disabled" };
+    if let Ok(item_guard) = state.sound_item.try_lock() {
+        if let Some(item) = &*item_guard {
+            if let Err(e) = item.set_text(&format!("Sound: {}", text)) {
+                error!("Failed to update sound item text: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
 
-    // Resize image if too large (max 512x512 to reduce size further)
-    let max_size = 512;
-    let (new_width, new_height) = if width > max_size || height > max_size {
-        let scale = (max_size as f32 / width.max(height) as f32).min(1.0);
-        ((width as f32 * scale) as u32, (height as f32 * scale) as u32)
-    } else {
-        (width, height)
+async fn update_hotkeys_enabled_menu_text(app_handle: &tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let text = if enabled { "Enabled" } else { "Disabled" };
+    if let Ok(item_guard) = state.hotkeys_item.try_lock() {
+        if let Some(item) = &*item_guard {
+            if let Err(e) = item.set_text(&format!("Hotkeys: {}", text)) {
+                error!("Failed to update hotkeys item text: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// "Last: ✓ 3s ago" / "Last: ✗ Authentication failed"
+fn format_last_status(status: Option<&LastStatus>, now: std::time::Instant) -> String {
+    let status = match status {
+        Some(status) => status,
+        None => return "Last: —".to_string(),
     };
 
-    let resized_img = if new_width != width || new_height != height {
-        image::imageops::resize(&img, new_width, new_height, image::imageops::FilterType::Lanczos3)
-    } else {
-        img
+    match &status.error {
+        Some(error) => format!("Last: ✗ {}", error),
+        None => {
+            let secs = now.saturating_duration_since(status.at).as_secs();
+            let ago = if secs < 60 {
+                format!("{}s", secs)
+            } else if secs < 3600 {
+                format!("{}m", secs / 60)
+            } else {
+                format!("{}h", secs / 3600)
+            };
+            format!("Last: ✓ {} ago", ago)
+        }
+    }
+}
+
+// 按当前时间刷新托盘中的最近状态（打开托盘菜单时也会调用，使 "ago" 保持准确）
+fn update_last_status_item(app_handle: &tauri::AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let text = match state.last_status.try_lock() {
+        Ok(status) => format_last_status(status.as_ref(), std::time::Instant::now()),
+        Err(_) => return,
     };
 
-    // Convert RGBA to RGB for JPEG (JPEG doesn't support alpha channel)
-    let rgb_img: image::RgbImage = image::ImageBuffer::from_fn(new_width, new_height, |x, y| {
-        let rgba = resized_img.get_pixel(x, y);
-        image::Rgb([rgba[0], rgba[1], rgba[2]]) // Drop alpha channel
-    });
+    if let Ok(item_guard) = state.last_status_item.try_lock() {
+        if let Some(item) = &*item_guard {
+            if let Err(e) = item.set_text(&text) {
+                error!("Failed to update last status item text: {}", e);
+            }
+        }
+    }
+}
 
-    // Convert to JPEG for smaller size
-    let mut buffer = Vec::new();
-    {
-        let mut cursor = std::io::Cursor::new(&mut buffer);
-        image::write_buffer_with_format(
-            &mut cursor,
-            rgb_img.as_raw(),
-            new_width,
-            new_height,
-            image::ColorType::Rgb8,
-            image::ImageFormat::Jpeg,
-        ).map_err(|e| format!("Failed to encode image: {}", e))?;
+async fn record_last_status(app_handle: &tauri::AppHandle, error: Option<String>) {
+    let state = app_handle.state::<AppState>();
+    *state.last_status.lock().await = Some(LastStatus { error, at: std::time::Instant::now() });
+    update_last_status_item(app_handle);
+}
+
+// 规范化base_url：要求http(s)协议，去掉末尾斜杠，避免拼出 `https://host//models`
+// An empty string is kept as-is and means "not configured".
+fn normalize_base_url(base_url: &str) -> Result<String, String> {
+    let trimmed = base_url.trim().trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Ok(String::new());
     }
 
-    let base64_image = general_purpose::STANDARD.encode(&buffer);
-    println!("Screenshot captured: {}x{} -> {}x{}, size: {} bytes",
-             width, height, new_width, new_height, buffer.len());
-    Ok(format!("data:image/jpeg;base64,{}", base64_image))
+    let parsed = reqwest::Url::parse(trimmed)
+        .map_err(|e| format!("Invalid base URL '{}': {}", base_url, e))?;
+
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(format!("Invalid base URL '{}': must start with http:// or https://", base_url));
+    }
+
+    if parsed.host_str().map_or(true, |h| h.is_empty()) {
+        return Err(format!("Invalid base URL '{}': missing host", base_url));
+    }
+
+    if parsed.query().is_some() || parsed.fragment().is_some() {
+        return Err(format!("Invalid base URL '{}': query strings and fragments are not allowed", base_url));
+    }
+
+    Ok(trimmed.to_string())
 }
 
-// 新的分析函数，支持自定义prompt
-async fn analyze_image_with_prompt(
-    image_data: String,
-    state: State<'_, AppState>,
-    custom_prompt: Option<String>,
-    app_handle: Option<tauri::AppHandle>,
-) -> Result<String, String> {
-    // 使用活跃profile的配置
-    let active_profile = state.get_active_profile().await?;
+// 调试模式下返回原始错误，否则脱敏
+fn user_facing_error(error: &str, debug_mode: bool) -> String {
+    if debug_mode {
+        error.to_string()
+    } else {
+        sanitize_error(error)
+    }
+}
+
+// Sanitize error messages to avoid information leakage
+fn sanitize_error(error: &str) -> String {
+    // 拒绝语中可能带有数字，先于状态码判断
+    if error.contains("no usable content") {
+        "Model returned no usable content".to_string()
+    } else if error.contains("Connection refused") || error.contains("timeout") {
+        "Network connection failed".to_string()
+    } else if error.contains("401") || error.contains("403") {
+        "Authentication failed".to_string()
+    } else if error.contains("404") {
+        "Service not found".to_string()
+    } else if error.contains("500") || error.contains("502") || error.contains("503") {
+        "Server error".to_string()
+    } else {
+        "Request failed".to_string()
+    }
+}
+
+
+
+#[tauri::command]
+async fn get_config(state: State<'_, AppState>) -> Result<Config, String> {
     let config = state.config.lock().await;
-    let sound_enabled = config.sound_enabled;
-    drop(config);
+    Ok(config.clone())
+}
 
-    // 验证API配置
-    if active_profile.api_config.api_key.is_empty() || active_profile.api_config.base_url.is_empty() {
-        // Show system dialog for missing API config (only for hotkey usage)
-        if let Some(ref _handle) = app_handle {
-            if sound_enabled {
-                // Play error sound
-                if let Err(sound_err) = play_error_sound().await {
-                    println!("Failed to play error sound: {}", sound_err);
-                }
+// 简化的Tauri命令 - 保持前端兼容
+
+#[tauri::command]
+async fn create_profile(state: State<'_, AppState>, profile: serde_json::Value) -> Result<String, String> {
+    debug!("🔧 Creating profile from frontend data...");
+    
+    // 从前端数据中提取profile名称
+    let name = profile.get("name")
+        .and_then(|v| v.as_str())
+        .ok_or("Profile name is required")?;
+    
+    // 使用简化的内部方法
+    let profile_id = state.create_new_profile(name.to_string()).await?;
+    debug!("✅ Profile created successfully: {} ({})", name, profile_id);
+    Ok(profile_id)
+}
+
+#[tauri::command]
+async fn update_profile_config(app_handle: tauri::AppHandle, state: State<'_, AppState>, profile_data: serde_json::Value) -> Result<(), String> {
+    debug!("🔧 Updating profile config (legacy compatibility)...");
+    
+    // 直接使用新的update_active_profile逻辑
+    update_active_profile(app_handle, state, profile_data).await
+}
+
+#[tauri::command]
+async fn update_active_profile(app_handle: tauri::AppHandle, state: State<'_, AppState>, update_data: serde_json::Value) -> Result<(), String> {
+    debug!("🔧 Updating active profile configuration...");
+    
+    let mut updates = ProfileConfigUpdate::default();
+    
+    // 解析更新字段
+    if let Some(name) = update_data.get("profileName").and_then(|v| v.as_str()) {
+        if !name.trim().is_empty() {
+            updates.name = Some(name.trim().to_string());
+        }
+    }
+    
+    if let Some(base_url) = update_data.get("apiBaseUrl").and_then(|v| v.as_str()) {
+        updates.base_url = Some(base_url.to_string());
+    }
+    
+    if let Some(api_key) = update_data.get("apiKey").and_then(|v| v.as_str()) {
+        updates.api_key = Some(api_key.to_string());
+    }
+    
+    if let Some(model) = update_data.get("model").and_then(|v| v.as_str()) {
+        updates.model = Some(model.to_string());
+    }
+
+    if let Some(urls) = update_data.get("fallbackBaseUrls").and_then(|v| v.as_array()) {
+        updates.fallback_base_urls = Some(
+            urls.iter()
+                .filter_map(|u| u.as_str())
+                .map(|u| u.trim().to_string())
+                .filter(|u| !u.is_empty())
+                .collect()
+        );
+    }
+
+    if let Some(models_url) = update_data.get("modelsUrl").and_then(|v| v.as_str()) {
+        let models_url = models_url.trim();
+        updates.models_url = Some(if models_url.is_empty() { None } else { Some(models_url.to_string()) });
+    }
+
+    if let Some(danger_accept_invalid_certs) = update_data.get("dangerAcceptInvalidCerts").and_then(|v| v.as_bool()) {
+        updates.danger_accept_invalid_certs = Some(danger_accept_invalid_certs);
+    }
+
+    if let Some(bypass_proxy) = update_data.get("bypassProxy").and_then(|v| v.as_bool()) {
+        updates.bypass_proxy = Some(bypass_proxy);
+    }
+    
+    // 解析prompt模式
+    if let Some(prompt_mode) = update_data.get("promptMode").and_then(|v| v.as_str()) {
+        match prompt_mode {
+            "user_input" => {
+                updates.prompt_mode = Some(PromptMode::UserInput);
+            }
+            "template" => {
+                let template = update_data.get("prompt")
+                    .and_then(|v| v.as_str())
+                    .ok_or("Template prompt mode requires a prompt")?;
+                updates.prompt_mode = Some(PromptMode::Template(template.to_string()));
+            }
+            "predefined" | _ => {
+                let prompt_text = update_data.get("prompt")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("识别公式和文字，返回使用pandoc语法的markdown排版内容。公式请用katex语法包裹，文字内容不要丢失。只返回内容不需要其他解释。");
+                updates.prompt_mode = Some(PromptMode::Predefined(prompt_text.to_string()));
+            }
+        }
+    }
+    
+    // 解析输出模式  
+    if let Some(output_mode) = update_data.get("outputMode").and_then(|v| v.as_str()) {
+        match output_mode {
+            "dialog" => {
+                updates.output_mode = Some(OutputMode::Dialog);
             }
+            "type" => {
+                updates.output_mode = Some(OutputMode::Type);
+            }
+            "action_dialog" => {
+                updates.output_mode = Some(OutputMode::ActionDialog);
+            }
+            "clipboard_append" => {
+                updates.output_mode = Some(OutputMode::ClipboardAppend);
+            }
+            "clipboard_image_text" => {
+                updates.output_mode = Some(OutputMode::ClipboardImageText);
+            }
+            "clipboard_toast" => {
+                updates.output_mode = Some(OutputMode::ClipboardToast);
+            }
+            "file" => {
+                updates.output_mode = Some(OutputMode::File);
+            }
+            "webhook" => {
+                let url = update_data.get("webhookUrl")
+                    .and_then(|v| v.as_str())
+                    .map(|u| u.trim().to_string())
+                    .ok_or("Webhook output mode requires a webhookUrl")?;
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err("Webhook URL must start with http:// or https://".to_string());
+                }
 
-            // Show macOS system dialog
-            if let Err(dialog_err) = show_system_dialog(
-                "MathImage Error".to_string(),
-                format!("Profile '{}': API key and base URL are required. Please configure them in Settings.", active_profile.name),
-                "error".to_string()
-            ).await {
-                println!("Failed to show system dialog: {}", dialog_err);
+                let headers = update_data.get("webhookHeaders")
+                    .and_then(|v| v.as_object())
+                    .map(|headers| {
+                        headers.iter()
+                            .filter_map(|(name, value)| value.as_str().map(|v| (name.clone(), v.to_string())))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                let fallback_to_clipboard = update_data.get("webhookFallbackToClipboard")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or_else(default_webhook_fallback);
+
+                updates.output_mode = Some(OutputMode::Webhook { url, headers, fallback_to_clipboard });
+            }
+            "clipboard" | _ => {
+                updates.output_mode = Some(OutputMode::Clipboard);
             }
         }
-        return Err(format!("Profile '{}': API key and base URL are required", active_profile.name));
+    }
+    
+    // 解析prompt预设: [{ "name": ..., "text": ... }]
+    if let Some(presets) = update_data.get("promptPresets").and_then(|v| v.as_array()) {
+        updates.prompt_presets = Some(
+            presets.iter()
+                .filter_map(|p| {
+                    let name = p.get("name").and_then(|v| v.as_str())?.trim();
+                    let text = p.get("text").and_then(|v| v.as_str())?;
+                    if name.is_empty() { None } else { Some((name.to_string(), text.to_string())) }
+                })
+                .collect()
+        );
     }
 
-    if active_profile.api_config.model.is_empty() {
-        // Show system dialog for missing model (only for hotkey usage)
-        if let Some(ref _handle) = app_handle {
-            if sound_enabled {
-                // Play error sound
-                if let Err(sound_err) = play_error_sound().await {
-                    println!("Failed to play error sound: {}", sound_err);
+    if let Some(active_preset) = update_data.get("activePreset") {
+        updates.active_preset = Some(active_preset.as_u64().map(|i| i as usize));
+    }
+
+    // 解析图像预处理选项
+    if let Some(preprocess) = update_data.get("preprocess") {
+        updates.preprocess = Some(PreprocessOptions {
+            grayscale: preprocess.get("grayscale").and_then(|v| v.as_bool()).unwrap_or(false),
+            contrast: preprocess.get("contrast").and_then(|v| v.as_f64()).unwrap_or(0.0) as f32,
+        });
+    }
+
+    if let Some(capture_padding) = update_data.get("capturePadding").and_then(|v| v.as_u64()) {
+        updates.capture_padding = Some(capture_padding.min(u32::MAX as u64) as u32);
+    }
+
+    if let Some(latex_delimiters) = update_data.get("latexDelimiters").and_then(|v| v.as_str()) {
+        updates.latex_delimiters = Some(match latex_delimiters {
+            "dollar" => DelimiterStyle::Dollar,
+            "paren" => DelimiterStyle::Paren,
+            "katex" => DelimiterStyle::Katex,
+            "keep" => DelimiterStyle::Keep,
+            other => return Err(format!("Unknown LaTeX delimiter style: {}", other)),
+        });
+    }
+
+    if let Some(strip_code_fences) = update_data.get("stripCodeFences").and_then(|v| v.as_bool()) {
+        updates.strip_code_fences = Some(strip_code_fences);
+    }
+
+    if let Some(extract_latex_only) = update_data.get("extractLatexOnly").and_then(|v| v.as_bool()) {
+        updates.extract_latex_only = Some(extract_latex_only);
+    }
+
+    if let Some(requests_per_minute) = update_data.get("requestsPerMinute").and_then(|v| v.as_u64()) {
+        updates.requests_per_minute = Some(requests_per_minute.min(u32::MAX as u64) as u32);
+    }
+
+    if let Some(include_reasoning) = update_data.get("includeReasoning").and_then(|v| v.as_bool()) {
+        updates.include_reasoning = Some(include_reasoning);
+    }
+
+    // 空字符串表示清除system prompt
+    if let Some(system_prompt) = update_data.get("systemPrompt").and_then(|v| v.as_str()) {
+        let system_prompt = system_prompt.trim();
+        updates.system_prompt = Some((!system_prompt.is_empty()).then(|| system_prompt.to_string()));
+    }
+
+    if let Some(tile_large_images) = update_data.get("tileLargeImages").and_then(|v| v.as_bool()) {
+        updates.tile_large_images = Some(tile_large_images);
+    }
+
+    if let Some(tile_threshold_px) = update_data.get("tileThresholdPx").and_then(|v| v.as_u64()) {
+        updates.tile_threshold_px = Some(tile_threshold_px.clamp(MIN_TILE_THRESHOLD_PX as u64, u32::MAX as u64) as u32);
+    }
+
+    if let Some(request_log) = update_data.get("requestLog").and_then(|v| v.as_bool()) {
+        updates.request_log = Some(request_log);
+    }
+
+    // 空字符串表示不使用备用Profile
+    if let Some(fallback_profile_id) = update_data.get("fallbackProfileId").and_then(|v| v.as_str()) {
+        let fallback_profile_id = fallback_profile_id.trim();
+        updates.fallback_profile_id = Some((!fallback_profile_id.is_empty()).then(|| fallback_profile_id.to_string()));
+    }
+
+    if let Some(local_ocr) = update_data.get("localOcr").and_then(|v| v.as_bool()) {
+        updates.local_ocr = Some(local_ocr);
+    }
+
+    if let Some(local_ocr_language) = update_data.get("localOcrLanguage").and_then(|v| v.as_str()) {
+        let local_ocr_language = local_ocr_language.trim();
+        updates.local_ocr_language = Some(if local_ocr_language.is_empty() {
+            default_local_ocr_language()
+        } else {
+            local_ocr_language.to_string()
+        });
+    }
+
+    if let Some(post_processing) = update_data.get("postProcessing") {
+        let steps: Vec<PostStep> = serde_json::from_value(post_processing.clone())
+            .map_err(|e| format!("Invalid post-processing steps: {}", e))?;
+        updates.post_processing = Some(steps);
+    }
+
+    if let Some(few_shot) = update_data.get("fewShot") {
+        let examples: Vec<(String, String)> = serde_json::from_value(few_shot.clone())
+            .map_err(|e| format!("Invalid few-shot examples (expected [input, output] pairs): {}", e))?;
+        updates.few_shot = Some(examples.into_iter()
+            .filter(|(input, output)| !input.trim().is_empty() || !output.trim().is_empty())
+            .collect());
+    }
+
+    if let Some(few_shot_inline) = update_data.get("fewShotInline").and_then(|v| v.as_bool()) {
+        updates.few_shot_inline = Some(few_shot_inline);
+    }
+
+    if let Some(image_detail) = update_data.get("imageDetail").and_then(|v| v.as_str()) {
+        updates.image_detail = Some(match image_detail {
+            "auto" => ImageDetail::Auto,
+            "low" => ImageDetail::Low,
+            "high" => ImageDetail::High,
+            other => return Err(format!("Unknown image detail level: {}", other)),
+        });
+    }
+
+    if let Some(overrides) = update_data.get("modelPromptOverrides").and_then(|v| v.as_array()) {
+        updates.model_prompt_overrides = Some(
+            overrides.iter()
+                .filter_map(|o| {
+                    let pattern = o.get("pattern").and_then(|v| v.as_str())?.trim();
+                    let prompt = o.get("prompt").and_then(|v| v.as_str())?;
+                    if pattern.is_empty() { None } else { Some((pattern.to_string(), prompt.to_string())) }
+                })
+                .collect()
+        );
+    }
+
+    let parse_patterns = |key: &str| update_data.get(key).and_then(|v| v.as_array()).map(|patterns| {
+        patterns.iter()
+            .filter_map(|p| p.as_str())
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<String>>()
+    });
+    updates.model_allowlist = parse_patterns("modelAllowlist");
+    updates.model_denylist = parse_patterns("modelDenylist");
+    updates.model_owners = parse_patterns("modelOwners");
+
+    if let Some(output_dir) = update_data.get("outputDir").and_then(|v| v.as_str()) {
+        updates.output_dir = Some(PathBuf::from(output_dir.trim()));
+    }
+
+    if let Some(template) = update_data.get("filenameTemplate").and_then(|v| v.as_str()) {
+        let template = template.trim();
+        updates.filename_template = Some(if template.is_empty() { default_filename_template() } else { template.to_string() });
+    }
+
+    if let Some(capture_mode) = update_data.get("captureMode").and_then(|v| v.as_str()) {
+        updates.capture_mode = Some(match capture_mode {
+            "window" => CaptureMode::Window,
+            "region" => CaptureMode::Region,
+            "overlay" => CaptureMode::Overlay,
+            other => return Err(format!("Unknown capture mode: {}", other)),
+        });
+    }
+
+    let presets_changed = updates.prompt_presets.is_some() || updates.active_preset.is_some();
+    let model_lists_changed = updates.model_allowlist.is_some() || updates.model_denylist.is_some();
+    let model_owners_changed = updates.model_owners.is_some();
+
+    state.update_active_profile_config(updates).await?;
+
+    if presets_changed {
+        if let Err(e) = rebuild_preset_submenu(&app_handle).await {
+            warn!("⚠️ Failed to rebuild prompt preset submenu: {}", e);
+        }
+    }
+
+    if model_lists_changed {
+        if let Err(e) = rebuild_model_submenu(&app_handle).await {
+            warn!("⚠️ Failed to rebuild model submenu: {}", e);
+        }
+    }
+
+    // 提供方过滤在加载模型时生效，修改后在后台重新加载
+    if model_owners_changed {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = refresh_models_in_tray(app_handle).await {
+                warn!("⚠️ Failed to reload models after owner filter change: {}", e);
+            }
+        });
+    }
+    
+    // 同时更新全局设置（如果提供）
+    let sound_enabled = update_data.get("soundEnabled").and_then(|v| v.as_bool());
+    let debug_mode = update_data.get("debugMode").and_then(|v| v.as_bool());
+    let clipboard_append_separator = update_data.get("clipboardAppendSeparator").and_then(|v| v.as_str()).map(|s| s.to_string());
+    let hotkey_debounce_ms = update_data.get("hotkeyDebounceMs").and_then(|v| v.as_u64());
+    let success_sound_path = update_data.get("successSoundPath").and_then(|v| v.as_str()).map(|s| s.trim().to_string());
+    let error_sound_path = update_data.get("errorSoundPath").and_then(|v| v.as_str()).map(|s| s.trim().to_string());
+    let rate_limit_queue = update_data.get("rateLimitQueue").and_then(|v| v.as_bool());
+    let capture_timeout_secs = update_data.get("captureTimeoutSecs").and_then(|v| v.as_u64());
+    let mock_mode = update_data.get("mockMode").and_then(|v| v.as_bool());
+    // 空字符串 / 0 表示关闭
+    let save_captures_dir = update_data.get("saveCapturesDir").and_then(|v| v.as_str())
+        .map(|dir| if dir.trim().is_empty() { None } else { Some(PathBuf::from(dir.trim())) });
+    let save_captures_max_files = update_data.get("saveCapturesMaxFiles").and_then(|v| v.as_u64())
+        .map(|max| if max == 0 { None } else { Some(max as usize) });
+    let log_level = update_data.get("logLevel").and_then(|v| v.as_str()).map(|s| s.to_string());
+    let auto_refresh_models_on_switch = update_data.get("autoRefreshModelsOnSwitch").and_then(|v| v.as_bool());
+    let max_payload_bytes = update_data.get("maxPayloadBytes").and_then(|v| v.as_u64()).map(|v| v as usize);
+    let shrink_oversized_images = update_data.get("shrinkOversizedImages").and_then(|v| v.as_bool());
+    let stream_to_clipboard = update_data.get("streamToClipboard").and_then(|v| v.as_bool());
+    let notify_on_success = update_data.get("notifyOnSuccess").and_then(|v| v.as_bool());
+    let preview_before_send = update_data.get("previewBeforeSend").and_then(|v| v.as_bool());
+    let analysis_cache_size = update_data.get("analysisCacheSize").and_then(|v| v.as_u64()).map(|v| v as usize);
+    let analysis_cache_ttl_secs = update_data.get("analysisCacheTtlSecs").and_then(|v| v.as_u64());
+    let analysis_cache_exact_bytes = update_data.get("analysisCacheExactBytes").and_then(|v| v.as_bool());
+    let refusal_patterns = update_data.get("refusalPatterns").and_then(|v| v.as_array()).map(|patterns| {
+        patterns.iter()
+            .filter_map(|p| p.as_str())
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<String>>()
+    });
+    let retry_on_empty = update_data.get("retryOnEmpty").and_then(|v| v.as_bool());
+    let empty_retry_max = update_data.get("emptyRetryMax").and_then(|v| v.as_u64()).map(|v| v.min(10) as u32);
+    let remote_server_enabled = update_data.get("remoteServerEnabled").and_then(|v| v.as_bool());
+    let remote_server_port = update_data.get("remoteServerPort").and_then(|v| v.as_u64())
+        .filter(|port| (1024..=u16::MAX as u64).contains(port))
+        .map(|port| port as u16);
+    // 空字符串表示重新生成token
+    let remote_server_token = update_data.get("remoteServerToken").and_then(|v| v.as_str()).map(|token| match token.trim() {
+        "" => generate_remote_server_token(),
+        token => token.to_string(),
+    });
+    let auto_select_first_model = update_data.get("autoSelectFirstModel").and_then(|v| v.as_bool());
+    if sound_enabled.is_some() || debug_mode.is_some() || clipboard_append_separator.is_some() || hotkey_debounce_ms.is_some()
+        || success_sound_path.is_some() || error_sound_path.is_some() || rate_limit_queue.is_some()
+        || capture_timeout_secs.is_some() || mock_mode.is_some() || save_captures_dir.is_some()
+        || save_captures_max_files.is_some() || log_level.is_some() || auto_refresh_models_on_switch.is_some()
+        || max_payload_bytes.is_some() || shrink_oversized_images.is_some() || stream_to_clipboard.is_some() || notify_on_success.is_some()
+        || preview_before_send.is_some() || analysis_cache_size.is_some() || analysis_cache_ttl_secs.is_some()
+        || analysis_cache_exact_bytes.is_some() || refusal_patterns.is_some() || retry_on_empty.is_some()
+        || empty_retry_max.is_some() || remote_server_enabled.is_some() || remote_server_port.is_some()
+        || remote_server_token.is_some() || auto_select_first_model.is_some()
+    {
+        let global_updates = ConfigUpdates {
+            sound_enabled,
+            debug_mode,
+            clipboard_append_separator,
+            hotkey_debounce_ms,
+            success_sound_path,
+            error_sound_path,
+            rate_limit_queue,
+            capture_timeout_secs,
+            mock_mode,
+            save_captures_dir,
+            save_captures_max_files,
+            log_level,
+            auto_refresh_models_on_switch,
+            max_payload_bytes,
+            shrink_oversized_images,
+            stream_to_clipboard,
+            notify_on_success,
+            preview_before_send,
+            analysis_cache_size,
+            analysis_cache_ttl_secs,
+            analysis_cache_exact_bytes,
+            refusal_patterns,
+            retry_on_empty,
+            empty_retry_max,
+            remote_server_enabled,
+            remote_server_port,
+            remote_server_token,
+            auto_select_first_model,
+            active_profile_id: None,
+            global_hotkey: None,
+            switch_profile_hotkey: None,
+        };
+        state.update_multiple_settings(global_updates).await?;
+    }
+    
+    debug!("✅ Active profile updated successfully");
+    Ok(())
+}
+
+#[tauri::command]
+async fn delete_profile(state: State<'_, AppState>, profile_id: String) -> Result<(), String> {
+    debug!("🔧 Deleting profile: {}", profile_id);
+    
+    state.update_and_save_config(|config| {
+        if config.profiles.len() <= 1 {
+            return Err("Cannot delete the last profile".to_string());
+        }
+
+        let profile_index = config.profiles.iter()
+            .position(|p| p.id == profile_id)
+            .ok_or("Profile not found")?;
+
+        let deleted_profile = config.profiles.remove(profile_index);
+
+        // 如果删除的是活跃profile，切换到第一个profile
+        if config.active_profile_id.as_ref() == Some(&profile_id) {
+            config.active_profile_id = config.profiles.first().map(|p| p.id.clone());
+            debug!("   📝 Switched active profile to: {:?}", config.active_profile_id);
+        }
+
+        // 其他Profile不再回退到已删除的Profile
+        for profile in config.profiles.iter_mut() {
+            if profile.fallback_profile_id.as_ref() == Some(&profile_id) {
+                profile.fallback_profile_id = None;
+            }
+        }
+
+        debug!("   📝 Deleted profile: {} ({})", deleted_profile.name, profile_id);
+        Ok(())
+    }).await?;
+
+    debug!("✅ Profile deleted successfully: {}", profile_id);
+    Ok(())
+}
+
+#[tauri::command]
+async fn export_profiles(
+    state: State<'_, AppState>,
+    path: String,
+    profile_ids: Option<Vec<String>>,
+    include_api_keys: Option<bool>,
+) -> Result<usize, String> {
+    debug!("🔧 Exporting profiles to: {}", path);
+
+    let include_api_keys = include_api_keys.unwrap_or(false);
+    let profiles: Vec<Profile> = {
+        let config = state.config.lock().await;
+        config.profiles.iter()
+            .filter(|p| profile_ids.as_ref().map_or(true, |ids| ids.contains(&p.id)))
+            .cloned()
+            .map(|mut p| {
+                if !include_api_keys {
+                    p.api_config.api_key = String::new();
+                }
+                p
+            })
+            .collect()
+    };
+
+    let json = serde_json::to_string_pretty(&profiles)
+        .map_err(|e| format!("Failed to serialize profiles: {}", e))?;
+
+    fs::write(&path, json)
+        .map_err(|e| format!("Failed to write export file: {}", e))?;
+
+    debug!("✅ Exported {} profiles (api keys included: {})", profiles.len(), include_api_keys);
+    Ok(profiles.len())
+}
+
+#[tauri::command]
+async fn import_profiles(state: State<'_, AppState>, path: String) -> Result<usize, String> {
+    debug!("🔧 Importing profiles from: {}", path);
+
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read import file: {}", e))?;
+
+    // 反序列化即校验Profile结构
+    let imported: Vec<Profile> = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid profile file: {}", e))?;
+
+    let mut added = 0;
+    state.update_and_save_config(|config| {
+        for mut profile in imported {
+            // 按名称去重
+            if config.profiles.iter().any(|p| p.name == profile.name) {
+                debug!("   ⏭️  Skipping profile with existing name: {}", profile.name);
+                continue;
+            }
+
+            // 重新生成ID避免冲突
+            profile.id = uuid::Uuid::new_v4().to_string();
+            // 使用统计属于本机，不随导入带过来
+            profile.use_count = 0;
+            profile.last_used = None;
+            debug!("   📝 Imported profile: {} ({})", profile.name, profile.id);
+            config.profiles.push(profile);
+            added += 1;
+        }
+        Ok(())
+    }).await?;
+
+    debug!("✅ Imported {} profiles", added);
+    Ok(added)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResetSummary {
+    // 数据目录中被删除的文件名
+    pub removed_files: Vec<String>,
+    pub keychain_entries_removed: usize,
+    pub analyses_cancelled: usize,
+}
+
+// 数据目录中属于本应用的文件：配置、模型缓存、保存时的临时文件和解析失败时的备份
+fn is_app_data_file(file_name: &str) -> bool {
+    matches!(file_name, "config.json" | "config.tmp" | "models.json")
+        || file_name.starts_with("config.json.bak-")
+}
+
+// 恢复出厂设置：删除配置、模型缓存、备份文件和请求日志，清除钥匙串中的API key，内存中的状态恢复为默认并重建托盘
+// 截图存档目录由用户指定，不在删除范围内
+#[tauri::command]
+async fn reset_all_data(app_handle: tauri::AppHandle, state: State<'_, AppState>) -> Result<ResetSummary, String> {
+    warn!("🧹 Resetting all data to factory defaults");
+
+    // 先取消进行中的分析，避免旧任务在重置后写回状态
+    let analyses_cancelled = {
+        let mut tasks = state.analysis_tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let running = tasks.iter().filter(|task| !task.inner().is_finished()).count();
+        for task in tasks.drain(..) {
+            task.abort();
+        }
+        running
+    };
+
+    // 替换内存中的配置；之后的保存只会写入默认配置
+    let defaults = Config::default();
+    let old_profile_ids: Vec<String> = {
+        let mut config = state.config.lock().await;
+        let ids = config.profiles.iter().map(|p| p.id.clone()).collect();
+        *config = defaults.clone();
+        ids
+    };
+
+    state.loaded_models.lock().await.clear();
+    state.endpoint_health.lock().await.clear();
+    state.rate_limiters.lock().await.clear();
+    state.analysis_cache.lock().await.clear();
+    *state.last_capture.lock().await = None;
+    *state.rerun_profile_id.lock().await = None;
+    *state.last_trigger.lock().await = None;
+    *state.last_status.lock().await = None;
+
+    let mut keychain_entries_removed = 0;
+    for profile_id in &old_profile_ids {
+        KEYCHAIN_SYNCED_KEYS.lock().unwrap_or_else(|e| e.into_inner()).remove(profile_id);
+        match AppState::keychain_entry(profile_id).and_then(|entry| entry.delete_password()) {
+            Ok(()) => keychain_entries_removed += 1,
+            Err(keyring::Error::NoEntry) => {}
+            Err(e) => warn!("⚠️ Failed to delete keychain entry for profile {}: {}", profile_id, e),
+        }
+    }
+
+    let mut removed_files = Vec::new();
+    {
+        // 与save_config_atomic互斥，避免正在进行的保存在删除后重新写回
+        let _save_guard = CONFIG_SAVE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let config_dir = AppState::get_config_dir()?;
+        let entries = fs::read_dir(&config_dir)
+            .map_err(|e| format!("Failed to read config directory: {}", e))?;
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !is_app_data_file(&file_name) {
+                continue;
+            }
+            match fs::remove_file(entry.path()) {
+                Ok(()) => removed_files.push(file_name),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to remove {}: {}", file_name, e)),
+            }
+        }
+
+        // 请求日志可能包含截图内容
+        match fs::remove_dir_all(config_dir.join(REQUEST_LOG_DIR)) {
+            Ok(()) => removed_files.push(format!("{}/", REQUEST_LOG_DIR)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to remove request logs: {}", e)),
+        }
+    }
+
+    apply_log_level(&defaults.log_level);
+
+    if let Err(e) = register_hotkeys_internal(
+        app_handle.clone(),
+        defaults.global_hotkey.clone(),
+        defaults.switch_profile_hotkey.clone(),
+        defaults.switch_previous_profile_hotkey.clone(),
+        defaults.rerun_last_hotkey.clone(),
+    ).await {
+        warn!("⚠️ Failed to register default hotkeys after reset: {}", e);
+    }
+
+    // 重建托盘菜单，使其与默认配置一致
+    let active_profile = state.get_active_profile().await?;
+    rebuild_profile_submenu(&app_handle).await?;
+    update_profile_submenu_title(&app_handle, &active_profile.name).await?;
+    refresh_profile_menus(&app_handle).await?;
+    update_hotkey_menu_text(&app_handle, &defaults.global_hotkey, &defaults.switch_profile_hotkey).await?;
+    update_sound_menu_text(&app_handle, defaults.sound_enabled).await?;
+    update_hotkeys_enabled_menu_text(&app_handle, defaults.hotkeys_enabled).await?;
+    update_last_status_item(&app_handle);
+
+    info!("✅ Reset complete: removed {:?}, {} keychain entries, {} analyses cancelled",
+        removed_files, keychain_entries_removed, analyses_cancelled);
+    Ok(ResetSummary {
+        removed_files,
+        keychain_entries_removed,
+        analyses_cancelled,
+    })
+}
+
+// 按给定的ID顺序重排profiles（决定切换热键的循环顺序）
+#[tauri::command]
+async fn reorder_profiles(app_handle: tauri::AppHandle, state: State<'_, AppState>, ids: Vec<String>) -> Result<(), String> {
+    debug!("🔧 Reordering profiles: {:?}", ids);
+
+    state.update_and_save_config(|config| {
+        // 必须是现有profile ID的一个排列
+        let mut sorted_ids = ids.clone();
+        sorted_ids.sort();
+        sorted_ids.dedup();
+        let mut existing_ids: Vec<String> = config.profiles.iter().map(|p| p.id.clone()).collect();
+        existing_ids.sort();
+        if sorted_ids.len() != ids.len() || sorted_ids != existing_ids {
+            return Err("Profile order must contain every existing profile id exactly once".to_string());
+        }
+
+        let mut reordered = Vec::with_capacity(config.profiles.len());
+        for id in &ids {
+            let index = config.profiles.iter().position(|p| &p.id == id)
+                .ok_or_else(|| format!("Profile with id '{}' not found", id))?;
+            reordered.push(config.profiles.remove(index));
+        }
+        config.profiles = reordered;
+
+        debug!("   📝 Reordered {} profiles", config.profiles.len());
+        Ok(())
+    }).await?;
+
+    rebuild_profile_submenu(&app_handle).await
+}
+
+// 重命名任意Profile（不限于活跃Profile），名称需唯一
+#[tauri::command]
+async fn rename_profile(app_handle: tauri::AppHandle, state: State<'_, AppState>, profile_id: String, new_name: String) -> Result<(), String> {
+    debug!("🔧 Renaming profile {} to '{}'", profile_id, new_name);
+
+    let new_name = new_name.trim().to_string();
+    if new_name.is_empty() {
+        return Err("Profile name cannot be empty".to_string());
+    }
+
+    let mut is_active = false;
+    state.update_and_save_config(|config| {
+        if config.profiles.iter().any(|p| p.id != profile_id && p.name == new_name) {
+            return Err(format!("A profile named '{}' already exists", new_name));
+        }
+
+        let profile = config.profiles.iter_mut()
+            .find(|p| p.id == profile_id)
+            .ok_or("Profile not found")?;
+        profile.name = new_name.clone();
+
+        is_active = config.active_profile_id.as_ref() == Some(&profile_id);
+        Ok(())
+    }).await?;
+
+    if let Some(item) = state.profile_check_items.lock().await.get(&profile_id) {
+        if let Err(e) = item.set_text(&new_name) {
+            error!("   ❌ Failed to update profile menu item label: {}", e);
+        }
+    }
+
+    if is_active {
+        update_profile_submenu_title(&app_handle, &new_name).await?;
+    }
+
+    debug!("✅ Profile renamed successfully: {}", new_name);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_active_profile(app_handle: tauri::AppHandle, state: State<'_, AppState>, profile_id: String) -> Result<(), String> {
+    state.set_active_profile(profile_id.clone()).await?;
+    
+    // Get the new active profile for submenu title update
+    let active_profile = state.get_active_profile().await?;
+    
+    // Update profile submenu title
+    debug!("🔧 Updating profile submenu title from Settings page...");
+    update_profile_submenu_title(&app_handle, &active_profile.name).await?;
+
+    // 新Profile的prompt预设
+    refresh_profile_menus(&app_handle).await?;
+    schedule_model_refresh(&app_handle).await;
+    
+    Ok(())
+}
+
+#[tauri::command]
+async fn update_config(state: State<'_, AppState>, new_config: Config) -> Result<(), String> {
+    debug!("🔧 Updating entire configuration...");
+    
+    // 先原子性保存到文件
+    AppState::save_config_atomic(&new_config).await?;
+    
+    // 然后更新内存中的配置
+    let mut config = state.config.lock().await;
+    *config = new_config;
+    
+    debug!("✅ Configuration updated successfully");
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_models(
+    base_url: String,
+    api_key: String,
+    models_url: Option<String>,
+    owned_by: Option<Vec<String>>,
+    state: State<'_, AppState>,
+) -> Result<Vec<ModelInfo>, String> {
+    if api_key.is_empty() || base_url.is_empty() {
+        return Err("API key and base URL are required".to_string());
+    }
+
+    // 未显式传入时，若base_url属于活跃Profile则沿用它的models_url和提供方过滤
+    let active_profile = state.get_active_profile().await.ok()
+        .filter(|p| p.api_config.base_url == base_url);
+    let models_url = models_url
+        .or_else(|| active_profile.as_ref().and_then(|p| p.api_config.models_url.clone()));
+    let owners = owned_by
+        .or_else(|| active_profile.as_ref().map(|p| p.model_owners.clone()))
+        .unwrap_or_default();
+    let url = resolve_models_url(&base_url, models_url.as_deref());
+    let debug_mode = state.config.lock().await.debug_mode;
+    let client = match &active_profile {
+        Some(profile) => state.http_client_for(&profile.api_config).await?,
+        None => state.http_client.clone(),
+    };
+
+    let response = client
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .send()
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch models from {}: {}", url, e);
+            user_facing_error(&e.to_string(), debug_mode)
+        })?;
+
+    if !response.status().is_success() {
+        let error = format!("Status: {} {}", response.status(), url);
+        error!("Failed to fetch models: {}", error);
+        return Err(user_facing_error(&error, debug_mode));
+    }
+
+    let response_text = response.text().await
+        .map_err(|_| "Failed to read response".to_string())?;
+
+    let json: serde_json::Value = serde_json::from_str(&response_text)
+        .map_err(|_| "Invalid response format".to_string())?;
+
+    let models = parse_models_response(&json).ok_or_else(|| "Invalid response format".to_string())?;
+    Ok(models.into_iter()
+        .filter(|model| owner_allowed(model.owned_by.as_deref(), &owners))
+        .collect())
+}
+
+// 解析 `/models` 响应中的 `data` 数组
+fn parse_models_response(json: &serde_json::Value) -> Option<Vec<ModelInfo>> {
+    let data = json.get("data").and_then(|d| d.as_array())?;
+    Some(data.iter()
+        .filter_map(|model| {
+            if let (Some(id), Some(object)) = (
+                model.get("id").and_then(|i| i.as_str()),
+                model.get("object").and_then(|o| o.as_str())
+            ) {
+                let capabilities = parse_model_capabilities(model);
+                Some(ModelInfo {
+                    id: id.to_string(),
+                    object: object.to_string(),
+                    owned_by: model.get("owned_by").and_then(|o| o.as_str()).map(|o| o.to_string()),
+                    context_length: parse_model_context_length(model),
+                    supports_vision: model_supports_vision(model, capabilities.as_deref()),
+                    capabilities,
+                })
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
+// 上下文长度：OpenRouter等返回context_length，也有服务商用context_window或放在top_provider中
+fn parse_model_context_length(model: &serde_json::Value) -> Option<u64> {
+    ["context_length", "context_window", "max_context_length"].iter()
+        .find_map(|key| model.get(*key).and_then(|v| v.as_u64()))
+        .or_else(|| model.pointer("/top_provider/context_length").and_then(|v| v.as_u64()))
+}
+
+// capabilities可能是字符串数组（["vision", "tools"]），也可能是布尔值对象（{"vision": true}）
+fn parse_model_capabilities(model: &serde_json::Value) -> Option<Vec<String>> {
+    match model.get("capabilities")? {
+        serde_json::Value::Array(items) => Some(items.iter()
+            .filter_map(|item| item.as_str())
+            .map(|item| item.to_string())
+            .collect()),
+        serde_json::Value::Object(flags) => Some(flags.iter()
+            .filter(|(_, enabled)| enabled.as_bool() == Some(true))
+            .map(|(name, _)| name.clone())
+            .collect()),
+        _ => None,
+    }
+}
+
+// 优先看capabilities，其次看architecture.input_modalities（OpenRouter）；都没有时无法判断
+fn model_supports_vision(model: &serde_json::Value, capabilities: Option<&[String]>) -> Option<bool> {
+    let is_image_capability = |name: &str| matches!(name.to_lowercase().as_str(), "vision" | "image" | "images" | "image_input");
+    if let Some(capabilities) = capabilities {
+        return Some(capabilities.iter().any(|c| is_image_capability(c)));
+    }
+    model.pointer("/architecture/input_modalities")
+        .and_then(|m| m.as_array())
+        .map(|modalities| modalities.iter().any(|m| m.as_str() == Some("image")))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionTestResult {
+    pub reachable: bool,
+    pub auth_ok: bool,
+    pub model_count: usize,
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+// 测试活跃Profile的连接 - 返回未脱敏的详细诊断信息
+#[tauri::command]
+async fn test_connection(state: State<'_, AppState>) -> Result<ConnectionTestResult, String> {
+    let active_profile = state.get_active_profile().await?;
+    let base_url = active_profile.api_config.base_url.clone();
+    let api_key = active_profile.api_config.usable_api_key().to_string();
+
+    if base_url.is_empty() {
+        return Err(format!("Profile '{}': base URL is not configured", active_profile.name));
+    }
+
+    let url = active_profile.api_config.models_endpoint();
+    debug!("🔧 Testing connection for profile '{}': {}", active_profile.name, url);
+
+    let mut request = state.http_client_for(&active_profile.api_config).await?
+        .get(&url)
+        .timeout(std::time::Duration::from_secs(15));
+    if !api_key.is_empty() {
+        request = request.header("Authorization", format!("Bearer {}", api_key));
+    }
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            return Ok(ConnectionTestResult {
+                reachable: false,
+                auth_ok: false,
+                model_count: 0,
+                status: None,
+                message: format!("Could not reach {}: {}", url, e),
+            });
+        }
+    };
+
+    let status = response.status();
+    let auth_ok = status != reqwest::StatusCode::UNAUTHORIZED && status != reqwest::StatusCode::FORBIDDEN;
+
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Ok(ConnectionTestResult {
+            reachable: true,
+            auth_ok,
+            model_count: 0,
+            status: Some(status.as_u16()),
+            message: format!("{} returned {}: {}", url, status, body.chars().take(200).collect::<String>()),
+        });
+    }
+
+    let models = response.json::<serde_json::Value>().await
+        .ok()
+        .and_then(|json| parse_models_response(&json));
+
+    let result = match models {
+        Some(models) => ConnectionTestResult {
+            reachable: true,
+            auth_ok: true,
+            model_count: models.len(),
+            status: Some(status.as_u16()),
+            message: format!("Connected, {} models available", models.len()),
+        },
+        None => ConnectionTestResult {
+            reachable: true,
+            auth_ok: true,
+            model_count: 0,
+            status: Some(status.as_u16()),
+            message: format!("{} responded but the model list could not be parsed", url),
+        },
+    };
+
+    debug!("✅ Connection test finished: {}", result.message);
+    Ok(result)
+}
+
+
+
+#[tauri::command]
+async fn take_interactive_screenshot(state: State<'_, AppState>) -> Result<String, String> {
+    let timeout = std::time::Duration::from_secs(state.config.lock().await.capture_timeout_secs);
+    let image_data = take_interactive_region_screenshot(timeout).await?;
+    archive_capture(&state, &image_data).await;
+    Ok(image_data)
+}
+
+async fn take_interactive_region_screenshot(timeout: std::time::Duration) -> Result<String, String> {
+    // -i: interactive selection, -r: do not add drop shadow
+    run_screencapture(&["-i", "-r"], timeout).await
+}
+
+// 点击选择窗口并截取整个窗口（-w 只允许窗口选择，-o 去掉窗口阴影）
+#[cfg(target_os = "macos")]
+async fn take_interactive_window_screenshot(timeout: std::time::Duration) -> Result<String, String> {
+    run_screencapture(&["-i", "-w", "-o"], timeout).await
+}
+
+// 其他平台没有screencapture的窗口选择，截取最前面的（非本应用、未最小化的）窗口
+#[cfg(not(target_os = "macos"))]
+async fn take_interactive_window_screenshot(_timeout: std::time::Duration) -> Result<String, String> {
+    tokio::task::spawn_blocking(capture_frontmost_window)
+        .await
+        .map_err(|e| format!("Window capture task failed: {}", e))?
+}
+
+#[cfg(not(target_os = "macos"))]
+fn capture_frontmost_window() -> Result<String, String> {
+    // xcap按z序返回窗口，最上层在前
+    let window = xcap::Window::all()
+        .map_err(|e| format!("Failed to list windows: {}", e))?
+        .into_iter()
+        .find(|w| !w.is_minimized() && !w.title().starts_with("MathImage") && w.width() > 0 && w.height() > 0)
+        .ok_or("No window found to capture")?;
+    debug!("🪟 Capturing window: {} ({})", window.title(), window.app_name());
+
+    let captured = window.capture_image()
+        .map_err(|e| format!("Failed to capture window: {}", e))?;
+    let (width, height) = (captured.width(), captured.height());
+    let img = image::RgbaImage::from_raw(width, height, captured.into_raw())
+        .ok_or_else(|| "Failed to create image from RGBA data".to_string())?;
+
+    let mut buffer = Vec::new();
+    image::DynamicImage::ImageRgba8(img)
+        .write_to(&mut std::io::Cursor::new(&mut buffer), image::ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(image_bytes_to_data_url(&buffer))
+}
+
+#[tauri::command]
+async fn take_window_screenshot(state: State<'_, AppState>) -> Result<String, String> {
+    let timeout = std::time::Duration::from_secs(state.config.lock().await.capture_timeout_secs);
+    let image_data = take_interactive_window_screenshot(timeout).await?;
+    archive_capture(&state, &image_data).await;
+    Ok(image_data)
+}
+
+async fn run_screencapture(args: &[&str], timeout: std::time::Duration) -> Result<String, String> {
+    use std::fs;
+
+    // Create temp file path with timestamp for uniqueness
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let temp_path = format!("/tmp/mathimage_screenshot_{}.png", timestamp);
+
+    // Use macOS screencapture with interactive selection
+    // 显示器变化时screencapture可能卡住，超时后结束进程而不是一直阻塞热键
+    let mut child = tokio::process::Command::new("screencapture")
+        .args(args)
+        .arg(&temp_path)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to execute screencapture: {}", e))?;
+
+    let status = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => status.map_err(|e| format!("Failed to wait for screencapture: {}", e))?,
+        Err(_) => {
+            let _ = child.kill().await;
+            let _ = fs::remove_file(&temp_path);
+            return Err(format!("Screenshot timed out after {}s, screencapture was stopped", timeout.as_secs()));
+        }
+    };
+
+    if !status.success() {
+        return Err("Screenshot was cancelled".to_string()); // 用户取消，不显示对话框
+    }
+
+    // Check if file was created and has content
+    if !std::path::Path::new(&temp_path).exists() {
+        return Err("Screenshot was cancelled".to_string()); // 用户取消，不显示对话框
+    }
+
+    let metadata = fs::metadata(&temp_path)
+        .map_err(|_| "Screenshot was cancelled".to_string())?; // 用户取消，不显示对话框
+
+    if metadata.len() == 0 {
+        // Clean up empty file
+        let _ = fs::remove_file(&temp_path);
+        return Err("Screenshot was cancelled".to_string()); // 用户取消，不显示对话框
+    }
+
+    // Read the image file with size limit (10MB max)
+    const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+    if metadata.len() > MAX_FILE_SIZE {
+        let _ = fs::remove_file(&temp_path);
+        return Err("Screenshot file too large".to_string());
+    }
+
+    let image_data = fs::read(&temp_path)
+        .map_err(|e| format!("Failed to read screenshot file: {}", e))?;
+
+    // Clean up temp file
+    let _ = fs::remove_file(&temp_path);
+
+    info!("Interactive screenshot captured, size: {} bytes", image_data.len());
+
+    Ok(image_bytes_to_data_url(&image_data))
+}
+
+const CAPTURE_FILE_PREFIX: &str = "mathimage_capture_";
+
+// 把截图（data URL）写入存档目录，文件名带时间戳；超出数量上限时删除最旧的存档
+fn save_capture_to_dir(image_data: &str, dir: &std::path::Path, max_files: Option<usize>) -> Result<PathBuf, String> {
+    let (header, base64_part) = image_data.split_once(',')
+        .ok_or("Invalid image data URL")?;
+    let extension = if header.contains("image/jpeg") { "jpg" } else { "png" };
+    let bytes = general_purpose::STANDARD.decode(base64_part)
+        .map_err(|e| format!("Failed to decode image data: {}", e))?;
+
+    fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create capture directory {:?}: {}", dir, e))?;
+
+    let file_name = format!("{}{}.{}", CAPTURE_FILE_PREFIX, chrono::Local::now().format("%Y%m%d_%H%M%S_%3f"), extension);
+    let path = dir.join(file_name);
+    fs::write(&path, bytes)
+        .map_err(|e| format!("Failed to write capture {:?}: {}", path, e))?;
+
+    if let Some(max_files) = max_files {
+        // 文件名中的时间戳可以直接按字典序排序
+        let mut captures: Vec<PathBuf> = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read capture directory: {}", e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.file_name().and_then(|n| n.to_str()).map_or(false, |n| n.starts_with(CAPTURE_FILE_PREFIX)))
+            .collect();
+        captures.sort();
+        let excess = captures.len().saturating_sub(max_files);
+        for old in captures.iter().take(excess) {
+            if let Err(e) = fs::remove_file(old) {
+                warn!("⚠️ Failed to remove old capture {:?}: {}", old, e);
+            }
+        }
+    }
+
+    Ok(path)
+}
+
+// 如果配置了存档目录则保存截图，失败只记录日志不影响分析
+async fn archive_capture(state: &AppState, image_data: &str) {
+    let (dir, max_files) = {
+        let config = state.config.lock().await;
+        (config.save_captures_dir.clone(), config.save_captures_max_files)
+    };
+
+    if let Some(dir) = dir {
+        match save_capture_to_dir(image_data, &dir, max_files) {
+            Ok(path) => info!("Capture saved to {:?}", path),
+            Err(e) => warn!("⚠️ Failed to save capture: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScreenInfo {
+    pub index: usize,
+    pub id: u32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+    pub is_primary: bool,
+}
+
+#[tauri::command]
+async fn list_screens() -> Result<Vec<ScreenInfo>, String> {
+    let screens = Screen::all().map_err(|_| "Failed to access screen".to_string())?;
+
+    Ok(screens.iter()
+        .enumerate()
+        .map(|(index, screen)| {
+            let info = &screen.display_info;
+            ScreenInfo {
+                index,
+                id: info.id,
+                x: info.x,
+                y: info.y,
+                width: info.width,
+                height: info.height,
+                scale_factor: info.scale_factor,
+                is_primary: info.is_primary,
+            }
+        })
+        .collect())
+}
+
+// 按padding扩展区域 (x, y, w, h)，返回限制在屏幕内的 (left, top, right, bottom)
+fn pad_region(region: (u32, u32, u32, u32), padding: u32, screen_size: (u32, u32)) -> (u32, u32, u32, u32) {
+    let (x, y, w, h) = region;
+    let (screen_width, screen_height) = screen_size;
+
+    let left = x.saturating_sub(padding).min(screen_width);
+    let top = y.saturating_sub(padding).min(screen_height);
+    let right = x.saturating_add(w).saturating_add(padding).min(screen_width).max(left);
+    let bottom = y.saturating_add(h).saturating_add(padding).min(screen_height).max(top);
+
+    (left, top, right, bottom)
+}
+
+// 灰度 + 对比度增强，改善浅色手写体的识别效果
+fn preprocess_image(img: image::RgbaImage, options: &PreprocessOptions) -> image::RgbaImage {
+    if options.is_noop() {
+        return img;
+    }
+
+    let img = if options.grayscale {
+        image::DynamicImage::ImageLuma8(image::imageops::grayscale(&img)).to_rgba8()
+    } else {
+        img
+    };
+
+    if options.contrast != 0.0 {
+        image::imageops::contrast(&img, options.contrast)
+    } else {
+        img
+    }
+}
+
+// 根据文件头（magic number）判断图片格式，无法识别时返回None
+fn sniff_image_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.starts_with(b"BM") {
+        Some("image/bmp")
+    } else {
+        None
+    }
+}
+
+// 所有采集来源统一用这里构造data URL，保证MIME与实际字节一致
+fn image_bytes_to_data_url(bytes: &[u8]) -> String {
+    let mime_type = sniff_image_mime(bytes).unwrap_or_else(|| {
+        warn!("⚠️ Unrecognized image format ({} bytes), labeling as PNG", bytes.len());
+        "image/png"
+    });
+    format!("data:{};base64,{}", mime_type, general_purpose::STANDARD.encode(bytes))
+}
+
+// 前端或剪贴板传入的data URL前缀可能与内容不符（如JPEG标成PNG），按实际字节修正
+fn normalize_image_data_url(image_data: String) -> String {
+    let Some((prefix, base64_part)) = image_data.split_once(',') else {
+        return image_data;
+    };
+    // 只需要文件头：16个base64字符解码为12字节，足够识别所有支持的格式，不必解码整张图片
+    let head = base64_part.get(..16).unwrap_or(base64_part);
+    let Ok(bytes) = general_purpose::STANDARD.decode(head) else {
+        return image_data;
+    };
+    match sniff_image_mime(&bytes) {
+        Some(mime_type) if prefix != format!("data:{};base64", mime_type) => {
+            debug!("🔧 Fixing data URL prefix '{}' -> '{}'", prefix, mime_type);
+            format!("data:{};base64,{}", mime_type, base64_part)
+        }
+        _ => image_data,
+    }
+}
+
+// 对已编码的data URL应用预处理，重新编码为PNG
+fn preprocess_image_data_url(image_data: &str, options: &PreprocessOptions) -> Result<String, String> {
+    if options.is_noop() {
+        return Ok(image_data.to_string());
+    }
+
+    let base64_part = image_data.split_once(',').map(|(_, data)| data).unwrap_or(image_data);
+    let bytes = general_purpose::STANDARD.decode(base64_part)
+        .map_err(|e| format!("Failed to decode image data: {}", e))?;
+    let img = image::load_from_memory(&bytes)
+        .map_err(|e| format!("Failed to decode image: {}", e))?
+        .to_rgba8();
+
+    let processed = preprocess_image(img, options);
+
+    let mut buffer = Vec::new();
+    image::DynamicImage::ImageRgba8(processed)
+        .write_to(&mut std::io::Cursor::new(&mut buffer), image::ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(image_bytes_to_data_url(&buffer))
+}
+
+// 重新编码为JPEG并逐步缩小，直到data URL不超过max_bytes
+fn shrink_image_data_url(image_data: &str, max_bytes: usize) -> Result<String, String> {
+    const JPEG_QUALITY: u8 = 80;
+    const MAX_SHRINK_STEPS: usize = 8;
+    const MIN_DIMENSION: u32 = 64;
+
+    let base64_part = image_data.split_once(',').map(|(_, data)| data).unwrap_or(image_data);
+    let bytes = general_purpose::STANDARD.decode(base64_part)
+        .map_err(|e| format!("Failed to decode image data: {}", e))?;
+    // JPEG不支持透明通道
+    let mut img = image::DynamicImage::ImageRgb8(
+        image::load_from_memory(&bytes)
+            .map_err(|e| format!("Failed to decode image: {}", e))?
+            .to_rgb8()
+    );
+
+    for _ in 0..MAX_SHRINK_STEPS {
+        let mut buffer = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut buffer), image::ImageOutputFormat::Jpeg(JPEG_QUALITY))
+            .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+        let data_url = image_bytes_to_data_url(&buffer);
+        if data_url.len() <= max_bytes {
+            return Ok(data_url);
+        }
+
+        let (width, height) = (img.width(), img.height());
+        if width <= MIN_DIMENSION || height <= MIN_DIMENSION {
+            break;
+        }
+        img = img.resize(width * 3 / 4, height * 3 / 4, image::imageops::FilterType::Triangle);
+    }
+
+    Err("Image is too large to send even after recompressing. Please select a smaller region.".to_string())
+}
+
+// 截取屏幕区域（按Profile的capture_padding外扩），未指定区域时截取整个屏幕
+fn capture_region_image(
+    profile: &Profile,
+    x: Option<u32>,
+    y: Option<u32>,
+    width: Option<u32>,
+    height: Option<u32>,
+    screen_index: Option<usize>,
+) -> Result<image::RgbaImage, String> {
+    let screens = Screen::all().map_err(|_| "Failed to access screen".to_string())?;
+
+    if screens.is_empty() {
+        return Err("No screens found".to_string());
+    }
+
+    // Use primary screen unless a specific display was requested
+    let screen_index = screen_index.unwrap_or(0);
+    let screen = screens.get(screen_index).ok_or_else(|| format!(
+        "Screen index {} out of range ({} screens available, valid indices 0-{})",
+        screen_index, screens.len(), screens.len() - 1
+    ))?;
+
+    // Expand the selection by the profile's padding, clamped to the screen bounds
+    let (x, y, width, height) = match (x, y, width, height) {
+        (Some(x), Some(y), Some(w), Some(h)) if profile.capture_padding > 0 => {
+            let (left, top, right, bottom) = pad_region(
+                (x, y, w, h),
+                profile.capture_padding,
+                (screen.display_info.width, screen.display_info.height),
+            );
+            (Some(left), Some(top), Some(right - left), Some(bottom - top))
+        }
+        region => region,
+    };
+
+    // Validate region size to prevent memory issues
+    if let (Some(_), Some(_), Some(w), Some(h)) = (x, y, width, height) {
+        const MAX_DIMENSION: u32 = 4096; // 4K max
+        if w > MAX_DIMENSION || h > MAX_DIMENSION {
+            return Err("Screenshot region too large".to_string());
+        }
+
+        // Check memory requirement (4 bytes per pixel for RGBA)
+        const MAX_MEMORY: u64 = 64 * 1024 * 1024; // 64MB max
+        let memory_needed = (w as u64) * (h as u64) * 4;
+        if memory_needed > MAX_MEMORY {
+            return Err("Screenshot would require too much memory".to_string());
+        }
+    }
+
+    let image = if let (Some(x), Some(y), Some(w), Some(h)) = (x, y, width, height) {
+        // Capture specific region
+        screen.capture_area(x as i32, y as i32, w, h)
+            .map_err(|_| "Failed to capture region".to_string())?
+    } else {
+        // Capture full screen
+        screen.capture().map_err(|_| "Failed to capture screen".to_string())?
+    };
+
+    // screenshots::Image has rgba() method
+    let rgba_data = image.rgba();
+
+    // Create image from raw RGBA data
+    image::RgbaImage::from_raw(image.width(), image.height(), rgba_data.to_vec())
+        .ok_or_else(|| "Failed to create image from RGBA data".to_string())
+}
+
+// 覆盖窗口中框选的区域（CSS像素，相对于主屏幕左上角）
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SelectedRegion {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+const REGION_PICKER_LABEL: &str = "region_picker";
+// 覆盖窗口关闭后等待窗口从屏幕上消失再截图
+const REGION_PICKER_CLOSE_DELAY: std::time::Duration = std::time::Duration::from_millis(150);
+
+// 覆盖窗口通过该命令返回选区，region为None表示取消
+#[tauri::command]
+async fn submit_region_selection(state: State<'_, AppState>, region: Option<SelectedRegion>) -> Result<(), String> {
+    let sender = state.region_picker.lock().await.take()
+        .ok_or("No region selection in progress")?;
+    let _ = sender.send(region);
+    Ok(())
+}
+
+// 在主屏幕上显示透明的置顶覆盖窗口，等待用户框选
+// 注意：覆盖窗口只覆盖Screen::all()中的第一个屏幕，选区坐标也按该屏幕截取，其他显示器上无法框选
+async fn pick_region_with_overlay(app_handle: &tauri::AppHandle, timeout: std::time::Duration) -> Result<Option<SelectedRegion>, String> {
+    let screen = Screen::all()
+        .map_err(|_| "Failed to access screen".to_string())?
+        .into_iter()
+        .next()
+        .ok_or("No screens found")?;
+
+    // 上一次未正常关闭的覆盖窗口
+    if let Some(existing) = app_handle.get_webview_window(REGION_PICKER_LABEL) {
+        let _ = existing.destroy();
+    }
+
+    let state = app_handle.state::<AppState>();
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    *state.region_picker.lock().await = Some(sender);
+
+    let window = tauri::WebviewWindowBuilder::new(app_handle, REGION_PICKER_LABEL, tauri::WebviewUrl::App("overlay.html".into()))
+        .title("MathImage Region Picker")
+        .position(screen.display_info.x as f64, screen.display_info.y as f64)
+        .inner_size(screen.display_info.width as f64, screen.display_info.height as f64)
+        .decorations(false)
+        .transparent(true)
+        .always_on_top(true)
+        .skip_taskbar(true)
+        .resizable(false)
+        .focused(true)
+        .build()
+        .map_err(|e| format!("Failed to open region picker: {}", e));
+    let window = match window {
+        Ok(window) => window,
+        Err(e) => {
+            state.region_picker.lock().await.take();
+            return Err(e);
+        }
+    };
+
+    // 窗口被直接关闭（如Alt+F4）时发送端仍留在AppState中，receiver不会结束，需要单独监听这个窗口的销毁
+    let (closed_sender, closed_receiver) = tokio::sync::oneshot::channel::<()>();
+    let closed_sender = std::sync::Mutex::new(Some(closed_sender));
+    window.on_window_event(move |event| {
+        if let tauri::WindowEvent::Destroyed = event {
+            if let Some(sender) = closed_sender.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                let _ = sender.send(());
+            }
+        }
+    });
+
+    let result = tokio::time::timeout(timeout, async {
+        tokio::select! {
+            region = receiver => region.unwrap_or(None),
+            _ = closed_receiver => None,
+        }
+    }).await;
+    let _ = window.destroy();
+    state.region_picker.lock().await.take();
+
+    // 窗口关闭视为取消
+    result.map_err(|_| format!("Region selection timed out after {}s", timeout.as_secs()))
+}
+
+// CaptureMode::Overlay: 覆盖窗口框选后截取该区域，返回未缩放的PNG data URL
+async fn take_overlay_screenshot(app_handle: &tauri::AppHandle, timeout: std::time::Duration) -> Result<String, String> {
+    let region = pick_region_with_overlay(app_handle, timeout).await?
+        .ok_or("Screenshot was cancelled")?;
+    tokio::time::sleep(REGION_PICKER_CLOSE_DELAY).await;
+
+    let x = region.x.max(0.0).round() as u32;
+    let y = region.y.max(0.0).round() as u32;
+    let width = region.width.round().max(1.0) as u32;
+    let height = region.height.round().max(1.0) as u32;
+    debug!("📐 Overlay selection: {}x{} at ({}, {})", width, height, x, y);
+
+    let profile = app_handle.state::<AppState>().get_active_profile().await?;
+    let img = capture_region_image(&profile, Some(x), Some(y), Some(width), Some(height), None)?;
+
+    let mut buffer = Vec::new();
+    image::DynamicImage::ImageRgba8(img)
+        .write_to(&mut std::io::Cursor::new(&mut buffer), image::ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(image_bytes_to_data_url(&buffer))
+}
+
+#[tauri::command]
+async fn take_screenshot_region(state: State<'_, AppState>, x: Option<u32>, y: Option<u32>, width: Option<u32>, height: Option<u32>, screen_index: Option<usize>) -> Result<String, String> {
+    let active_profile = state.get_active_profile().await?;
+    let img = capture_region_image(&active_profile, x, y, width, height, screen_index)?;
+    let (width, height) = img.dimensions();
+
+    // Apply the active profile's preprocessing (no-op by default)
+    let img = preprocess_image(img, &active_profile.preprocess);
+
+    // Resize image if too large (max 512x512 to reduce size further)
+    let max_size = 512;
+    let (new_width, new_height) = if width > max_size || height > max_size {
+        let scale = (max_size as f32 / width.max(height) as f32).min(1.0);
+        ((width as f32 * scale) as u32, (height as f32 * scale) as u32)
+    } else {
+        (width, height)
+    };
+
+    let resized_img = if new_width != width || new_height != height {
+        image::imageops::resize(&img, new_width, new_height, image::imageops::FilterType::Lanczos3)
+    } else {
+        img
+    };
+
+    // Convert RGBA to RGB for JPEG (JPEG doesn't support alpha channel)
+    let rgb_img: image::RgbImage = image::ImageBuffer::from_fn(new_width, new_height, |x, y| {
+        let rgba = resized_img.get_pixel(x, y);
+        image::Rgb([rgba[0], rgba[1], rgba[2]]) // Drop alpha channel
+    });
+
+    // Convert to JPEG for smaller size
+    let mut buffer = Vec::new();
+    {
+        let mut cursor = std::io::Cursor::new(&mut buffer);
+        image::write_buffer_with_format(
+            &mut cursor,
+            rgb_img.as_raw(),
+            new_width,
+            new_height,
+            image::ColorType::Rgb8,
+            image::ImageFormat::Jpeg,
+        ).map_err(|e| format!("Failed to encode image: {}", e))?;
+    }
+
+    info!("Screenshot captured: {}x{} -> {}x{}, size: {} bytes",
+             width, height, new_width, new_height, buffer.len());
+    let image_data = image_bytes_to_data_url(&buffer);
+    archive_capture(&state, &image_data).await;
+    Ok(image_data)
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct FallbackProfileEvent {
+    from: String,
+    to: String,
+    error: String,
+}
+
+// 请求阶段的失败（网络、HTTP错误、空响应）才值得换Profile重试；配置缺失、用户取消等不回退
+fn is_fallback_eligible(error: &str) -> bool {
+    error.contains("attempts failed")
+        || error.contains("Could not connect")
+        || error.contains("failed with status")
+        || error.contains("temporarily unavailable")
+        || error.contains("No content received")
+        || error.contains("no usable content")
+        || error.contains("Failed to read chunk")
+}
+
+// 新的分析函数，支持自定义prompt
+async fn analyze_image_with_prompt(
+    image_data: String,
+    state: State<'_, AppState>,
+    custom_prompt: Option<String>,
+    app_handle: Option<tauri::AppHandle>,
+) -> Result<String, String> {
+    analyze_images_with_prompt(vec![image_data], state, custom_prompt, app_handle).await
+}
+
+// 使用活跃profile分析（多张图片放在同一条消息中，得到一个结果）；
+// 失败时如果配置了备用Profile，用备用Profile的配置再试一次
+async fn analyze_images_with_prompt(
+    images: Vec<String>,
+    state: State<'_, AppState>,
+    custom_prompt: Option<String>,
+    app_handle: Option<tauri::AppHandle>,
+) -> Result<String, String> {
+    let active_profile = state.get_active_profile().await?;
+    let fallback_profile_id = active_profile.fallback_profile_id.clone();
+
+    let error = match analyze_image_with_profile(
+        images.clone(), state.clone(), custom_prompt.clone(), app_handle.clone(), active_profile.clone(),
+    ).await {
+        Ok(result) => return Ok(result),
+        Err(e) => e,
+    };
+
+    // 只回退一次，不再跟随备用Profile自己的fallback_profile_id，避免循环
+    let fallback_profile = match fallback_profile_id {
+        Some(id) if id != active_profile.id && is_fallback_eligible(&error) => {
+            state.config.lock().await.profiles.iter().find(|p| p.id == id).cloned()
+        }
+        _ => None,
+    };
+    let Some(fallback_profile) = fallback_profile else {
+        return Err(error);
+    };
+
+    warn!("🔀 Profile '{}' failed ({}), retrying with fallback profile '{}'", active_profile.name, error, fallback_profile.name);
+    if let Some(handle) = &app_handle {
+        let _ = handle.emit("fallback_profile_used", FallbackProfileEvent {
+            from: active_profile.name.clone(),
+            to: fallback_profile.name.clone(),
+            error: error.clone(),
+        });
+    }
+
+    let fallback_name = fallback_profile.name.clone();
+    analyze_image_with_profile(images, state, custom_prompt, app_handle, fallback_profile).await
+        .map_err(|e| format!("{} (fallback profile '{}' also failed: {})", error, fallback_name, e))
+}
+
+// 模型名看起来支持图片输入（模型缓存中只有id，只能按名称判断）
+fn looks_like_vision_model(model_id: &str) -> bool {
+    let id = model_id.to_lowercase();
+    ["vision", "llava", "pixtral", "4o", "omni"].iter().any(|hint| id.contains(hint))
+        || id.split(|c: char| !c.is_ascii_alphanumeric()).any(|part| part == "vl")
+}
+
+// 从模型列表中选择：优先第一个看起来支持图片的模型，否则第一个
+fn pick_default_model(models: &[String]) -> Option<String> {
+    models.iter()
+        .find(|m| looks_like_vision_model(m))
+        .or_else(|| models.first())
+        .cloned()
+}
+
+// 为没有选择模型的Profile从已加载的模型中自动选择一个并保存；没有已加载的模型时返回None
+async fn auto_select_model(state: &AppState, app_handle: Option<&tauri::AppHandle>, profile: &Profile) -> Result<Option<String>, String> {
+    let cached_models = state.loaded_models.lock().await
+        .get(&profile.api_config.base_url)
+        .cloned()
+        .unwrap_or_default();
+    // 遵循托盘的允许/屏蔽名单和过滤字符串，全部被过滤掉时退回完整列表
+    let candidates = match profile.tray_models(&cached_models) {
+        models if models.is_empty() => cached_models,
+        models => models,
+    };
+    let model = match pick_default_model(&candidates) {
+        Some(model) => model,
+        None => return Ok(None),
+    };
+
+    let profile_id = profile.id.clone();
+    state.update_and_save_config(|config| {
+        let profile = config.profiles.iter_mut()
+            .find(|p| p.id == profile_id)
+            .ok_or("Profile not found")?;
+        profile.api_config.model = model.clone();
+        Ok(())
+    }).await?;
+    info!("🤖 Auto-selected model '{}' for profile '{}'", model, profile.name);
+
+    if let Some(handle) = app_handle {
+        let is_active = state.config.lock().await.active_profile_id.as_deref() == Some(profile.id.as_str());
+        if is_active {
+            update_model_submenu_title(handle, &model).await.ok();
+            update_model_menu_selection(handle, &model).await.ok();
+        }
+    }
+    Ok(Some(model))
+}
+
+async fn analyze_image_with_profile(
+    images: Vec<String>,
+    state: State<'_, AppState>,
+    custom_prompt: Option<String>,
+    app_handle: Option<tauri::AppHandle>,
+    mut active_profile: Profile,
+) -> Result<String, String> {
+    let config = state.config.lock().await;
+    let sound_enabled = config.sound_enabled;
+    let auto_select_first_model = config.auto_select_first_model;
+    let mock_mode = config.mock_mode || mock_mode_from_env();
+    let max_payload_bytes = config.max_payload_bytes;
+    let shrink_oversized_images = config.shrink_oversized_images;
+    let analysis_cache_size = config.analysis_cache_size;
+    let analysis_cache_ttl = std::time::Duration::from_secs(config.analysis_cache_ttl_secs);
+    let analysis_cache_exact_bytes = config.analysis_cache_exact_bytes;
+    let refusal_patterns = config.refusal_patterns.clone();
+    let empty_retry_max = if config.retry_on_empty { config.empty_retry_max } else { 0 };
+    let include_reasoning = active_profile.include_reasoning;
+    // 只有热键触发且输出到剪贴板时才边接收边写入
+    let stream_to_clipboard = config.stream_to_clipboard
+        && app_handle.is_some()
+        && matches!(active_profile.output_mode, OutputMode::Clipboard | OutputMode::ClipboardToast);
+    drop(config);
+
+    // 模拟模式下跳过API配置检查和网络请求，便于离线测试截图→输出流程
+    if mock_mode {
+        return mock_analysis_response(app_handle.as_ref(), custom_prompt.as_deref()).await;
+    }
+
+    // 本地OCR不需要API配置，也不发送网络请求；结果同样经过后续的输出流程
+    if active_profile.local_ocr {
+        let language = active_profile.local_ocr_language.clone();
+        info!("🔤 Running local OCR for profile '{}' (language: {})", active_profile.name, language);
+        let text = tokio::task::spawn_blocking(move || {
+            images.iter()
+                .map(|image_data| run_local_ocr(image_data, &language))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|texts| texts.join("\n\n"))
+        }).await
+            .map_err(|e| format!("Local OCR task failed: {}", e))??;
+        check_usable_content(&text, &[])?;
+        return Ok(text);
+    }
+
+    // 验证API配置
+    if active_profile.api_config.usable_api_key().is_empty() || active_profile.api_config.base_url.is_empty() {
+        // Show system dialog for missing API config (only for hotkey usage)
+        if let Some(ref _handle) = app_handle {
+            if sound_enabled {
+                // Play error sound
+                if let Err(sound_err) = play_error_sound(state.clone()).await {
+                    error!("Failed to play error sound: {}", sound_err);
+                }
+            }
+
+            // Show macOS system dialog
+            if let Err(dialog_err) = show_system_dialog(
+                "MathImage Error".to_string(),
+                format!("Profile '{}': API key and base URL are required. Please configure them in Settings.", active_profile.name),
+                "error".to_string()
+            ).await {
+                error!("Failed to show system dialog: {}", dialog_err);
+            }
+        }
+        return Err(format!("Profile '{}': API key and base URL are required", active_profile.name));
+    }
+
+    if active_profile.api_config.model.is_empty() && auto_select_first_model {
+        match auto_select_model(&state, app_handle.as_ref(), &active_profile).await {
+            Ok(Some(model)) => active_profile.api_config.model = model,
+            Ok(None) => debug!("No loaded models to auto-select for profile '{}'", active_profile.name),
+            Err(e) => warn!("⚠️ Failed to auto-select model: {}", e),
+        }
+    }
+
+    if active_profile.api_config.model.is_empty() {
+        // Show system dialog for missing model (only for hotkey usage)
+        if let Some(ref _handle) = app_handle {
+            if sound_enabled {
+                // Play error sound
+                if let Err(sound_err) = play_error_sound(state.clone()).await {
+                    error!("Failed to play error sound: {}", sound_err);
+                }
+            }
+
+            // Show macOS system dialog
+            if let Err(dialog_err) = show_system_dialog(
+                "MathImage Error".to_string(),
+                format!("Profile '{}': Please select a model first. Check Settings to load available models.", active_profile.name),
+                "error".to_string()
+            ).await {
+                error!("Failed to show system dialog: {}", dialog_err);
+            }
+        }
+        return Err(format!("Profile '{}': Please select a model first", active_profile.name));
+    }
+
+    // 模型缓存非空但不包含所选模型，说明服务商已下线该模型；缓存为空时跳过检查
+    let cached_models = state.loaded_models.lock().await
+        .get(&active_profile.api_config.base_url)
+        .cloned()
+        .unwrap_or_default();
+    if !cached_models.is_empty() && !cached_models.contains(&active_profile.api_config.model) {
+        if app_handle.is_some() {
+            if sound_enabled {
+                if let Err(sound_err) = play_error_sound(state.clone()).await {
+                    error!("Failed to play error sound: {}", sound_err);
+                }
+            }
+
+            if let Err(dialog_err) = show_system_dialog(
+                "MathImage Error".to_string(),
+                format!("Profile '{}': Model '{}' is no longer available. Please reload models and select another one.", active_profile.name, active_profile.api_config.model),
+                "error".to_string()
+            ).await {
+                error!("Failed to show system dialog: {}", dialog_err);
+            }
+        }
+        return Err(format!("Selected model '{}' no longer available, please reload models", active_profile.api_config.model));
+    }
+
+    // 复用AppState中的连接池，连续截图时不必重新建立TLS/HTTP2连接
+    let client = state.http_client_for(&active_profile.api_config).await?;
+    info!("Analyzing image with profile '{}' using model: {}", active_profile.name, active_profile.api_config.model);
+    let total_image_bytes: usize = images.iter().map(|image_data| image_data.len()).sum();
+    debug!("Image data size: {} chars ({} images)", total_image_bytes, images.len());
+
+    // 无论来自哪个采集来源，发送前都按实际字节校正MIME
+    let images: Vec<String> = images.into_iter().map(normalize_image_data_url).collect();
+
+    // 图片过大时容易超时：缩小后重新压缩，或提示用户选择更小的区域
+    // 多张图片时限制作用于总大小，按各自大小比例分配
+    let images = if max_payload_bytes > 0 && total_image_bytes > max_payload_bytes {
+        if !shrink_oversized_images {
+            return Err(format!(
+                "Image is too large ({} KB, limit {} KB). Please select a smaller region.",
+                total_image_bytes / 1024, max_payload_bytes / 1024
+            ));
+        }
+
+        warn!("Image data is large ({} bytes, limit {}), recompressing", total_image_bytes, max_payload_bytes);
+        let shrunk = images.iter()
+            .map(|image_data| {
+                let budget = (max_payload_bytes as u128 * image_data.len() as u128 / total_image_bytes as u128) as usize;
+                shrink_image_data_url(image_data, budget)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        info!("Recompressed images to {} bytes", shrunk.iter().map(|image_data| image_data.len()).sum::<usize>());
+        shrunk
+    } else {
+        images
+    };
+
+    // 确定使用的prompt：自定义prompt优先，否则使用Profile的prompt模式
+    let prompt_text = if let Some(custom) = custom_prompt {
+        info!("Using custom prompt: {}", custom);
+        custom
+    } else {
+        match &active_profile.effective_prompt_mode() {
+            PromptMode::Predefined(prompt) => {
+                info!("Using predefined prompt from profile: {}", prompt);
+                prompt.clone()
+            },
+            PromptMode::UserInput => {
+                // 没有传入prompt时通过输入对话框向用户请求
+                let handle = app_handle.clone().ok_or_else(|| format!(
+                    "Profile '{}' requires a user-entered prompt, but none was provided and no input dialog is available",
+                    active_profile.name
+                ))?;
+
+                info!("Profile requires user input prompt, showing input dialog");
+                let user_prompt = show_input_dialog(handle, "Enter your prompt:".to_string(), "请输入分析图片的提示词...".to_string()).await?;
+                if user_prompt.trim().is_empty() {
+                    return Err("Prompt input was empty".to_string());
+                }
+                user_prompt
+            }
+            PromptMode::Template(template) => {
+                info!("Using template prompt from profile: {}", template);
+                template.clone()
+            }
+        }
+    };
+
+    // 模板模式下（包括热键路径传入的模板文本）替换占位符
+    let prompt_text = if matches!(active_profile.effective_prompt_mode(), PromptMode::Template(_)) {
+        let resolved = resolve_prompt_template(&prompt_text, &active_profile);
+        info!("Resolved template prompt: {}", resolved);
+        resolved
+    } else {
+        prompt_text
+    };
+
+    // few-shot示例默认作为独立的user/assistant消息；few_shot_inline时拼接到system prompt中
+    let mut system_prompt = active_profile.system_prompt.clone().filter(|p| !p.trim().is_empty());
+    let mut few_shot_turns = Vec::new();
+    if active_profile.few_shot_inline {
+        system_prompt = inline_few_shot(system_prompt.as_deref(), &active_profile.few_shot);
+    } else {
+        few_shot_turns = few_shot_messages(&active_profile.few_shot);
+    }
+
+    // 同一图片+prompt+模型在有效期内直接返回缓存结果，不再请求API
+    let cache_key = if analysis_cache_size > 0 {
+        // few-shot示例、图片detail和是否附带推理过程同样影响输出，和system prompt一起计入缓存键
+        let cache_context = format!("{}\n{:?}\n{:?}\n{}", system_prompt.as_deref().unwrap_or_default(), few_shot_turns, active_profile.image_detail, active_profile.include_reasoning);
+        let key = analysis_cache_key(
+            &images,
+            &cache_context,
+            &prompt_text,
+            &active_profile.api_config.model,
+            &active_profile.api_config.base_url,
+            analysis_cache_exact_bytes,
+        );
+        if let Some(cached) = state.analysis_cache.lock().await.get(key, analysis_cache_ttl) {
+            info!("♻️ Returning cached analysis result ({} chars)", cached.len());
+            return Ok(cached);
+        }
+        Some(key)
+    } else {
+        None
+    };
+
+    state.acquire_rate_limit(&active_profile).await?;
+
+    // 只支持OpenAI兼容的chat completions接口，system prompt作为第一条role为system的消息
+    let mut messages = Vec::new();
+    if let Some(system_prompt) = system_prompt {
+        messages.push(serde_json::json!({
+            "role": "system",
+            "content": system_prompt
+        }));
+    }
+    messages.extend(few_shot_turns);
+    let mut content = vec![serde_json::json!({
+        "type": "text",
+        "text": prompt_text
+    })];
+    content.extend(images.iter().map(|image_data| {
+        let mut image_url = serde_json::json!({ "url": image_data });
+        if let Some(detail) = active_profile.image_detail.as_param() {
+            image_url["detail"] = serde_json::json!(detail);
+        }
+        serde_json::json!({
+            "type": "image_url",
+            "image_url": image_url
+        })
+    }));
+    messages.push(serde_json::json!({
+        "role": "user",
+        "content": content
+    }));
+
+    let payload = serde_json::json!({
+        "model": active_profile.api_config.model,
+        "messages": messages,
+        "temperature": 1,
+        "top_p": 1,
+        "stream": true
+    });
+
+    debug!("Payload size: {} bytes", serde_json::to_string(&payload).unwrap_or_default().len());
+
+    let request_log = if active_profile.request_log {
+        match RequestLog::create(active_profile.api_config.usable_api_key()) {
+            Ok(log) => {
+                log.section(
+                    &format!("Request (profile '{}')", active_profile.name),
+                    &serde_json::to_string_pretty(&payload_for_log(&payload)).unwrap_or_default(),
+                );
+                Some(log)
+            }
+            Err(e) => {
+                warn!("⚠️ Request logging disabled for this request: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // 依次尝试主endpoint和备用endpoint，跳过熔断中的endpoint
+    let endpoint_count = 1 + active_profile.api_config.fallback_base_urls.len();
+    let endpoints = std::iter::once(&active_profile.api_config.base_url)
+        .chain(active_profile.api_config.fallback_base_urls.iter());
+
+    let mut last_error = None;
+    for (index, endpoint) in endpoints.enumerate() {
+        if !state.endpoint_available(endpoint).await {
+            info!("Skipping endpoint with open circuit: {}", endpoint);
+            continue;
+        }
+
+        let url = format!("{}/chat/completions", endpoint);
+        info!("Sending request to: {}", url);
+
+        let mut request = client
+            .post(&url)
+            .header("Content-Type", "application/json");
+
+        // Only add auth headers if API key is provided
+        if !active_profile.api_config.usable_api_key().is_empty() {
+            request = request.header("Authorization", format!("Bearer {}", active_profile.api_config.usable_api_key()));
+        }
+
+        // 继续使用现有的请求处理逻辑...
+        // 请求成功但内容为空时按retry_on_empty重新发送；拒绝回答不重试
+        let mut empty_retries = 0;
+        // 连接不上时还有下一个endpoint可用就直接切换，不在这里消耗重试
+        let fail_fast_on_connect = index + 1 < endpoint_count;
+        let outcome = loop {
+            let attempt_request = request.try_clone().ok_or("Failed to clone request")?;
+            let outcome = analyze_image_request_internal(attempt_request, payload.clone(), app_handle.as_ref(), &url, stream_to_clipboard, include_reasoning, fail_fast_on_connect, request_log.as_ref()).await
+                .and_then(|result| check_usable_content(&result, &refusal_patterns).map(|()| result).map_err(RequestError::Request));
+            match outcome {
+                Err(e) if is_empty_content_error(e.message()) && empty_retries < empty_retry_max => {
+                    empty_retries += 1;
+                    warn!("{}, re-sending request ({}/{})", e.message(), empty_retries, empty_retry_max);
+                }
+                outcome => break outcome,
+            }
+        };
+
+        match outcome {
+            Ok(result) => {
+                state.record_endpoint_success(endpoint).await;
+                if let Some(key) = cache_key {
+                    state.analysis_cache.lock().await.insert(key, result.clone(), analysis_cache_size);
+                }
+                return Ok(result);
+            }
+            Err(e) if e.is_endpoint_failure() => {
+                warn!("Endpoint {} failed: {}", endpoint, e.message());
+                state.record_endpoint_failure(endpoint).await;
+                last_error = Some(e.into_message());
+            }
+            Err(e) => {
+                // The endpoint answered - the failure is not the host's fault
+                state.record_endpoint_success(endpoint).await;
+                return Err(e.into_message());
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| "All endpoints are temporarily unavailable, please try again later".to_string()))
+}
+
+fn is_empty_content_error(error: &str) -> bool {
+    error.contains("No content received") || error.contains("(whitespace only)")
+}
+
+// 空白结果或以拒绝语开头的结果不输出，返回错误走错误提示流程
+fn check_usable_content(result: &str, refusal_patterns: &[String]) -> Result<(), String> {
+    let trimmed = result.trim();
+    if trimmed.is_empty() {
+        return Err("Model returned no usable content (whitespace only)".to_string());
+    }
+
+    let lowered = trimmed.to_lowercase();
+    if let Some(pattern) = refusal_patterns.iter()
+        .find(|pattern| !pattern.trim().is_empty() && lowered.starts_with(&pattern.trim().to_lowercase()))
+    {
+        warn!("Model refused the request (matched '{}'): {}", pattern, truncate_preview(trimmed, NOTIFICATION_PREVIEW_CHARS));
+        return Err(format!("Model returned no usable content (refusal: {})", truncate_preview(trimmed, NOTIFICATION_PREVIEW_CHARS)));
+    }
+
+    Ok(())
+}
+
+// 单个endpoint请求失败的原因，决定是否计入熔断并切换到下一个endpoint
+#[derive(Debug)]
+enum RequestError {
+    // 连接不上（DNS、拒绝连接、TLS握手）：还有备用endpoint时不重试，直接切换
+    Connect(String),
+    // 重试用完的网络错误或5xx响应
+    Endpoint(String),
+    // endpoint正常响应，但请求本身失败（4xx、空内容等）
+    Request(String),
+}
+
+impl RequestError {
+    // Connection failures and 5xx responses count against an endpoint's circuit breaker
+    fn is_endpoint_failure(&self) -> bool {
+        matches!(self, RequestError::Connect(_) | RequestError::Endpoint(_))
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            RequestError::Connect(message) | RequestError::Endpoint(message) | RequestError::Request(message) => message,
+        }
+    }
+
+    fn into_message(self) -> String {
+        match self {
+            RequestError::Connect(message) | RequestError::Endpoint(message) | RequestError::Request(message) => message,
+        }
+    }
+}
+
+impl Profile {
+    // 实际生效的后处理步骤：显式配置的post_processing优先，
+    // 否则按旧的固定顺序由三个独立选项推导，保证老配置行为不变
+    fn effective_post_steps(&self) -> Vec<PostStep> {
+        if !self.post_processing.is_empty() {
+            return self.post_processing.clone();
+        }
+        let mut steps = Vec::new();
+        if self.strip_code_fences {
+            steps.push(PostStep::StripCodeFences);
+        }
+        if self.extract_latex_only {
+            // 旧逻辑中提取到公式后直接返回，不再改写定界符
+            steps.push(PostStep::ExtractLatex);
+        } else if self.latex_delimiters != DelimiterStyle::Keep {
+            steps.push(PostStep::LatexDelimiters(self.latex_delimiters));
+        }
+        steps
+    }
+}
+
+// 输出变换链 - 对分析结果按顺序应用profile配置的后处理步骤
+// Each step sees the output of the previous one; see effective_post_steps for how
+// the chain is derived when the profile has no explicit post_processing list.
+// ExtractLatex passes text without any math through unchanged instead of emptying it.
+// Both the capture path and preview_output_transforms go through this function,
+// so the preview always matches what actually reaches the clipboard/dialog.
+fn apply_output_transforms(profile: &Profile, text: &str) -> String {
+    profile.effective_post_steps()
+        .iter()
+        .fold(text.to_string(), |text, step| apply_post_step(step, &text))
+}
+
+fn apply_post_step(step: &PostStep, text: &str) -> String {
+    match step {
+        PostStep::StripCodeFences => strip_code_fences(text),
+        PostStep::ExtractLatex => extract_latex(text).unwrap_or_else(|| {
+            debug!("No math found in result, keeping full text");
+            text.to_string()
+        }),
+        PostStep::LatexDelimiters(style) => normalize_latex_delimiters(text, *style),
+        PostStep::Trim => text.trim().to_string(),
+    }
+}
+
+// 去掉包裹整个结果的代码块（开头的 ```lang 行和结尾的 ``` 行）
+// - 只有开头没有结尾（输出被截断）时只去掉开头
+// - 中间还有其他 ``` 行说明是多个代码块，原样返回
+// - 只有结尾没有开头时原样返回
+fn strip_code_fences(text: &str) -> String {
+    let trimmed = text.trim();
+    if !trimmed.starts_with("```") {
+        return text.to_string();
+    }
+
+    let (opening, rest) = match trimmed.split_once('\n') {
+        Some(parts) => parts,
+        None => return text.to_string(),
+    };
+
+    // 开头一行只能是 ``` 加可选的语言标记
+    let lang = opening.trim_start_matches('`').trim();
+    if lang.contains(char::is_whitespace) || lang.contains('`') {
+        return text.to_string();
+    }
+
+    let body = rest.trim_end();
+    let body = match body.rsplit_once('\n') {
+        Some((inner, last)) if last.trim() == "```" => inner,
+        None if body.trim() == "```" => "",
+        _ => body,
+    };
+
+    if body.lines().any(|line| line.trim_start().starts_with("```")) {
+        return text.to_string();
+    }
+
+    body.to_string()
+}
+
+enum MathSegment {
+    Text(String),
+    Math { content: String, display: bool },
+}
+
+// 把结果拆分为普通文本和 $..$ / $$..$$ / \(..\) / \[..\] 公式片段
+// - 行内代码和代码块原样保留
+// - 转义的 \$ 不视为定界符
+// - 公式内部按花括号深度匹配，{...} 里的 $ 或 \) 不会提前结束公式
+// - 行内 $ 需紧贴内容且结束的 $ 后面不是数字，避免把 "$5 and $10" 当成公式
+fn split_math_segments(text: &str) -> Vec<MathSegment> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut segments = Vec::new();
+    let mut out = String::new();
+    let mut i = 0;
+    let mut push_math = |out: &mut String, content: String, display: bool| {
+        if !out.is_empty() {
+            segments.push(MathSegment::Text(std::mem::take(out)));
+        }
+        segments.push(MathSegment::Math { content, display });
+    };
+
+    while i < chars.len() {
+        match chars[i] {
+            '`' => {
+                // 代码：找到相同长度的反引号结束符后原样复制
+                let ticks = chars[i..].iter().take_while(|&&c| c == '`').count();
+                let fence: Vec<char> = vec!['`'; ticks];
+                let end = find_sequence(&chars, i + ticks, &fence).map(|j| j + ticks).unwrap_or(i + ticks);
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            '\\' if matches!(chars.get(i + 1), Some('(') | Some('[')) => {
+                let display = chars[i + 1] == '[';
+                let closing = if display { ['\\', ']'] } else { ['\\', ')'] };
+                match find_math_end(&chars, i + 2, &closing) {
+                    Some(end) => {
+                        let content: String = chars[i + 2..end].iter().collect();
+                        push_math(&mut out, content, display);
+                        i = end + 2;
+                    }
+                    None => {
+                        out.extend(&chars[i..i + 2]);
+                        i += 2;
+                    }
+                }
+            }
+            '\\' => {
+                // 转义字符（包括 \$ 和 \\）原样保留
+                let end = (i + 2).min(chars.len());
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            '$' if chars.get(i + 1) == Some(&'$') => {
+                match find_math_end(&chars, i + 2, &['$', '$']) {
+                    Some(end) => {
+                        let content: String = chars[i + 2..end].iter().collect();
+                        push_math(&mut out, content, true);
+                        i = end + 2;
+                    }
+                    None => {
+                        out.push_str("$$");
+                        i += 2;
+                    }
+                }
+            }
+            '$' => {
+                let opens = chars.get(i + 1).map_or(false, |c| !c.is_whitespace());
+                let end = if opens { find_math_end(&chars, i + 1, &['$']) } else { None };
+                let valid = end.map_or(false, |end| {
+                    end > i + 1
+                        && !chars[end - 1].is_whitespace()
+                        && !chars[i + 1..end].windows(2).any(|w| w == ['\n', '\n'])
+                        && !chars.get(end + 1).map_or(false, |c| c.is_ascii_digit())
+                });
+                match end {
+                    Some(end) if valid => {
+                        let content: String = chars[i + 1..end].iter().collect();
+                        push_math(&mut out, content, false);
+                        i = end + 1;
+                    }
+                    _ => {
+                        out.push('$');
+                        i += 1;
+                    }
+                }
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    if !out.is_empty() {
+        segments.push(MathSegment::Text(out));
+    }
+    segments
+}
+
+// 把所有公式的定界符统一改写为指定风格
+fn normalize_latex_delimiters(text: &str, style: DelimiterStyle) -> String {
+    if style == DelimiterStyle::Keep {
+        return text.to_string();
+    }
+
+    split_math_segments(text)
+        .into_iter()
+        .map(|segment| match segment {
+            MathSegment::Text(text) => text,
+            MathSegment::Math { content, display } => wrap_math(&content, display, style),
+        })
+        .collect()
+}
+
+// 只保留公式内容（不含定界符），多个公式之间用换行分隔；没有公式时返回None
+fn extract_latex(text: &str) -> Option<String> {
+    let blocks: Vec<String> = split_math_segments(text)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            MathSegment::Math { content, .. } => Some(content.trim().to_string()),
+            MathSegment::Text(_) => None,
+        })
+        .filter(|content| !content.is_empty())
+        .collect();
+
+    if blocks.is_empty() {
+        None
+    } else {
+        Some(blocks.join("\n"))
+    }
+}
+
+fn find_sequence(chars: &[char], start: usize, needle: &[char]) -> Option<usize> {
+    (start..chars.len()).find(|&j| chars[j..].starts_with(needle))
+}
+
+// 在花括号深度为0处查找结束定界符，跳过转义字符
+fn find_math_end(chars: &[char], start: usize, closing: &[char]) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut j = start;
+    while j < chars.len() {
+        if depth == 0 && chars[j..].starts_with(closing) {
+            return Some(j);
+        }
+        match chars[j] {
+            '\\' => j += 1,
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        j += 1;
+    }
+    None
+}
+
+fn wrap_math(content: &str, display: bool, style: DelimiterStyle) -> String {
+    match (style, display) {
+        (DelimiterStyle::Paren, false) => format!("\\({}\\)", content),
+        (DelimiterStyle::Paren, true) => format!("\\[{}\\]", content),
+        (DelimiterStyle::Katex, true) => format!("$$\n{}\n$$", content.trim()),
+        (_, false) => format!("${}$", content),
+        (_, true) => format!("$${}$$", content),
+    }
+}
+
+// 替换模板中的占位符，未知占位符保持原样
+fn resolve_prompt_template(template: &str, profile: &Profile) -> String {
+    let now = chrono::Local::now();
+    let mut resolved = template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{time}", &now.format("%H:%M").to_string())
+        .replace("{profile_name}", &profile.name)
+        .replace("{model}", &profile.api_config.model);
+
+    // 只有模板用到时才读取剪贴板
+    if resolved.contains("{clipboard}") {
+        let clipboard_text = Clipboard::new()
+            .and_then(|mut clipboard| clipboard.get_text())
+            .unwrap_or_else(|e| {
+                error!("Failed to read clipboard for template: {}", e);
+                String::new()
+            });
+        resolved = resolved.replace("{clipboard}", &clipboard_text);
+    }
+
+    resolved
+}
+
+// resolve_prompt_template支持的占位符
+const PROMPT_TEMPLATE_PLACEHOLDERS: &[&str] = &["date", "time", "profile_name", "model", "clipboard"];
+
+// 找出模板中不认识的 {name} 占位符（去重，按出现顺序）
+// prompt里常有LaTeX，紧跟在命令/上下标/另一个参数后面的 {..}（如 \frac{a}{b}、x_{ij}）不算占位符
+fn unknown_template_placeholders(template: &str) -> Vec<String> {
+    let mut unknown: Vec<String> = Vec::new();
+    let mut rest = template;
+    let mut previous: Option<char> = None;
+
+    while let Some(open) = rest.find('{') {
+        let before = rest[..open].chars().last().or(previous);
+        let after_open = &rest[open + 1..];
+        previous = Some('{');
+
+        let is_latex_argument = before.map_or(false, |c| c.is_alphanumeric() || matches!(c, '\\' | '}' | '^' | '_'));
+        let name = after_open.find('}').map(|close| &after_open[..close]);
+        rest = after_open;
+
+        let name = match name {
+            Some(name) if !is_latex_argument => name,
+            _ => continue,
+        };
+        let is_identifier = name.chars().next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_identifier && !PROMPT_TEMPLATE_PLACEHOLDERS.contains(&name) && !unknown.iter().any(|u| u == name) {
+            unknown.push(name.to_string());
+        }
+    }
+    unknown
+}
+
+// 按分析时相同的规则解析模板（读取当前剪贴板），只返回结果不发送请求；有未知占位符时返回错误
+#[tauri::command]
+async fn preview_prompt(state: State<'_, AppState>, template: String) -> Result<String, String> {
+    let unknown = unknown_template_placeholders(&template);
+    if !unknown.is_empty() {
+        let unknown: Vec<String> = unknown.iter().map(|name| format!("{{{}}}", name)).collect();
+        let supported: Vec<String> = PROMPT_TEMPLATE_PLACEHOLDERS.iter().map(|name| format!("{{{}}}", name)).collect();
+        return Err(format!(
+            "Unknown placeholders: {} (supported: {})",
+            unknown.join(", "), supported.join(", ")
+        ));
+    }
+
+    let active_profile = state.get_active_profile().await?;
+    Ok(resolve_prompt_template(&template, &active_profile))
+}
+
+// 文件名中不允许出现的字符（取Windows/macOS/Linux的并集）
+const UNSAFE_FILENAME_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+fn sanitize_filename_part(part: &str) -> String {
+    part.chars()
+        .map(|c| if UNSAFE_FILENAME_CHARS.contains(&c) || c.is_control() { '_' } else { c })
+        .collect()
+}
+
+// 解析文件名模板：{date} -> 2024-05-01, {time} -> 153045, {profile} -> Profile名称（不安全字符替换为_）
+// 模板本身含有路径分隔符等字符时返回错误
+fn resolve_filename_template(template: &str, profile_name: &str, now: chrono::DateTime<chrono::Local>) -> Result<String, String> {
+    let name = template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{time}", &now.format("%H%M%S").to_string())
+        .replace("{profile}", &sanitize_filename_part(profile_name));
+    let name = name.trim();
+
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("Filename template '{}' produces an empty file name", template));
+    }
+    if let Some(c) = name.chars().find(|c| UNSAFE_FILENAME_CHARS.contains(c) || c.is_control()) {
+        return Err(format!("Filename template '{}' contains invalid character '{}'", template, c.escape_default()));
+    }
+    if name.len() > 255 {
+        return Err(format!("Filename template '{}' produces a file name longer than 255 bytes", template));
+    }
+
+    Ok(name.to_string())
+}
+
+// 文件已存在时追加 -1, -2 ...，不覆盖之前的结果
+fn unique_file_path(dir: &std::path::Path, file_name: &str) -> PathBuf {
+    let candidate = dir.join(file_name);
+    if !candidate.exists() {
+        return candidate;
+    }
+
+    let path = std::path::Path::new(file_name);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (1..)
+        .map(|n| dir.join(format!("{}-{}{}", stem, n, extension)))
+        .find(|p| !p.exists())
+        .unwrap_or(candidate)
+}
+
+// OutputMode::File: 写入Profile的输出目录，目录不存在时自动创建
+fn write_result_to_file(profile: &Profile, result: &str) -> Result<PathBuf, String> {
+    let output_dir = if profile.output_dir.as_os_str().is_empty() {
+        dirs_next::document_dir()
+            .or_else(dirs_next::home_dir)
+            .ok_or("Failed to determine default output directory")?
+            .join("MathImage")
+    } else {
+        profile.output_dir.clone()
+    };
+
+    fs::create_dir_all(&output_dir)
+        .map_err(|e| format!("Failed to create output directory {:?}: {}", output_dir, e))?;
+
+    let file_name = resolve_filename_template(&profile.filename_template, &profile.name, chrono::Local::now())?;
+    let path = unique_file_path(&output_dir, &file_name);
+    fs::write(&path, result)
+        .map_err(|e| format!("Failed to write result to {:?}: {}", path, e))?;
+
+    info!("📝 Result written to {:?}", path);
+    Ok(path)
+}
+
+// 相邻分块的重叠比例（相对分块边长），避免切断的文字行两边都读不全
+const TILE_OVERLAP_DIVISOR: u32 = 10;
+// 分块阈值的下限，太小的分块会丢失上下文
+const MIN_TILE_THRESHOLD_PX: u32 = 512;
+
+// 把长度为length的边切成若干段 (start, size)：每段不超过max_tile，相邻段重叠约overlap像素，
+// 段长尽量相等，最后一段与边缘对齐
+fn tile_spans(length: u32, max_tile: u32, overlap: u32) -> Vec<(u32, u32)> {
+    if length <= max_tile || overlap >= max_tile {
+        return vec![(0, length)];
+    }
+
+    let step = max_tile - overlap;
+    let count = (length - overlap).div_ceil(step);
+    let size = (length + (count - 1) * overlap).div_ceil(count);
+    (0..count)
+        .map(|i| ((i * (size - overlap)).min(length - size), size))
+        .collect()
+}
+
+// 行方向的切分位置尽量落在重叠带中的空白行（行间距）上：找到时两块在该行处衔接不再重叠，
+// 避免同一行文字被两块各识别一次；找不到空白行时保留重叠，由stitch_tile_results去重
+fn snap_spans_to_blank_rows(spans: &mut [(u32, u32)], is_blank_row: impl Fn(u32) -> bool) {
+    for i in 1..spans.len() {
+        let (prev_start, prev_size) = spans[i - 1];
+        let (start, size) = spans[i];
+        let prev_end = prev_start + prev_size;
+        if start >= prev_end {
+            continue;
+        }
+
+        // 从重叠带中间向两侧搜索
+        let middle = start + (prev_end - start) / 2;
+        let cut = (0..=(prev_end - start) / 2)
+            .flat_map(|offset| [middle.checked_sub(offset), Some(middle + offset)])
+            .flatten()
+            .filter(|y| (start..prev_end).contains(y))
+            .find(|&y| is_blank_row(y));
+
+        if let Some(cut) = cut {
+            spans[i - 1].1 = cut - prev_start;
+            spans[i] = (cut, start + size - cut);
+        }
+    }
+}
+
+// 拼接各分块的结果：去掉下一块开头与上一块结尾重复的行（重叠区域被识别了两次）
+fn stitch_tile_results(results: &[String]) -> String {
+    const MAX_OVERLAP_LINES: usize = 10;
+
+    let mut stitched: Vec<&str> = Vec::new();
+    for result in results {
+        let lines: Vec<&str> = result.trim().lines().collect();
+        let max_overlap = MAX_OVERLAP_LINES.min(lines.len()).min(stitched.len());
+        let overlap = (1..=max_overlap).rev()
+            .find(|&n| {
+                stitched[stitched.len() - n..].iter()
+                    .zip(&lines[..n])
+                    .all(|(a, b)| a.trim() == b.trim())
+            })
+            .filter(|&n| lines[..n].iter().any(|line| !line.trim().is_empty()))
+            .unwrap_or(0);
+
+        if !stitched.is_empty() && overlap == 0 {
+            stitched.push("");
+        }
+        stitched.extend(&lines[overlap..]);
+    }
+    stitched.join("\n")
+}
+
+// 整行像素亮度差很小即视为空白行（行间距、页边距）
+fn is_blank_row(luma: &image::GrayImage, y: u32) -> bool {
+    const BLANK_ROW_TOLERANCE: u8 = 12;
+    let (min, max) = (0..luma.width())
+        .map(|x| luma.get_pixel(x, y)[0])
+        .fold((u8::MAX, u8::MIN), |(min, max), v| (min.min(v), max.max(v)));
+    max.saturating_sub(min) <= BLANK_ROW_TOLERANCE
+}
+
+// 把任一边超过阈值的图片切成重叠的分块，按阅读顺序（从上到下、从左到右）返回PNG data URL
+// 不需要切分时返回None
+fn split_image_into_tiles(image_data: &str, threshold: u32) -> Result<Option<Vec<String>>, String> {
+    let threshold = threshold.max(MIN_TILE_THRESHOLD_PX);
+    let base64_part = image_data.split_once(',').map(|(_, data)| data).unwrap_or(image_data);
+    let bytes = general_purpose::STANDARD.decode(base64_part)
+        .map_err(|e| format!("Failed to decode image data: {}", e))?;
+    let mut img = image::load_from_memory(&bytes)
+        .map_err(|e| format!("Failed to decode image: {}", e))?
+        .to_rgba8();
+
+    let (width, height) = img.dimensions();
+    if width <= threshold && height <= threshold {
+        return Ok(None);
+    }
+
+    let overlap = threshold / TILE_OVERLAP_DIVISOR;
+    let columns = tile_spans(width, threshold, overlap);
+    let mut rows = tile_spans(height, threshold, overlap);
+    let luma = image::imageops::grayscale(&img);
+    snap_spans_to_blank_rows(&mut rows, |y| is_blank_row(&luma, y));
+
+    let mut tiles = Vec::with_capacity(rows.len() * columns.len());
+    for &(y, tile_height) in &rows {
+        for &(x, tile_width) in &columns {
+            let tile = image::imageops::crop(&mut img, x, y, tile_width, tile_height).to_image();
+            let mut buffer = Vec::new();
+            image::DynamicImage::ImageRgba8(tile)
+                .write_to(&mut std::io::Cursor::new(&mut buffer), image::ImageOutputFormat::Png)
+                .map_err(|e| format!("Failed to encode image: {}", e))?;
+            tiles.push(image_bytes_to_data_url(&buffer));
+        }
+    }
+
+    debug!("🧩 Split {}x{} image into {} rows x {} columns", width, height, rows.len(), columns.len());
+    Ok(Some(tiles))
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TileProgressEvent {
+    index: usize,
+    total: usize,
+}
+
+// Profile开启分块时，把大图切成分块依次分析（复用analyze_image_with_prompt的重试/缓存等逻辑）再拼接结果；
+// 未开启或图片不超过阈值时等同于analyze_image_with_prompt
+async fn analyze_image_tiled(
+    image_data: String,
+    state: State<'_, AppState>,
+    custom_prompt: Option<String>,
+    app_handle: Option<tauri::AppHandle>,
+) -> Result<String, String> {
+    let active_profile = state.get_active_profile().await?;
+    if !active_profile.tile_large_images {
+        return analyze_image_with_prompt(image_data, state, custom_prompt, app_handle).await;
+    }
+
+    let tiles = match split_image_into_tiles(&image_data, active_profile.tile_threshold_px) {
+        Ok(Some(tiles)) if tiles.len() > 1 => tiles,
+        Ok(_) => return analyze_image_with_prompt(image_data, state, custom_prompt, app_handle).await,
+        Err(e) => {
+            warn!("Failed to split image into tiles, analyzing it whole: {}", e);
+            return analyze_image_with_prompt(image_data, state, custom_prompt, app_handle).await;
+        }
+    };
+
+    // 需要用户输入prompt时只询问一次，所有分块共用
+    let custom_prompt = match custom_prompt {
+        None if matches!(active_profile.effective_prompt_mode(), PromptMode::UserInput) => {
+            let handle = app_handle.clone().ok_or_else(|| format!(
+                "Profile '{}' requires a user-entered prompt, but none was provided and no input dialog is available",
+                active_profile.name
+            ))?;
+            let user_prompt = show_input_dialog(handle, "Enter your prompt:".to_string(), "请输入分析图片的提示词...".to_string()).await?;
+            if user_prompt.trim().is_empty() {
+                return Err("Prompt input was empty".to_string());
+            }
+            Some(user_prompt)
+        }
+        custom_prompt => custom_prompt,
+    };
+
+    let total = tiles.len();
+    info!("🧩 Analyzing large image in {} tiles", total);
+
+    let mut results = Vec::with_capacity(total);
+    for (index, tile) in tiles.into_iter().enumerate() {
+        if let Some(handle) = &app_handle {
+            let _ = handle.emit("tile_progress", TileProgressEvent { index, total });
+        }
+        let result = analyze_image_with_prompt(tile, state.clone(), custom_prompt.clone(), app_handle.clone()).await
+            .map_err(|e| format!("Tile {}/{} failed: {}", index + 1, total, e))?;
+        results.push(result);
+    }
+
+    Ok(stitch_tile_results(&results))
+}
+
+// 保持向后兼容的原函数
+async fn analyze_image_internal(
+    image_data: String,
+    state: State<'_, AppState>,
+    app_handle: Option<tauri::AppHandle>,
+) -> Result<String, String> {
+    analyze_image_tiled(image_data, state, None, app_handle).await
+}
+
+// 截图/请求生命周期事件，供前端显示当前阶段
+#[derive(Debug, Clone, Serialize)]
+struct CaptureFinishedEvent {
+    width: u32,
+    height: u32,
+    bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RequestStartedEvent {
+    url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RequestRetryEvent {
+    attempt: u32,
+    max_attempts: u32,
+    error: String,
+}
+
+// 单次分析请求的耗时统计，用于比较不同服务商和观察连接复用的效果
+// （复用已有连接时首字节时间明显更短）
+#[derive(Debug, Clone, Default, Serialize)]
+struct RequestTimingEvent {
+    url: String,
+    // 实际发送的次数，包括连接失败重试和限流重试
+    attempts: u32,
+    rate_limit_retries: u32,
+    // 最后一次发送到收到响应头的时间
+    time_to_first_byte_ms: Option<u64>,
+    // 最后一次发送到收到第一个流式数据块的时间
+    time_to_first_chunk_ms: Option<u64>,
+    // 包括重试等待在内的总耗时
+    total_ms: u64,
+    success: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CaptureQueueEvent {
+    // 包括正在分析的那一个
+    depth: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct AnalysisCompleteEvent {
+    chars: usize,
+    duration_ms: u64,
+}
+
+// 从data URL中读取图像尺寸和字节数
+fn image_data_url_info(image_data: &str) -> Option<CaptureFinishedEvent> {
+    let base64_part = image_data.split_once(',').map(|(_, data)| data)?;
+    let bytes = general_purpose::STANDARD.decode(base64_part).ok()?;
+    let (width, height) = image::io::Reader::new(std::io::Cursor::new(&bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+    Some(CaptureFinishedEvent { width, height, bytes: bytes.len() })
+}
+
+// 剪贴板原有内容（文本或图片），流式写入部分结果前保存
+enum ClipboardSnapshot {
+    Text(String),
+    Image(arboard::ImageData<'static>),
+    Empty,
+}
+
+fn snapshot_clipboard() -> ClipboardSnapshot {
+    let mut clipboard = match Clipboard::new() {
+        Ok(clipboard) => clipboard,
+        Err(_) => return ClipboardSnapshot::Empty,
+    };
+    if let Ok(text) = clipboard.get_text() {
+        return ClipboardSnapshot::Text(text);
+    }
+    match clipboard.get_image() {
+        Ok(image) => ClipboardSnapshot::Image(image.to_owned_img()),
+        Err(_) => ClipboardSnapshot::Empty,
+    }
+}
+
+fn restore_clipboard(snapshot: ClipboardSnapshot) -> Result<(), String> {
+    let mut clipboard = Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?;
+    match snapshot {
+        ClipboardSnapshot::Text(text) => clipboard.set_text(text),
+        ClipboardSnapshot::Image(image) => clipboard.set_image(image),
+        ClipboardSnapshot::Empty => clipboard.clear(),
+    }
+    .map_err(|e| format!("Failed to restore clipboard: {}", e))
+}
+
+// 流式写入剪贴板时保护原内容：分析失败或任务被取消（future被drop）时，
+// 如果剪贴板中仍是本次写入的部分结果，就恢复为分析前的内容；用户在此期间自己复制的内容不会被覆盖。
+// 分析成功后调用disarm，由输出阶段写入完整结果
+struct ClipboardRestoreGuard {
+    snapshot: Option<ClipboardSnapshot>,
+    partial: Arc<std::sync::Mutex<Option<String>>>,
+}
+
+impl ClipboardRestoreGuard {
+    fn new(partial: Arc<std::sync::Mutex<Option<String>>>) -> Self {
+        *partial.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        Self { snapshot: Some(snapshot_clipboard()), partial }
+    }
+
+    fn disarm(mut self) {
+        self.snapshot = None;
+    }
+}
+
+impl Drop for ClipboardRestoreGuard {
+    fn drop(&mut self) {
+        let partial = self.partial.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let (Some(snapshot), Some(partial)) = (self.snapshot.take(), partial) else {
+            return;
+        };
+
+        let current = Clipboard::new().and_then(|mut clipboard| clipboard.get_text()).ok();
+        if current.as_deref() != Some(partial.as_str()) {
+            debug!("Clipboard changed since the partial result was written, not restoring");
+            return;
+        }
+        match restore_clipboard(snapshot) {
+            Ok(()) => info!("📋 Restored previous clipboard content after failed analysis"),
+            Err(e) => warn!("⚠️ {}", e),
+        }
+    }
+}
+
+// 流式写入剪贴板的最小间隔
+const CLIPBOARD_STREAM_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+// 提取请求处理逻辑为独立函数
+#[allow(clippy::too_many_arguments)]
+async fn analyze_image_request_internal(
+    request: reqwest::RequestBuilder,
+    payload: serde_json::Value,
+    app_handle: Option<&tauri::AppHandle>,
+    url: &str,
+    stream_to_clipboard: bool,
+    include_reasoning: bool,
+    fail_fast_on_connect: bool,
+    request_log: Option<&RequestLog>,
+) -> Result<String, RequestError> {
+
+    if let Some(handle) = app_handle {
+        let _ = handle.emit("request_started", RequestStartedEvent { url: url.to_string() });
+    }
+
+    let started = std::time::Instant::now();
+    let mut timing = RequestTimingEvent { url: url.to_string(), ..Default::default() };
+    let result = send_analysis_request(request, payload, app_handle, url, stream_to_clipboard, include_reasoning, fail_fast_on_connect, request_log, &mut timing).await;
+    timing.total_ms = started.elapsed().as_millis() as u64;
+    timing.success = result.is_ok();
+
+    let summary = format!(
+        "attempts: {}, rate-limit retries: {}, ttfb: {}, first chunk: {}, total: {}ms",
+        timing.attempts,
+        timing.rate_limit_retries,
+        timing.time_to_first_byte_ms.map_or("-".to_string(), |ms| format!("{}ms", ms)),
+        timing.time_to_first_chunk_ms.map_or("-".to_string(), |ms| format!("{}ms", ms)),
+        timing.total_ms,
+    );
+    debug!("⏱️ Request timing for {}: {}", url, summary);
+    if let Some(log) = request_log {
+        log.section("Timing", &summary);
+    }
+    if let Some(handle) = app_handle {
+        let _ = handle.emit("request_timing", timing);
+    }
+
+    result
+}
+
+// 发送请求并读取流式响应，带连接失败重试和限流重试；耗时统计写入timing
+#[allow(clippy::too_many_arguments)]
+async fn send_analysis_request(
+    request: reqwest::RequestBuilder,
+    mut payload: serde_json::Value,
+    app_handle: Option<&tauri::AppHandle>,
+    url: &str,
+    stream_to_clipboard: bool,
+    include_reasoning: bool,
+    fail_fast_on_connect: bool,
+    request_log: Option<&RequestLog>,
+    timing: &mut RequestTimingEvent,
+) -> Result<String, RequestError> {
+
+    // Retry logic for connection issues
+    // 429/503 按 Retry-After 等待，单独计数，不消耗普通的重试次数
+    let mut last_error = String::new();
+    let mut attempt = 1;
+    let mut rate_limit_retries = 0;
+    let mut timeout_shrinks = 0;
+    while attempt <= 3 {
+        info!("Attempt {} of 3", attempt);
+        if let Some(log) = request_log {
+            log.section(&format!("Attempt {}", attempt), &format!("POST {}", url));
+        }
+
+        if attempt > 1 || rate_limit_retries > 0 {
+            if let Some(handle) = app_handle {
+                let _ = handle.emit("request_retry", RequestRetryEvent {
+                    attempt,
+                    max_attempts: 3,
+                    error: last_error.clone(),
+                });
+            }
+        }
+
+        timing.attempts += 1;
+        let sent_at = std::time::Instant::now();
+        let response_result = request
+            .try_clone()
+            .ok_or_else(|| RequestError::Request("Failed to clone request".to_string()))?
+            .json(&payload)
+            .send()
+            .await;
+
+        match response_result {
+            Ok(response) => {
+                timing.time_to_first_byte_ms = Some(sent_at.elapsed().as_millis() as u64);
+                timing.time_to_first_chunk_ms = None;
+                info!("Request successful on attempt {}", attempt);
+                if let Some(log) = request_log {
+                    let headers: Vec<String> = response.headers().iter()
+                        .map(|(name, value)| format!("{}: {}", name, value.to_str().unwrap_or("<binary>")))
+                        .collect();
+                    log.section("Response", &format!("Status: {}\n{}", response.status(), headers.join("\n")));
+                }
+
+                if response.status().is_success() {
+                    // Handle streaming response
+                    use futures_util::StreamExt;
+
+                    let mut stream = response.bytes_stream();
+                    let mut full_content = String::new();
+                    let mut full_reasoning = String::new();
+                    let mut parser = SseParser::default();
+                    let mut last_clipboard_write = std::time::Instant::now();
+                    let mut clipboard_len = 0;
+                    let mut done = false;
+
+                    while !done {
+                        let events = match stream.next().await {
+                            Some(chunk) => {
+                                let chunk = chunk.map_err(|e| RequestError::Request(format!("Failed to read chunk: {}", e)))?;
+                                if timing.time_to_first_chunk_ms.is_none() {
+                                    timing.time_to_first_chunk_ms = Some(sent_at.elapsed().as_millis() as u64);
+                                }
+                                if let Some(log) = request_log {
+                                    log.append(&String::from_utf8_lossy(&chunk));
+                                }
+                                parser.push(&chunk)
+                            }
+                            None => {
+                                done = true;
+                                parser.finish()
+                            }
+                        };
+
+                        for data in events {
+                            if data == "[DONE]" {
+                                done = true;
+                                break;
+                            }
+                            if let Some(delta) = sse_delta(&data) {
+                                full_content.push_str(&delta.content);
+                                full_reasoning.push_str(&delta.reasoning);
+                            }
+                        }
+
+                        // 节流写入剪贴板，让部分结果可以提前粘贴；完整结果由输出阶段写入
+                        if stream_to_clipboard
+                            && full_content.len() > clipboard_len
+                            && last_clipboard_write.elapsed() >= CLIPBOARD_STREAM_INTERVAL
+                        {
+                            match Clipboard::new().and_then(|mut clipboard| clipboard.set_text(full_content.clone())) {
+                                Ok(()) => {
+                                    if let Some(state) = app_handle.and_then(|handle| handle.try_state::<AppState>()) {
+                                        *state.partial_clipboard_text.lock().unwrap_or_else(|e| e.into_inner()) = Some(full_content.clone());
+                                    }
+                                }
+                                Err(e) => warn!("Failed to write partial result to clipboard: {}", e),
+                            }
+                            clipboard_len = full_content.len();
+                            last_clipboard_write = std::time::Instant::now();
+                        }
+                    }
+
+                    if let Some(log) = request_log {
+                        log.section("Stream finished", &format!(
+                            "content: {} chars, reasoning: {} chars",
+                            full_content.chars().count(), full_reasoning.chars().count()
+                        ));
+                    }
+                    return combine_reasoning(full_reasoning, full_content, include_reasoning).map_err(RequestError::Request);
+                } else {
+                    let status = response.status();
+                    let retry_after = response.headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| parse_retry_after(v, chrono::Utc::now()));
+                    let error_text = response.text().await.unwrap_or_default();
+                    if let Some(log) = request_log {
+                        log.section("Error body", &error_text);
+                    }
+                    let error = format!("Analysis failed with status {}: {}", status, error_text);
+
+                    let rate_limited = status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status == reqwest::StatusCode::SERVICE_UNAVAILABLE;
+                    if rate_limited && rate_limit_retries < MAX_RATE_LIMIT_RETRIES {
+                        let wait = retry_after.unwrap_or(DEFAULT_RATE_LIMIT_BACKOFF).min(MAX_RETRY_AFTER);
+                        rate_limit_retries += 1;
+                        timing.rate_limit_retries = rate_limit_retries;
+                        last_error = error;
+                        info!("Rate limited ({}), retrying in {:.1}s", status, wait.as_secs_f64());
+                        tokio::time::sleep(wait).await;
+                        continue;
+                    }
+
+                    return Err(if status.is_server_error() {
+                        RequestError::Endpoint(error)
+                    } else {
+                        RequestError::Request(error)
+                    });
+                }
+            }
+            Err(e) if e.is_connect() && fail_fast_on_connect => {
+                let error = format!("Could not connect to {}: {}", url, e);
+                warn!("{}, switching to the next endpoint", error);
+                if let Some(log) = request_log {
+                    log.section("Request error", &error);
+                }
+                return Err(RequestError::Connect(error));
+            }
+            Err(e) => {
+                last_error = format!("Request failed: {}", e);
+                warn!("Attempt {} failed: {}", attempt, last_error);
+                if let Some(log) = request_log {
+                    log.section("Request error", &last_error);
+                }
+
+                // 超时多半是图片太大：重试前缩小图片，而不是原样重发
+                if e.is_timeout() && attempt < 3 && timeout_shrinks < MAX_TIMEOUT_SHRINK_STEPS {
+                    timeout_shrinks += 1;
+                    if shrink_payload_images(&mut payload) {
+                        info!("Request timed out, retrying with smaller images (step {}/{})", timeout_shrinks, MAX_TIMEOUT_SHRINK_STEPS);
+                        if let Some(log) = request_log {
+                            log.section("Timeout shrink", &format!("Images recompressed (step {}/{})", timeout_shrinks, MAX_TIMEOUT_SHRINK_STEPS));
+                        }
+                    }
+                }
+
+                if attempt < 3 {
+                    info!("Retrying in 2 seconds...");
+                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                }
+            }
+        }
+
+        attempt += 1;
+    }
+
+    Err(RequestError::Endpoint(format!("All 3 attempts failed. Last error: {}", last_error)))
+}
+
+// 超时后最多缩小图片的次数，每次字节数减半
+const MAX_TIMEOUT_SHRINK_STEPS: u32 = 2;
+
+// 把payload中所有data URL图片按一半的字节数重新压缩；返回是否有图片被缩小
+// 已经无法再缩小的图片保持原样
+fn shrink_payload_images(payload: &mut serde_json::Value) -> bool {
+    let mut shrunk = false;
+    let Some(messages) = payload.get_mut("messages").and_then(|m| m.as_array_mut()) else {
+        return false;
+    };
+    let parts = messages.iter_mut()
+        .filter_map(|message| message.get_mut("content").and_then(|c| c.as_array_mut()))
+        .flatten();
+    for part in parts {
+        let Some(url) = part.pointer_mut("/image_url/url") else {
+            continue;
+        };
+        let Some(image_data) = url.as_str().filter(|u| u.starts_with("data:")).map(str::to_string) else {
+            continue;
+        };
+        match shrink_image_data_url(&image_data, image_data.len() / 2) {
+            Ok(smaller) => {
+                debug!("Shrunk image from {} to {} bytes after timeout", image_data.len(), smaller.len());
+                *url = serde_json::Value::String(smaller);
+                shrunk = true;
+            }
+            Err(e) => warn!("Could not shrink image further: {}", e),
+        }
+    }
+    shrunk
+}
+
+// Server-Sent Events解析器（按SSE规范）：
+// - 行结束符可以是 \n、\r\n 或单独的 \r，跨chunk拆开的 \r\n 也能正确处理
+// - 按字节缓冲，避免多字节UTF-8字符被拆到两个chunk时出现乱码
+// - 空行分发事件；多个data行用 \n 拼接为同一事件；以 : 开头的注释行（keep-alive）忽略
+// - 兼容不发送空行分隔的服务商：已缓存的data是完整JSON时，新的data行先分发上一个事件
+#[derive(Debug, Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    data: String,
+    has_data: bool,
+}
+
+impl SseParser {
+    // 追加一个chunk，返回其中已完整的事件data
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.buffer.len() {
+            let next = match self.buffer[i] {
+                b'\n' => i + 1,
+                b'\r' => match self.buffer.get(i + 1) {
+                    Some(b'\n') => i + 2,
+                    Some(_) => i + 1,
+                    // \r 在末尾：等下一个chunk确认是否为 \r\n
+                    None => break,
+                },
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let line = String::from_utf8_lossy(&self.buffer[start..i]).into_owned();
+            self.process_line(&line, &mut events);
+            start = next;
+            i = next;
+        }
+        self.buffer.drain(..start);
+        events
+    }
+
+    // 流结束：处理没有换行结尾的最后一行，并分发未完成的事件
+    fn finish(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        let rest = std::mem::take(&mut self.buffer);
+        let line = String::from_utf8_lossy(&rest);
+        let line = line.trim_end_matches('\r');
+        if !line.is_empty() {
+            self.process_line(line, &mut events);
+        }
+        self.dispatch(&mut events);
+        events
+    }
+
+    fn process_line(&mut self, line: &str, events: &mut Vec<String>) {
+        if line.is_empty() {
+            self.dispatch(events);
+            return;
+        }
+        if line.starts_with(':') {
+            return;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        // event/id/retry 字段对chat completions流没有意义
+        if field != "data" {
+            return;
+        }
+
+        if self.has_data && (self.data == "[DONE]" || serde_json::from_str::<serde::de::IgnoredAny>(&self.data).is_ok()) {
+            self.dispatch(events);
+        }
+        if self.has_data {
+            self.data.push('\n');
+        }
+        self.data.push_str(value);
+        self.has_data = true;
+    }
+
+    fn dispatch(&mut self, events: &mut Vec<String>) {
+        if self.has_data {
+            events.push(std::mem::take(&mut self.data));
+            self.has_data = false;
+        }
+    }
+}
+
+// chat completions流中一个事件的增量：答案和推理过程分开收集
+#[derive(Debug, Default, PartialEq)]
+struct SseDelta {
+    content: String,
+    reasoning: String,
+}
+
+// 推理过程的字段名因服务商而异：reasoning_content (DeepSeek等) 或 reasoning (OpenRouter等)
+fn sse_delta(data: &str) -> Option<SseDelta> {
+    let json = serde_json::from_str::<serde_json::Value>(data).ok()?;
+    let delta = json.get("choices")?.as_array()?.first()?.get("delta")?;
+    let field = |name: &str| delta.get(name).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+
+    let reasoning = match field("reasoning_content") {
+        reasoning if reasoning.is_empty() => field("reasoning"),
+        reasoning => reasoning,
+    };
+    Some(SseDelta { content: field("content"), reasoning })
+}
+
+// few-shot示例转换为不带图片的user/assistant消息对
+fn few_shot_messages(examples: &[(String, String)]) -> Vec<serde_json::Value> {
+    examples.iter()
+        .flat_map(|(input, output)| [
+            serde_json::json!({ "role": "user", "content": input }),
+            serde_json::json!({ "role": "assistant", "content": output }),
+        ])
+        .collect()
+}
+
+// 不支持多轮对话时把few-shot示例追加到system prompt后面；没有示例时原样返回
+fn inline_few_shot(system_prompt: Option<&str>, examples: &[(String, String)]) -> Option<String> {
+    if examples.is_empty() {
+        return system_prompt.map(str::to_string);
+    }
+    let mut text = system_prompt.map(|p| format!("{}\n\n", p.trim_end())).unwrap_or_default();
+    text.push_str("Examples:");
+    for (input, output) in examples {
+        text.push_str(&format!("\n\nInput:\n{}\nOutput:\n{}", input.trim(), output.trim()));
+    }
+    Some(text)
+}
+
+// 默认只返回答案；include_reasoning时把推理过程放在 <think> 块中
+// 只收到推理过程没有答案时（部分模型把答案也写在推理字段里）退回推理内容，避免结果为空
+fn combine_reasoning(reasoning: String, content: String, include_reasoning: bool) -> Result<String, String> {
+    let reasoning = reasoning.trim();
+    match (reasoning.is_empty(), content.trim().is_empty()) {
+        (true, true) if content.is_empty() => Err("No content received from stream".to_string()),
+        (true, true) => Err("Model returned no usable content (whitespace only)".to_string()),
+        (true, false) => Ok(content),
+        (false, true) => {
+            warn!("Stream contained only reasoning content ({} chars), using it as the result", reasoning.len());
+            Ok(reasoning.to_string())
+        }
+        (false, false) if include_reasoning => Ok(format!("<think>\n{}\n</think>\n\n{}", reasoning, content)),
+        (false, false) => {
+            debug!("Discarding {} chars of reasoning content", reasoning.len());
+            Ok(content)
+        }
+    }
+}
+
+#[cfg(feature = "tesseract")]
+fn run_local_ocr(image_data: &str, language: &str) -> Result<String, String> {
+    let base64_part = image_data.split_once(',').map(|(_, data)| data).unwrap_or(image_data);
+    let bytes = general_purpose::STANDARD.decode(base64_part)
+        .map_err(|e| format!("Failed to decode image data: {}", e))?;
+
+    let mut tesseract = leptess::LepTess::new(None, language)
+        .map_err(|e| format!("Failed to initialize tesseract (language '{}'): {}", language, e))?;
+    tesseract.set_image_from_mem(&bytes)
+        .map_err(|e| format!("Failed to load image for OCR: {}", e))?;
+    tesseract.get_utf8_text()
+        .map_err(|e| format!("Local OCR failed: {}", e))
+}
+
+#[cfg(not(feature = "tesseract"))]
+fn run_local_ocr(_image_data: &str, _language: &str) -> Result<String, String> {
+    Err("Local OCR is not available: MathImage was built without the 'tesseract' feature".to_string())
+}
+
+const MOCK_ANALYSIS_RESULT: &str = "Mock analysis result\n\n$$\nE = mc^2\n$$\n\nInline math: $a^2 + b^2 = c^2$.";
+
+fn mock_mode_from_env() -> bool {
+    std::env::var("MATHIMAGE_MOCK").map_or(false, |v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+// 模拟一次流式请求：发送与真实请求相同的事件，短暂延迟后返回固定结果
+async fn mock_analysis_response(app_handle: Option<&tauri::AppHandle>, prompt: Option<&str>) -> Result<String, String> {
+    info!("🧪 [MOCK] Returning canned analysis result (prompt: {:?})", prompt);
+
+    if let Some(handle) = app_handle {
+        let _ = handle.emit("request_started", RequestStartedEvent { url: "mock://analysis".to_string() });
+    }
+
+    // 模拟流式响应的延迟
+    for _ in 0..3 {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    Ok(MOCK_ANALYSIS_RESULT.to_string())
+}
+
+// 请求日志目录（配置目录下），每次分析一个文件
+const REQUEST_LOG_DIR: &str = "requests";
+// 日志中图片data URL只保留开头，完整的base64动辄数MB且对排查问题没有帮助
+const REQUEST_LOG_IMAGE_PREFIX_CHARS: usize = 64;
+
+// 把文本中的API key和所有Bearer token替换掉，保证密钥不会写入磁盘
+fn redact_api_key(text: &str, api_key: &str) -> String {
+    const REDACTED: &str = "[REDACTED]";
+
+    let api_key = api_key.trim();
+    let text = if api_key.is_empty() {
+        text.to_string()
+    } else {
+        text.replace(api_key, REDACTED)
+    };
+
+    // 兜底：服务商在错误信息中回显的其他token
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(index) = rest.find("Bearer ") {
+        let (before, after) = rest.split_at(index + "Bearer ".len());
+        redacted.push_str(before);
+        let token_len = after.find(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == ',').unwrap_or(after.len());
+        if token_len > 0 {
+            redacted.push_str(REDACTED);
+        }
+        rest = &after[token_len..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+// 日志中的payload：截短图片data URL
+fn payload_for_log(payload: &serde_json::Value) -> serde_json::Value {
+    match payload {
+        serde_json::Value::String(s) if s.starts_with("data:") && s.len() > REQUEST_LOG_IMAGE_PREFIX_CHARS => {
+            let prefix: String = s.chars().take(REQUEST_LOG_IMAGE_PREFIX_CHARS).collect();
+            serde_json::Value::String(format!("{}... ({} chars)", prefix, s.len()))
+        }
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.iter().map(payload_for_log).collect()),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter().map(|(k, v)| (k.clone(), payload_for_log(v))).collect()
+        ),
+        other => other.clone(),
+    }
+}
+
+// Profile开启request_log时记录一次分析的请求和原始响应，写入前统一脱敏
+struct RequestLog {
+    path: PathBuf,
+    api_key: String,
+}
+
+impl RequestLog {
+    fn create(api_key: &str) -> Result<Self, String> {
+        let dir = AppState::get_config_dir()?.join(REQUEST_LOG_DIR);
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create request log directory: {}", e))?;
+
+        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f");
+        let path = unique_file_path(&dir, &format!("{}.log", timestamp));
+        fs::File::create(&path).map_err(|e| format!("Failed to create request log: {}", e))?;
+        info!("📝 Logging request to {:?}", path);
+        Ok(Self { path, api_key: api_key.to_string() })
+    }
+
+    fn append(&self, text: &str) {
+        use std::io::Write;
+
+        let result = fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(redact_api_key(text, &self.api_key).as_bytes()));
+        if let Err(e) = result {
+            warn!("⚠️ Failed to write request log: {}", e);
+        }
+    }
+
+    fn section(&self, title: &str, body: &str) {
+        self.append(&format!("\n===== {} [{}] =====\n{}\n", title, chrono::Local::now().format("%H:%M:%S%.3f"), body));
+    }
+}
+
+// 429/503 的额外重试次数，以及Retry-After缺失时的默认等待和最长等待
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+const DEFAULT_RATE_LIMIT_BACKOFF: std::time::Duration = std::time::Duration::from_secs(5);
+const MAX_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(60);
+
+// Retry-After 可以是秒数或HTTP-date（如 "Wed, 21 Oct 2015 07:28:00 GMT"）
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<std::time::Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // 已经过去的时间点表示可以立即重试
+    Some((date.with_timezone(&chrono::Utc) - now).to_std().unwrap_or_default())
+}
+
+#[tauri::command]
+async fn analyze_image(
+    image_data: String,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    // 外部图片按EXIF方向摆正；截图不带EXIF，不受影响
+    let image_data = orient_image_data_url(image_data);
+    analyze_image_internal(image_data, state, None).await
+}
+
+// 用新的prompt（或Profile的prompt）重新分析最近一次截图
+#[tauri::command]
+async fn reanalyze_last(state: State<'_, AppState>, prompt: Option<String>) -> Result<String, String> {
+    let image_data = state.last_capture.lock().await.clone()
+        .ok_or("No previous capture to re-analyze")?;
+
+    let active_profile = state.get_active_profile().await?;
+    let result = analyze_image_tiled(image_data, state, prompt, None).await?;
+    Ok(apply_output_transforms(&active_profile, &result))
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct FolderAnalysisProgressEvent {
+    index: usize,
+    total: usize,
+    file: String,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FolderAnalysisSummary {
+    pub output_path: String,
+    pub total: usize,
+    pub succeeded: usize,
+    // (文件名, 错误信息)
+    pub failed: Vec<(String, String)>,
+}
+
+fn image_mime_type(path: &std::path::Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "webp" => Some("image/webp"),
+        "gif" => Some("image/gif"),
+        "bmp" => Some("image/bmp"),
+        _ => None,
+    }
+}
+
+fn image_file_to_data_url(path: &std::path::Path) -> Result<String, String> {
+    const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+    let metadata = fs::metadata(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    if metadata.len() > MAX_FILE_SIZE {
+        return Err("Image file too large".to_string());
+    }
+
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    if let Some(oriented) = apply_exif_orientation(&bytes)? {
+        return Ok(image_bytes_to_data_url(&oriented));
+    }
+    // 扩展名只用于筛选文件，MIME以实际内容为准（如扩展名为.png的JPEG）
+    Ok(image_bytes_to_data_url(&bytes))
+}
+
+// 读取EXIF Orientation (1-8)，没有EXIF或读取失败时返回None
+fn exif_orientation(bytes: &[u8]) -> Option<u32> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(bytes))
+        .ok()?;
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+// 按EXIF Orientation旋转/翻转，使图片正向显示
+fn orient_image(img: image::DynamicImage, orientation: u32) -> image::DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        // 5: 沿主对角线翻转 (transpose)
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        // 7: 沿副对角线翻转 (transverse)
+        7 => img.rotate90().flipv(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// 外部图片（剪贴板/文件）可能带有EXIF旋转信息，截图本身总是正向的
+// 需要旋转时返回重新编码的PNG，否则返回None表示原图可直接使用
+fn apply_exif_orientation(bytes: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let orientation = match exif_orientation(bytes) {
+        Some(orientation) if (2..=8).contains(&orientation) => orientation,
+        _ => return Ok(None),
+    };
+
+    let img = image::load_from_memory(bytes)
+        .map_err(|e| format!("Failed to decode image: {}", e))?;
+    let oriented = orient_image(img, orientation);
+
+    let mut buffer = Vec::new();
+    oriented
+        .write_to(&mut std::io::Cursor::new(&mut buffer), image::ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    debug!("🔄 Applied EXIF orientation {}", orientation);
+    Ok(Some(buffer))
+}
+
+// 对data URL形式的外部图片应用EXIF方向，解析失败时原样返回
+fn orient_image_data_url(image_data: String) -> String {
+    let base64_part = image_data.split_once(',').map(|(_, data)| data).unwrap_or(&image_data);
+    let bytes = match general_purpose::STANDARD.decode(base64_part) {
+        Ok(bytes) => bytes,
+        Err(_) => return image_data,
+    };
+
+    match apply_exif_orientation(&bytes) {
+        Ok(Some(oriented)) => image_bytes_to_data_url(&oriented),
+        Ok(None) => image_data,
+        Err(e) => {
+            warn!("Failed to apply EXIF orientation, sending original: {}", e);
+            image_data
+        }
+    }
+}
+
+// Windows的CF_DIB是不带BITMAPFILEHEADER的BMP，补上14字节文件头后交给image解码
+// 不是合法的BITMAPINFOHEADER时返回None
+fn dib_to_bmp(dib: &[u8]) -> Option<Vec<u8>> {
+    const FILE_HEADER_SIZE: u32 = 14;
+    const BI_BITFIELDS: u32 = 3;
+
+    let read_u32 = |offset: usize| dib.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let read_u16 = |offset: usize| dib.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+
+    let header_size = read_u32(0)?;
+    // BITMAPCOREHEADER(12) / BITMAPINFOHEADER(40) / V2(52) / V3(56) / V4(108) / V5(124)
+    if ![12, 40, 52, 56, 108, 124].contains(&header_size) {
+        return None;
+    }
+
+    let (bit_count, compression, colors_used, palette_entry_size) = if header_size == 12 {
+        (read_u16(10)? as u32, 0, 0, 3)
+    } else {
+        (read_u16(14)? as u32, read_u32(16)?, read_u32(32)?, 4)
+    };
+    let palette_entries = match colors_used {
+        0 if bit_count <= 8 => 1u32 << bit_count,
+        n => n,
+    };
+    // BITMAPINFOHEADER + BI_BITFIELDS 时颜色掩码紧跟在头后面
+    let masks_size = if header_size == 40 && compression == BI_BITFIELDS { 12 } else { 0 };
+
+    let pixel_offset = FILE_HEADER_SIZE
+        .checked_add(header_size)?
+        .checked_add(masks_size)?
+        .checked_add(palette_entries.checked_mul(palette_entry_size)?)?;
+    let file_size = FILE_HEADER_SIZE.checked_add(u32::try_from(dib.len()).ok()?)?;
+    if pixel_offset > file_size {
+        return None;
+    }
+
+    let mut bmp = Vec::with_capacity(file_size as usize);
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&file_size.to_le_bytes());
+    bmp.extend_from_slice(&[0; 4]);
+    bmp.extend_from_slice(&pixel_offset.to_le_bytes());
+    bmp.extend_from_slice(dib);
+    Some(bmp)
+}
+
+// 按内容猜测格式并解码已编码的图片字节（PNG/JPEG/BMP/DIB/WebP等），失败时错误中带上具体格式
+fn decode_clipboard_image_bytes(bytes: &[u8]) -> Result<image::DynamicImage, String> {
+    if let Ok(format) = image::guess_format(bytes) {
+        let img = image::load_from_memory_with_format(bytes, format)
+            .map_err(|e| format!("Unsupported clipboard image format {:?}: {}", format, e))?;
+        return Ok(match exif_orientation(bytes) {
+            Some(orientation) => orient_image(img, orientation),
+            None => img,
+        });
+    }
+
+    if let Some(bmp) = dib_to_bmp(bytes) {
+        return image::load_from_memory_with_format(&bmp, image::ImageFormat::Bmp)
+            .map_err(|e| format!("Unsupported clipboard image format DIB: {}", e));
+    }
+
+    let signature: Vec<String> = bytes.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+    Err(format!("Unrecognized clipboard image format (leading bytes: {})", signature.join(" ")))
+}
+
+// arboard通常返回RGBA像素；尺寸对不上时说明拿到的是编码后的数据（部分平台/应用），按格式解码
+fn clipboard_image_data_to_image(data: arboard::ImageData) -> Result<image::DynamicImage, String> {
+    let expected_len = data.width.checked_mul(data.height).and_then(|pixels| pixels.checked_mul(4));
+    if expected_len == Some(data.bytes.len()) {
+        let rgba = image::RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned())
+            .ok_or("Invalid clipboard image dimensions")?;
+        return Ok(image::DynamicImage::ImageRgba8(rgba));
+    }
+
+    debug!("📋 Clipboard image is not raw RGBA ({} bytes for {}x{}), guessing format",
+        data.bytes.len(), data.width, data.height);
+    decode_clipboard_image_bytes(&data.bytes)
+}
+
+// 剪贴板中没有位图时，从文本内容中找图片：data URL或图片文件路径（如从文件管理器复制）
+fn clipboard_text_to_image_bytes(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    if let Some(rest) = text.strip_prefix("data:image/") {
+        let (_, base64_part) = rest.split_once(";base64,")?;
+        return general_purpose::STANDARD.decode(base64_part.trim()).ok();
+    }
+
+    let path = std::path::Path::new(text.strip_prefix("file://").unwrap_or(text));
+    image_mime_type(path)?;
+    fs::read(path).ok()
+}
+
+// 读取剪贴板图片并统一转换为PNG data URL
+fn read_clipboard_image() -> Result<String, String> {
+    let mut clipboard = Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?;
+
+    let img = match clipboard.get_image() {
+        Ok(data) => clipboard_image_data_to_image(data)?,
+        Err(image_error) => {
+            let bytes = clipboard.get_text().ok()
+                .and_then(|text| clipboard_text_to_image_bytes(&text))
+                .ok_or_else(|| format!("Clipboard does not contain an image: {}", image_error))?;
+            decode_clipboard_image_bytes(&bytes)?
+        }
+    };
+
+    let mut buffer = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut buffer), image::ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    debug!("📋 Read {}x{} image from clipboard", img.width(), img.height());
+    Ok(image_bytes_to_data_url(&buffer))
+}
+
+// 分析剪贴板中的图片（从其他应用复制的截图、图片、图片文件）
+#[tauri::command]
+async fn analyze_clipboard_image(state: State<'_, AppState>) -> Result<String, String> {
+    let image_data = read_clipboard_image()?;
+    analyze_image_internal(image_data, state, None).await
+}
+
+// 批量分析文件夹中的图片，结果写入文件夹旁边的 <文件夹名>.md
+// 逐个顺序处理（复用重试/超时逻辑），单个文件失败不影响其余文件
+#[tauri::command]
+async fn analyze_folder(app_handle: tauri::AppHandle, state: State<'_, AppState>, path: String, prompt: Option<String>) -> Result<FolderAnalysisSummary, String> {
+    let folder = PathBuf::from(&path);
+    if !folder.is_dir() {
+        return Err(format!("Not a folder: {}", path));
+    }
+
+    let mut images: Vec<PathBuf> = fs::read_dir(&folder)
+        .map_err(|e| format!("Failed to read folder: {}", e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .filter(|p| image_mime_type(p).is_some())
+        .collect();
+    images.sort();
+
+    if images.is_empty() {
+        return Err(format!("No image files found in {}", path));
+    }
+
+    let folder_name = folder.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("images")
+        .to_string();
+    let output_path = folder.parent()
+        .unwrap_or(&folder)
+        .join(format!("{}.md", folder_name));
+
+    debug!("🔧 Analyzing {} images in {}", images.len(), path);
+
+    let active_profile = state.get_active_profile().await?;
+    let total = images.len();
+    let mut markdown = format!("# {}\n", folder_name);
+    let mut succeeded = 0;
+    let mut failed = Vec::new();
+
+    for (index, image_path) in images.iter().enumerate() {
+        let file_name = image_path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let result = match image_file_to_data_url(image_path) {
+            Ok(image_data) => {
+                let image_data = preprocess_image_data_url(&image_data, &active_profile.preprocess).unwrap_or_else(|e| {
+                    warn!("Image preprocessing failed for {}, sending original: {}", file_name, e);
+                    image_data
+                });
+                analyze_image_tiled(image_data, state.clone(), prompt.clone(), None).await
+                    .map(|text| apply_output_transforms(&active_profile, &text))
+            }
+            Err(e) => Err(e),
+        };
+
+        let error = match result {
+            Ok(text) => {
+                markdown.push_str(&format!("\n## {}\n\n{}\n", file_name, text.trim()));
+                succeeded += 1;
+                None
+            }
+            Err(e) => {
+                error!("Failed to analyze {}: {}", file_name, e);
+                markdown.push_str(&format!("\n## {}\n\n> Analysis failed: {}\n", file_name, e));
+                failed.push((file_name.clone(), e.clone()));
+                Some(e)
+            }
+        };
+
+        let _ = app_handle.emit("folder_analysis_progress", FolderAnalysisProgressEvent {
+            index: index + 1,
+            total,
+            file: file_name,
+            error,
+        });
+    }
+
+    fs::write(&output_path, markdown)
+        .map_err(|e| format!("Failed to write results to {:?}: {}", output_path, e))?;
+
+    debug!("✅ Folder analysis finished: {}/{} succeeded, results in {:?}", succeeded, total, output_path);
+    Ok(FolderAnalysisSummary {
+        output_path: output_path.to_string_lossy().to_string(),
+        total,
+        succeeded,
+        failed,
+    })
+}
+
+#[tauri::command]
+async fn preview_output_transforms(state: State<'_, AppState>, sample: String) -> Result<String, String> {
+    let active_profile = state.get_active_profile().await?;
+    Ok(apply_output_transforms(&active_profile, &sample))
+}
+
+#[tauri::command]
+async fn copy_to_clipboard(text: String) -> Result<(), String> {
+    let mut clipboard = Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?;
+    clipboard.set_text(text).map_err(|e| format!("Failed to copy to clipboard: {}", e))?;
+    info!("Text copied to clipboard");
+    Ok(())
+}
+
+// 把文本追加到剪贴板已有文本之后；剪贴板为空或不是文本（如图片）时直接写入
+async fn append_to_clipboard(text: String, separator: &str) -> Result<(), String> {
+    let mut clipboard = Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?;
+    let combined = match clipboard.get_text() {
+        Ok(existing) if !existing.is_empty() => format!("{}{}{}", existing, separator, text),
+        Ok(_) => text,
+        Err(e) => {
+            info!("Clipboard has no text content ({}), replacing it", e);
+            text
+        }
+    };
+    clipboard.set_text(combined).map_err(|e| format!("Failed to copy to clipboard: {}", e))?;
+    info!("Text appended to clipboard");
+    Ok(())
+}
+
+// 同时复制截图和文本：先通过arboard的图片剪贴板写入原始截图，再写入文本。
+// 纯文本目标直接粘贴文字，带剪贴板历史的系统（Win+V、剪贴板管理器）里还能选到图片。
+// 平台不支持图片剪贴板时退化为只复制文本
+async fn copy_image_and_text_to_clipboard(image_data_url: &str, text: String) -> Result<(), String> {
+    let mut clipboard = Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?;
+
+    let image = image_data_url_to_clipboard_image(image_data_url)
+        .and_then(|image| clipboard.set_image(image).map_err(|e| e.to_string()));
+    match image {
+        Ok(()) => info!("Original capture copied to image clipboard"),
+        Err(e) => info!("Image clipboard not supported ({}), copying text only", e),
+    }
+
+    clipboard.set_text(text).map_err(|e| format!("Failed to copy to clipboard: {}", e))?;
+    info!("Text copied to clipboard");
+    Ok(())
+}
+
+// data URL解码为arboard需要的RGBA像素
+fn image_data_url_to_clipboard_image(image_data_url: &str) -> Result<arboard::ImageData<'static>, String> {
+    let base64_part = image_data_url.split_once(',').map(|(_, data)| data).unwrap_or(image_data_url);
+    let bytes = general_purpose::STANDARD.decode(base64_part)
+        .map_err(|e| format!("Failed to decode image data: {}", e))?;
+    let rgba = image::load_from_memory(&bytes)
+        .map_err(|e| format!("Failed to decode image: {}", e))?
+        .to_rgba8();
+
+    Ok(arboard::ImageData {
+        width: rgba.width() as usize,
+        height: rgba.height() as usize,
+        bytes: std::borrow::Cow::Owned(rgba.into_raw()),
+    })
+}
+
+// 模拟键盘输入文本到当前焦点窗口
+async fn type_text(text: String) -> Result<(), String> {
+    use enigo::{Enigo, Keyboard, Settings};
+
+    tokio::task::spawn_blocking(move || {
+        let mut enigo = Enigo::new(&Settings::default())
+            .map_err(|e| format!("Failed to initialize keyboard simulation: {}", e))?;
+        enigo.text(&text)
+            .map_err(|e| format!("Failed to type text: {}", e))?;
+        info!("Typed {} chars into the focused window", text.chars().count());
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("Typing task failed: {}", e))?
+}
+
+#[tauri::command]
+async fn show_system_dialog(title: String, message: String, dialog_type: String) -> Result<(), String> {
+    use std::process::Command;
+
+    info!("Showing system dialog: {} - {}", title, message);
+
+    // Determine the icon based on dialog type
+    let icon = match dialog_type.as_str() {
+        "error" => "stop",
+        "warning" => "caution", 
+        "info" => "note",
+        _ => "note",
+    };
+
+    // Use macOS osascript to show system dialog
+    let script = format!(
+        r#"display dialog "{}" with title "{}" with icon {} buttons {{"OK"}} default button "OK""#,
+        message.replace("\"", "\\\""),
+        title.replace("\"", "\\\""),
+        icon
+    );
+
+    debug!("AppleScript: {}", script);
+
+    let output = Command::new("osascript")
+        .arg("-e")
+        .arg(&script)
+        .output()
+        .map_err(|e| format!("Failed to show dialog: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!("osascript error: {}", stderr);
+        return Err(format!("Failed to show system dialog: {}", stderr));
+    }
+
+    info!("System dialog shown successfully");
+    Ok(())
+}
+
+#[tauri::command]
+async fn play_system_sound(state: State<'_, AppState>) -> Result<(), String> {
+    let custom_path = state.config.lock().await.success_sound_path.clone();
+
+    // Play custom sound, or macOS system sound (Glass)
+    play_sound_file(&sound_path_or_default(&custom_path, DEFAULT_SUCCESS_SOUND))
+        .map_err(|e| format!("Failed to play system sound: {}", e))
+}
+
+#[tauri::command]
+async fn play_error_sound(state: State<'_, AppState>) -> Result<(), String> {
+    let custom_path = state.config.lock().await.error_sound_path.clone();
+
+    // Play custom sound, or macOS system error sound (Basso)
+    play_sound_file(&sound_path_or_default(&custom_path, DEFAULT_ERROR_SOUND))
+        .map_err(|e| format!("Failed to play error sound: {}", e))
+}
+
+const DEFAULT_SUCCESS_SOUND: &str = "/System/Library/Sounds/Glass.aiff";
+const DEFAULT_ERROR_SOUND: &str = "/System/Library/Sounds/Basso.aiff";
+const SOUND_EXTENSIONS: &[&str] = &["aiff", "aif", "aifc", "wav", "mp3", "m4a", "aac", "caf"];
+
+fn play_sound_file(path: &str) -> Result<(), String> {
+    use std::process::Command;
+
+    let output = Command::new("afplay")
+        .arg(path)
+        .output()
+        .map_err(|e| format!("Failed to run afplay: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("afplay exited with {}", output.status));
+    }
+
+    Ok(())
+}
+
+// 自定义音效未设置或文件已被删除时回退到默认音效
+fn sound_path_or_default(custom_path: &str, default_path: &str) -> String {
+    if !custom_path.is_empty() && std::path::Path::new(custom_path).is_file() {
+        custom_path.to_string()
+    } else {
+        default_path.to_string()
+    }
+}
+
+// 保存时校验：空字符串表示使用默认音效，否则必须是存在的音频文件
+fn validate_sound_path(path: &str) -> Result<(), String> {
+    if path.is_empty() {
+        return Ok(());
+    }
+
+    let path_ref = std::path::Path::new(path);
+    if !path_ref.is_file() {
+        return Err(format!("Sound file not found: {}", path));
+    }
+
+    let extension = path_ref.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    if !SOUND_EXTENSIONS.contains(&extension.as_str()) {
+        return Err(format!("Unsupported sound file type '{}', expected one of: {}", path, SOUND_EXTENSIONS.join(", ")));
+    }
+
+    Ok(())
+}
+
+fn format_hotkey_for_display(hotkey: &str) -> String {
+    format_hotkey_for_platform(hotkey, cfg!(target_os = "macos"))
+}
+
+// 修饰键别名统一为规范名称：cmd / cmdorctrl / ctrl / alt / shift
+fn canonical_hotkey_modifier(token: &str) -> Option<&'static str> {
+    match token.to_lowercase().as_str() {
+        "cmd" | "command" | "super" | "meta" | "win" | "windows" => Some("cmd"),
+        "cmdorctrl" | "commandorcontrol" | "cmdorcontrol" | "commandorctrl" => Some("cmdorctrl"),
+        "ctrl" | "control" => Some("ctrl"),
+        "alt" | "option" | "opt" => Some("alt"),
+        "shift" => Some("shift"),
+        _ => None,
+    }
+}
+
+// 规范化热键字符串：修饰键按 cmd, cmdorctrl, ctrl, alt, shift 排序，全部小写，
+// 必须且只能有一个非修饰键，且整体能被快捷键解析器接受
+// 例如 "Shift+Cmd+M" -> "cmd+shift+m"
+fn normalize_hotkey(hotkey: &str) -> Result<String, String> {
+    const MODIFIER_ORDER: [&str; 5] = ["cmd", "cmdorctrl", "ctrl", "alt", "shift"];
+
+    let mut modifiers: Vec<&str> = Vec::new();
+    let mut key: Option<String> = None;
+
+    for token in hotkey.split('+').map(|t| t.trim()) {
+        if token.is_empty() {
+            return Err(format!("Invalid hotkey '{}': empty key", hotkey));
+        }
+        match canonical_hotkey_modifier(token) {
+            Some(modifier) => {
+                if !modifiers.contains(&modifier) {
+                    modifiers.push(modifier);
+                }
+            }
+            None => {
+                if let Some(existing) = &key {
+                    return Err(format!("Invalid hotkey '{}': multiple keys ('{}' and '{}')", hotkey, existing, token));
+                }
+                key = Some(token.to_lowercase());
+            }
+        }
+    }
+
+    let key = key.ok_or_else(|| format!("Invalid hotkey '{}': missing a non-modifier key", hotkey))?;
+    modifiers.sort_by_key(|m| MODIFIER_ORDER.iter().position(|o| o == m));
+
+    let normalized = modifiers.iter()
+        .map(|m| m.to_string())
+        .chain(std::iter::once(key))
+        .collect::<Vec<String>>()
+        .join("+");
+
+    // 未知按键名由解析器报错
+    normalized.parse::<Shortcut>()
+        .map_err(|e| format!("Invalid hotkey '{}': {}", hotkey, e))?;
+
+    Ok(normalized)
+}
+
+// 按键名显示：单个字符大写，KeyM/Digit1 去掉前缀，其余首字母大写
+fn format_hotkey_key(key: &str) -> String {
+    let key = key.trim();
+    let lower = key.to_lowercase();
+    let key = if key.is_ascii() && lower.len() == 4 && lower.starts_with("key") {
+        &key[3..]
+    } else if key.is_ascii() && lower.len() == 6 && lower.starts_with("digit") {
+        &key[5..]
+    } else {
+        key
+    };
+
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(|c| c.to_lowercase())).collect(),
+        None => String::new(),
+    }
+}
+
+// macOS 使用系统符号并按 ⌃⌥⇧⌘ 排序（如 "⇧⌘M"），其他平台显示 "Ctrl+Alt+Shift+Win+M"
+fn format_hotkey_for_platform(hotkey: &str, macos: bool) -> String {
+    let mut modifiers: Vec<&str> = Vec::new();
+    let mut keys: Vec<String> = Vec::new();
+
+    for token in hotkey.split('+').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        match canonical_hotkey_modifier(token) {
+            Some(modifier) => {
+                let display = match (modifier, macos) {
+                    ("ctrl", true) => "⌃",
+                    ("alt", true) => "⌥",
+                    ("shift", true) => "⇧",
+                    ("cmd", true) | ("cmdorctrl", true) => "⌘",
+                    ("ctrl", false) | ("cmdorctrl", false) => "Ctrl",
+                    ("alt", false) => "Alt",
+                    ("shift", false) => "Shift",
+                    _ => "Win",
+                };
+                if !modifiers.contains(&display) {
+                    modifiers.push(display);
+                }
+            }
+            None => keys.push(format_hotkey_key(token)),
+        }
+    }
+
+    let order: &[&str] = if macos {
+        &["⌃", "⌥", "⇧", "⌘"]
+    } else {
+        &["Ctrl", "Alt", "Shift", "Win"]
+    };
+    modifiers.sort_by_key(|m| order.iter().position(|o| o == m));
+
+    if macos {
+        format!("{}{}", modifiers.concat(), keys.join(""))
+    } else {
+        modifiers.into_iter().map(|m| m.to_string()).chain(keys).collect::<Vec<String>>().join("+")
+    }
+}
+
+#[tauri::command]
+async fn update_tray_model(app_handle: tauri::AppHandle, model_name: String) -> Result<(), String> {
+    // Update model submenu title and check selection without rebuilding the tray
+    update_model_submenu_title(&app_handle, &model_name).await?;
+    update_model_menu_selection(&app_handle, &model_name).await?;
+    Ok(())
+}
+
+async fn update_tray_menu(app_handle: tauri::AppHandle, model_name: Option<String>, sound_enabled: Option<bool>) -> Result<(), String> {
+    debug!("🔄 Updating tray menu in-place (no rebuild)...");
+    // Get current config
+    let app_state = app_handle.state::<AppState>();
+    let config = app_state.config.lock().await;
+    let current_config = config.clone();
+    drop(config);
+
+    // Update profile submenu title
+    if let Some(active_id) = &current_config.active_profile_id {
+        if let Some(profile) = current_config.profiles.iter().find(|p| &p.id == active_id) {
+            update_profile_submenu_title(&app_handle, &profile.name).await.ok();
+        }
+    }
+
+    // Update model submenu title and checked state
+    let model_display = if let Some(name) = model_name.clone() {
+        name
+    } else if let Some(active_id) = &current_config.active_profile_id {
+        if let Some(profile) = current_config.profiles.iter().find(|p| &p.id == active_id) {
+            if profile.api_config.model.is_empty() { "Not Selected".to_string() } else { profile.api_config.model.clone() }
+        } else { "Not Selected".to_string() }
+    } else { "Not Selected".to_string() };
+
+    update_model_submenu_title(&app_handle, &model_display).await.ok();
+    if let Some(name) = model_name {
+        update_model_menu_selection(&app_handle, &name).await.ok();
+    }
+
+    // Update hotkey display items
+    update_hotkey_menu_text(&app_handle, &current_config.global_hotkey, &current_config.switch_profile_hotkey).await.ok();
+
+    // Update sound menu item text
+    let sound_state = sound_enabled.unwrap_or(current_config.sound_enabled);
+    update_sound_menu_text(&app_handle, sound_state).await.ok();
+
+    Ok(())
+}
+
+
+const TRAY_ID: &str = "main";
+
+// 在托盘图标旁显示简短状态（macOS菜单栏标题），None清除
+fn set_tray_status(app_handle: &tauri::AppHandle, status: Option<&str>) {
+    if let Some(tray) = app_handle.tray_by_id(TRAY_ID) {
+        if let Err(e) = tray.set_title(status) {
+            error!("Failed to update tray status: {}", e);
+        }
+    }
+}
+
+fn create_tray_icon_with_menu(
+    app_handle: &tauri::AppHandle,
+    icon: tauri::image::Image<'_>,
+    menu: tauri::menu::Menu<tauri::Wry>,
+) -> Result<tauri::tray::TrayIcon, String> {
+    TrayIconBuilder::with_id(TRAY_ID)
+        .icon(icon)
+        .menu(&menu)
+        .show_menu_on_left_click(true)
+        .on_tray_icon_event(|tray, event| {
+            // Only log important events, not every mouse move
+            match event {
+                tauri::tray::TrayIconEvent::Click { .. } => {
+                    debug!("Tray icon clicked");
+                    update_last_status_item(tray.app_handle());
+                }
+                _ => {} // Don't log move, enter, leave events
+            }
+        })
+        .on_menu_event({
+            let app_handle_clone = app_handle.clone();
+            move |app, event| {
+                debug!("Tray menu event: {:?}", event.id());
+                match event.id().as_ref() {
+                    "settings" => {
+                        info!("Settings clicked - trying to show window");
+                        if let Some(webview_window) = app.get_webview_window("main") {
+                            restore_window_state(app.app_handle(), &webview_window);
+                            let _ = webview_window.show();
+                            let _ = webview_window.set_focus();
+                            info!("Window shown successfully");
+                        } else {
+                            warn!("Warning: No webview window named 'main' found");
+                        }
+                    }
+                    "open_config_dir" => {
+                        info!("Open config folder clicked from tray");
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = open_config_dir().await {
+                                error!("Failed to open config directory: {}", e);
+                            }
+                        });
+                    }
+                    "load_models" => {
+                        info!("Load models clicked from tray");
+                        let app_handle = app.app_handle().clone();
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = refresh_models_in_tray(app_handle).await {
+                                error!("Failed to refresh models: {}", e);
+                            }
+                        });
+                    }
+                    "filter_models" => {
+                        info!("Filter models clicked from tray");
+                        let app_handle = app.app_handle().clone();
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = prompt_model_filter(app_handle).await {
+                                error!("Failed to filter models: {}", e);
+                            }
+                        });
+                    }
+                    "toggle_sound" => {
+                        info!("Toggle sound clicked");
+                        let app_handle = app.app_handle().clone();
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = toggle_sound_setting(app_handle).await {
+                                error!("Failed to toggle sound: {}", e);
+                            }
+                        });
+                    }
+                    "toggle_hotkeys" => {
+                        info!("Toggle hotkeys clicked");
+                        let app_handle = app.app_handle().clone();
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = toggle_hotkeys_enabled(app_handle).await {
+                                error!("Failed to toggle hotkeys: {}", e);
+                            }
+                        });
+                    }
+                    "quit" => {
+                        info!("Quit clicked");
+                        let app_handle = app.app_handle().clone();
+                        tauri::async_runtime::spawn(async move {
+                            graceful_shutdown(app_handle).await;
+                        });
+                    }
+                    _ => {
+                        // Handle profile selection
+                        if event.id().as_ref().starts_with("profile_") {
+                            let profile_id = event.id().as_ref().strip_prefix("profile_").unwrap().to_string();
+                            info!("Profile selected from tray: {}", profile_id);
+
+                            let app_handle = app_handle_clone.clone();
+                            tauri::async_runtime::spawn(async move {
+                                match select_profile_in_tray(app_handle, profile_id.clone()).await {
+                                    Ok(()) => info!("Successfully selected profile: {}", profile_id),
+                                    Err(e) => error!("Failed to select profile {}: {}", profile_id, e),
+                                }
+                            });
+                        }
+                        // Handle prompt preset selection
+                        else if event.id().as_ref().starts_with("preset_") {
+                            let preset_key = event.id().as_ref().strip_prefix("preset_").unwrap().to_string();
+                            info!("Prompt preset selected from tray: {}", preset_key);
+
+                            let app_handle = app_handle_clone.clone();
+                            tauri::async_runtime::spawn(async move {
+                                if let Err(e) = select_preset_in_tray(app_handle, preset_key.clone()).await {
+                                    error!("Failed to select prompt preset {}: {}", preset_key, e);
+                                }
+                            });
+                        }
+                        // Handle model selection
+                        else if event.id().as_ref().starts_with("model_") {
+                            let model_id = event.id().as_ref().strip_prefix("model_").unwrap().to_string();
+                            info!("Model selected from tray: {}", model_id);
+
+                            let app_handle = app_handle_clone.clone();
+                            tauri::async_runtime::spawn(async move {
+                                match select_model_in_tray(app_handle, model_id.clone()).await {
+                                    Ok(()) => info!("Successfully selected model: {}", model_id),
+                                    Err(e) => error!("Failed to select model {}: {}", model_id, e),
+                                }
+                            });
+                        } else {
+                            info!("Unknown menu item: {:?}", event.id());
+                        }
+                    }
+                }
+            }
+        })
+        .build(app_handle)
+        .map_err(|e| format!("Failed to create tray icon: {}", e))
+}
+
+async fn select_profile_in_tray(app_handle: tauri::AppHandle, profile_id: String) -> Result<(), String> {
+    debug!("🔍 Selecting profile from tray: {}", profile_id);
+
+    let app_state = app_handle.state::<AppState>();
+
+    // Set the active profile
+    app_state.set_active_profile(profile_id.clone()).await?;
+
+    // Update profile CheckMenuItem selection (radio button behavior)
+    update_profile_menu_selection(&app_handle, &profile_id).await?;
+
+    // Get the new active profile for notification
+    let active_profile = app_state.get_active_profile().await?;
+
+    // Show profile switch notification
+    show_profile_switch_notification(&app_handle, &active_profile).await?;
+
+    // Update tray menu to reflect the new active profile name in submenu title
+    debug!("🔧 Updating profile submenu title...");
+    update_profile_submenu_title(&app_handle, &active_profile.name).await?;
+    refresh_profile_menus(&app_handle).await?;
+    schedule_model_refresh(&app_handle).await;
+
+    debug!("✅ Profile '{}' selected successfully from tray", active_profile.name);
+    Ok(())
+}
+
+async fn refresh_models_in_tray(app_handle: tauri::AppHandle) -> Result<(), String> {
+    info!("Loading models for tray menu update...");
+    
+    // Get current active profile's API settings
+    let app_state = app_handle.state::<AppState>();
+    let active_profile = app_state.get_active_profile().await?;
+    let api_key = active_profile.api_config.usable_api_key().to_string();
+    let base_url = active_profile.api_config.base_url.clone();
+    
+    if api_key.is_empty() || base_url.is_empty() {
+        return Err("API key and base URL must be configured first".to_string());
+    }
+    
+    // Get models using the same logic as get_models command
+    let url = active_profile.api_config.models_endpoint();
+    let response = app_state.http_client_for(&active_profile.api_config).await?
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch models: {}", e))?;
+    
+    if !response.status().is_success() {
+        return Err(format!("API error: Status {}", response.status()));
+    }
+    
+    let response_text = response.text().await
+        .map_err(|_| "Failed to read response".to_string())?;
+    
+    let json: serde_json::Value = serde_json::from_str(&response_text)
+        .map_err(|_| "Invalid JSON response".to_string())?;
+    
+    let models = if let Some(data) = json.get("data").and_then(|d| d.as_array()) {
+        data.iter()
+            .filter(|model| owner_allowed(model.get("owned_by").and_then(|o| o.as_str()), &active_profile.model_owners))
+            .filter_map(|model| {
+                model.get("id").and_then(|i| i.as_str()).map(|s| s.to_string())
+            })
+            .collect::<Vec<String>>()
+    } else {
+        return Err("Invalid response format".to_string());
+    };
+    
+    // Store the models in app state and save to cache
+    let mut loaded_models = app_state.loaded_models.lock().await;
+    loaded_models.insert(base_url.clone(), models.clone());
+    let cache_snapshot = loaded_models.clone();
+    drop(loaded_models);
+    
+    // Save models to persistent cache
+    if let Err(e) = AppState::save_loaded_models(&cache_snapshot) {
+        error!("Failed to save models to cache: {}", e);
+    }
+    
+    info!("Successfully loaded {} models for tray", models.len());
+
+    // 立即用新加载的模型重新填充子菜单
+    rebuild_model_submenu(&app_handle).await
+}
+
+// 设置活跃Profile的模型（供设置页和脚本调用），同步更新托盘的模型子菜单
+// 已加载模型列表时要求模型在列表中；列表为空时不校验
+#[tauri::command]
+async fn set_active_model(app_handle: tauri::AppHandle, state: State<'_, AppState>, model_id: String) -> Result<(), String> {
+    let model_id = model_id.trim().to_string();
+    if model_id.is_empty() {
+        return Err("Model ID cannot be empty".to_string());
+    }
+
+    let cached_models = state.get_models_for_active_profile().await?;
+    if !cached_models.is_empty() && !cached_models.contains(&model_id) {
+        return Err(format!("Model '{}' is not in the loaded model list, please reload models", model_id));
+    }
+
+    state.update_and_save_config(|config| {
+        let active_id = config.active_profile_id.clone()
+            .ok_or("No active profile")?;
+        let profile = config.profiles.iter_mut()
+            .find(|p| p.id == active_id)
+            .ok_or("Active profile not found")?;
+
+        profile.api_config.model = model_id.clone();
+        profile.recent_models.retain(|m| m != &model_id);
+        profile.recent_models.insert(0, model_id.clone());
+        profile.recent_models.truncate(MAX_RECENT_MODELS);
+        Ok(())
+    }).await?;
+    info!("Active model set to: {}", model_id);
+
+    if let Err(e) = update_model_submenu_title(&app_handle, &model_id).await {
+        warn!("⚠️ Failed to update model submenu title: {}", e);
+    }
+    if let Err(e) = update_model_menu_selection(&app_handle, &model_id).await {
+        warn!("⚠️ Failed to update model menu selection: {}", e);
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn select_model_in_tray(app_handle: tauri::AppHandle, model_id: String) -> Result<(), String> {
+    debug!("🔍 Selecting model from tray: {}", model_id);
+    
+    let state = app_handle.state::<AppState>();
+    
+    // Update active profile with selected model using atomic save
+    state.update_and_save_config(|config| {
+        // Clone active profile ID to avoid borrow conflicts
+        let active_id = config.active_profile_id.clone()
+            .ok_or("No active profile")?;
+
+        // Find and update the profile
+        let profile = config.profiles.iter_mut()
+            .find(|p| p.id == active_id)
+            .ok_or("Active profile not found")?;
+
+        profile.api_config.model = model_id.clone();
+        profile.recent_models.retain(|m| m != &model_id);
+        profile.recent_models.insert(0, model_id.clone());
+        profile.recent_models.truncate(MAX_RECENT_MODELS);
+        debug!("   📝 Updated model to: {}", model_id);
+        Ok(())
+    }).await?;
+    
+    debug!("✓ Model '{}' selected and saved to config", model_id);
+
+    // 重建子菜单，让刚选择的模型排到最前面（重建时会按新的选择设置勾选状态）
+    if let Err(e) = rebuild_model_submenu(&app_handle).await {
+        warn!("⚠️ Failed to reorder model submenu: {}", e);
+    }
+    
+    // Debug CheckMenuItem references availability
+    {
+        let model_check_items = state.model_check_items.lock().await;
+        debug!("🔍 CheckMenuItem storage status:");
+        debug!("   - Total stored references: {}", model_check_items.len());
+        
+        if model_check_items.is_empty() {
+            debug!("❌ No CheckMenuItem references found!");
+            debug!("   This means dynamic updates won't work - menu updates will be visible after app restart");
+            debug!("   The references may not have been stored yet or storage failed");
+            return Ok(());
+        }
+        
+        debug!("   - Available model IDs: {:?}", model_check_items.keys().collect::<Vec<_>>());
+        debug!("   - Target model ID: '{}'", model_id);
+        
+        // Verify target model exists in our references
+        if !model_check_items.contains_key(&model_id) {
+            debug!("⚠️  Target model '{}' not found in CheckMenuItem references!", model_id);
+            debug!("   This could indicate a mismatch between loaded models and stored references");
+        }
+        
+        debug!("📝 Implementing RadioButton behavior - updating {} CheckMenuItem states", model_check_items.len());
+        
+        let mut success_count = 0;
+        let mut failure_count = 0;
+        
+        for (item_model_id, check_item) in model_check_items.iter() {
+            let should_be_checked = item_model_id == &model_id;
+            
+            debug!("   🔄 Processing '{}': setting checked={}", item_model_id, should_be_checked);
+            
+            // Use the dynamic update API
+            match check_item.set_checked(should_be_checked) {
+                Ok(()) => {
+                    success_count += 1;
+                    debug!("      ✓ Successfully updated '{}' to checked={}", item_model_id, should_be_checked);
+                }
+                Err(e) => {
+                    failure_count += 1;
+                    debug!("      ✗ Failed to update '{}': {}", item_model_id, e);
+                }
+            }
+        }
+        
+        debug!("📊 RadioButton update summary:");
+        debug!("   - Successful updates: {}", success_count);
+        debug!("   - Failed updates: {}", failure_count);
+        debug!("   - Total processed: {}", model_check_items.len());
+        
+        if failure_count > 0 {
+            warn!("⚠️  Some CheckMenuItem updates failed - dynamic updates may not be fully working");
+        } else {
+            debug!("✅ All CheckMenuItem updates completed successfully");
+        }
+    }
+    
+    // Update the submenu title to reflect the new selection
+    debug!("🔄 Updating submenu title to show selected model...");
+    {
+        match state.model_submenu.try_lock() {
+            Ok(submenu_ref) => {
+                if let Some(submenu) = &*submenu_ref {
+                    let new_title = format!("Model: {}", model_id);
+                    debug!("   📝 Attempting to update submenu title to: '{}'", new_title);
+                    
+                    match submenu.set_text(&new_title) {
+                        Ok(()) => {
+                            debug!("   ✅ Successfully updated submenu title to '{}'", new_title);
+                        }
+                        Err(e) => {
+                            error!("   ❌ Failed to update submenu title: {}", e);
+                        }
+                    }
+                } else {
+                    warn!("   ⚠️  No submenu reference available for title update");
                 }
             }
-
-            // Show macOS system dialog
-            if let Err(dialog_err) = show_system_dialog(
-                "MathImage Error".to_string(),
-                format!("Profile '{}': Please select a model first. Check Settings to load available models.", active_profile.name),
-                "error".to_string()
-            ).await {
-                println!("Failed to show system dialog: {}", dialog_err);
+            Err(e) => {
+                error!("   ❌ Failed to acquire submenu lock for title update: {}", e);
             }
         }
-        return Err(format!("Profile '{}': Please select a model first", active_profile.name));
     }
+    
+    debug!("✅ Model '{}' selection process completed", model_id);
+    Ok(())
+}
 
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(120))
-        .tcp_keepalive(std::time::Duration::from_secs(60))
-        .pool_idle_timeout(std::time::Duration::from_secs(90))
-        .pool_max_idle_per_host(10)
-        .http2_keep_alive_interval(std::time::Duration::from_secs(30))
-        .http2_keep_alive_timeout(std::time::Duration::from_secs(10))
-        .http2_keep_alive_while_idle(true)
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
-    let url = format!("{}/chat/completions", active_profile.api_config.base_url);
-
-    println!("Analyzing image with profile '{}' using model: {}", active_profile.name, active_profile.api_config.model);
-    println!("Image data size: {} chars", image_data.len());
+#[allow(dead_code)]
+async fn toggle_sound_setting(app_handle: tauri::AppHandle) -> Result<(), String> {
+    debug!("🔧 Toggling sound setting...");
+    
+    let state = app_handle.state::<AppState>();
+    
+    state.update_and_save_config(|config| {
+        config.sound_enabled = !config.sound_enabled;
+        let new_sound_state = config.sound_enabled;
+        debug!("   📝 Sound setting toggled to: {}", new_sound_state);
+        Ok(())
+    }).await?;
 
-    // Check if image data is too large (some APIs have limits)
-    if image_data.len() > 100_000 {
-        println!("Warning: Image data is large ({} chars), this may cause timeouts", image_data.len());
+    // Read new state and update tray menu item text
+    let state = app_handle.state::<AppState>();
+    let enabled = {
+        let cfg = state.config.lock().await;
+        cfg.sound_enabled
+    };
+    if let Err(e) = update_sound_menu_text(&app_handle, enabled).await {
+        warn!("⚠️ Failed to update sound menu text: {}", e);
     }
 
-    // 确定使用的prompt：自定义prompt优先，否则使用Profile的prompt模式
-    let prompt_text = if let Some(custom) = custom_prompt {
-        println!("Using custom prompt: {}", custom);
-        custom
-    } else {
-        match &active_profile.prompt_mode {
-            PromptMode::Predefined(prompt) => {
-                println!("Using predefined prompt from profile: {}", prompt);
-                prompt.clone()
-            },
-            PromptMode::UserInput => {
-                // TODO: 实现用户输入prompt的逻辑
-                println!("Profile requires user input prompt, using default");
-                "识别公式和文字，返回使用pandoc语法的markdown排版内容。公式请用katex语法包裹，文字内容不要丢失。只返回内容不需要其他解释。".to_string()
-            }
-        }
-    };
+    debug!("✅ Sound setting updated successfully");
+    Ok(())
+}
 
-    let payload = serde_json::json!({
-        "model": active_profile.api_config.model,
-        "messages": [
-            {
-                "role": "user",
-                "content": [
-                    {
-                        "type": "text",
-                        "text": prompt_text
-                    },
-                    {
-                        "type": "image_url",
-                        "image_url": {
-                            "url": image_data
-                        }
-                    }
-                ]
+#[tauri::command]
+async fn get_endpoint_health(state: State<'_, AppState>) -> Result<Vec<EndpointHealth>, String> {
+    let health = state.endpoint_health.lock().await;
+
+    Ok(health.iter()
+        .map(|(endpoint, breaker)| {
+            let (circuit_state, cooldown_remaining) = match breaker.opened_at {
+                Some(opened_at) if opened_at.elapsed() < CIRCUIT_COOLDOWN => {
+                    ("open", CIRCUIT_COOLDOWN - opened_at.elapsed())
+                }
+                Some(_) => ("half_open", std::time::Duration::ZERO),
+                None => ("closed", std::time::Duration::ZERO),
+            };
+            EndpointHealth {
+                endpoint: endpoint.clone(),
+                state: circuit_state.to_string(),
+                consecutive_failures: breaker.consecutive_failures,
+                cooldown_remaining_secs: cooldown_remaining.as_secs(),
             }
-        ],
-        "temperature": 1,
-        "top_p": 1,
-        "stream": true
-    });
+        })
+        .collect())
+}
 
-    println!("Sending request to: {}", url);
-    println!("Payload size: {} bytes", serde_json::to_string(&payload).unwrap_or_default().len());
+// 汇总各子系统的状态。按固定顺序同时持有相关的锁（config → 热键 → 模型缓存 → 最近状态），
+// 得到一致的快照，避免读取过程中配置被切换
+#[tauri::command]
+async fn get_status(app_handle: tauri::AppHandle, state: State<'_, AppState>) -> Result<AppStatus, String> {
+    let config = state.config.lock().await;
+    let current_global_hotkey = state.current_global_hotkey.lock().await;
+    let loaded_models = state.loaded_models.lock().await;
+    let last_status = state.last_status.lock().await;
+
+    let active_profile = config.profiles.iter()
+        .find(|p| Some(&p.id) == config.active_profile_id.as_ref());
+    let hotkeys_registered = config.hotkeys_enabled && current_global_hotkey.as_deref()
+        .and_then(|hotkey| hotkey.parse::<Shortcut>().ok())
+        .map_or(false, |shortcut| app_handle.global_shortcut().is_registered(shortcut));
+
+    Ok(AppStatus {
+        active_profile: active_profile.map(|p| p.name.clone()),
+        model: active_profile
+            .map(|p| p.api_config.model.clone())
+            .filter(|model| !model.is_empty()),
+        credentials_configured: active_profile.map_or(false, |p| {
+            !p.api_config.base_url.is_empty()
+                && !p.api_config.usable_api_key().is_empty()
+        }),
+        hotkeys_enabled: config.hotkeys_enabled,
+        hotkeys_registered,
+        global_hotkey: current_global_hotkey.clone(),
+        model_cache_count: active_profile
+            .and_then(|p| loaded_models.get(&p.api_config.base_url))
+            .map_or(0, |models| models.len()),
+        last_error: last_status.as_ref().and_then(|status| status.error.clone()),
+        last_analysis_secs_ago: last_status.as_ref().map(|status| status.at.elapsed().as_secs()),
+        queued_captures: state.queued_captures.load(std::sync::atomic::Ordering::SeqCst),
+    })
+}
 
-    let mut request = client
-        .post(&url)
-        .header("Content-Type", "application/json");
+// 记录一次Profile使用：只更新内存中的计数，随下一次配置保存或退出时写盘，不拖慢热键
+async fn record_profile_use(state: &AppState, profile_id: &str) -> Result<(), String> {
+    let mut config = state.config.lock().await;
+    let profile = config.profiles.iter_mut()
+        .find(|p| p.id == profile_id)
+        .ok_or("Profile not found")?;
+    profile.use_count = profile.use_count.saturating_add(1);
+    profile.last_used = Some(std::time::SystemTime::now());
+    Ok(())
+}
 
-    // Only add auth headers if API key is provided
-    if !active_profile.api_config.api_key.is_empty() {
-        request = request.header("Authorization", format!("Bearer {}", active_profile.api_config.api_key));
-    }
+#[tauri::command]
+async fn get_profile_stats(state: State<'_, AppState>) -> Result<Vec<ProfileStats>, String> {
+    let config = state.config.lock().await;
 
-    // 继续使用现有的请求处理逻辑...
-    analyze_image_request_internal(request, payload).await
+    Ok(config.profiles.iter()
+        .map(|profile| ProfileStats {
+            id: profile.id.clone(),
+            name: profile.name.clone(),
+            use_count: profile.use_count,
+            last_used_secs: profile.last_used
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+        })
+        .collect())
 }
 
-// 保持向后兼容的原函数
-async fn analyze_image_internal(
-    image_data: String,
-    state: State<'_, AppState>,
-    app_handle: Option<tauri::AppHandle>,
-) -> Result<String, String> {
-    analyze_image_with_prompt(image_data, state, None, app_handle).await
+#[tauri::command]
+async fn get_loaded_models(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    state.get_models_for_active_profile().await
 }
 
-// 提取请求处理逻辑为独立函数
-async fn analyze_image_request_internal(
-    request: reqwest::RequestBuilder,
-    payload: serde_json::Value,
-) -> Result<String, String> {
+#[tauri::command]
+async fn clear_model_cache(app_handle: tauri::AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    debug!("🔧 Clearing model cache...");
 
-    // Retry logic for connection issues
-    let mut last_error = String::new();
-    for attempt in 1..=3 {
-        println!("Attempt {} of 3", attempt);
+    state.loaded_models.lock().await.clear();
+    AppState::save_loaded_models(&std::collections::HashMap::new())?;
 
-        let response_result = request
-            .try_clone()
-            .ok_or("Failed to clone request")?
-            .json(&payload)
-            .send()
-            .await;
+    rebuild_model_submenu(&app_handle).await
+}
 
-        match response_result {
-            Ok(response) => {
-                println!("Request successful on attempt {}", attempt);
+// 清空分析结果缓存，返回清除的条数
+#[tauri::command]
+async fn clear_analysis_cache(state: State<'_, AppState>) -> Result<usize, String> {
+    let cleared = state.analysis_cache.lock().await.clear();
+    info!("🧹 Cleared {} cached analysis results", cleared);
+    Ok(cleared)
+}
 
-                if response.status().is_success() {
-                    // Handle streaming response
-                    use futures_util::StreamExt;
+#[tauri::command]
+async fn remove_cached_model(app_handle: tauri::AppHandle, state: State<'_, AppState>, model_id: String) -> Result<(), String> {
+    debug!("🔧 Removing cached model: {}", model_id);
+
+    let base_url = state.get_active_profile().await?.api_config.base_url;
+    let cache_snapshot = {
+        let mut loaded_models = state.loaded_models.lock().await;
+        let models = loaded_models.get_mut(&base_url)
+            .ok_or_else(|| format!("Model '{}' is not in the cache", model_id))?;
+        let before = models.len();
+        models.retain(|m| m != &model_id);
+        if models.len() == before {
+            return Err(format!("Model '{}' is not in the cache", model_id));
+        }
+        loaded_models.clone()
+    };
 
-                    let mut stream = response.bytes_stream();
-                    let mut full_content = String::new();
-                    let mut buffer = String::new();
+    AppState::save_loaded_models(&cache_snapshot)?;
 
-                    while let Some(chunk) = stream.next().await {
-                        let chunk = chunk.map_err(|e| format!("Failed to read chunk: {}", e))?;
-                        let chunk_str = String::from_utf8_lossy(&chunk);
-                        buffer.push_str(&chunk_str);
+    rebuild_model_submenu(&app_handle).await
+}
 
-                        // Process complete lines
-                        while let Some(line_end) = buffer.find('\n') {
-                            let line = buffer[..line_end].trim().to_string();
-                            buffer = buffer[line_end + 1..].to_string();
+// 在系统文件管理器中打开数据目录（config.json / models.json所在位置）
+#[tauri::command]
+async fn open_config_dir() -> Result<String, String> {
+    let config_dir = AppState::get_config_dir()?;
+    debug!("📂 Opening config directory: {:?}", config_dir);
+
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(target_os = "windows") {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
 
-                            if line.starts_with("data: ") {
-                                let data = &line[6..]; // Remove "data: " prefix
+    std::process::Command::new(opener)
+        .arg(&config_dir)
+        .spawn()
+        .map_err(|e| format!("Failed to open config directory with {}: {}", opener, e))?;
 
-                                if data == "[DONE]" {
-                                    break;
-                                }
+    Ok(config_dir.to_string_lossy().to_string())
+}
 
-                                if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                                    if let Some(choices) = json.get("choices").and_then(|c| c.as_array()) {
-                                        if let Some(first_choice) = choices.first() {
-                                            if let Some(delta) = first_choice.get("delta") {
-                                                if let Some(content) = delta.get("content").and_then(|c| c.as_str()) {
-                                                    full_content.push_str(content);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+#[tauri::command]
+async fn refresh_tray_models(app_handle: tauri::AppHandle) -> Result<(), String> {
+    refresh_models_in_tray(app_handle).await
+}
 
-                    if !full_content.is_empty() {
-                        return Ok(full_content);
-                    } else {
-                        return Err("No content received from stream".to_string());
-                    }
-                } else {
-                    let status = response.status();
-                    let error_text = response.text().await.unwrap_or_default();
-                    return Err(format!("Analysis failed with status {}: {}", status, error_text));
-                }
-            }
-            Err(e) => {
-                last_error = format!("Request failed: {}", e);
-                println!("Attempt {} failed: {}", attempt, last_error);
+#[tauri::command]
+async fn refresh_tray_menu(app_handle: tauri::AppHandle) -> Result<(), String> {
+    // 刷新整个托盘菜单，包括Profile列表
+    info!("Refreshing tray menu with updated profiles");
 
-                if attempt < 3 {
-                    println!("Retrying in 2 seconds...");
-                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-                }
+    // 获取当前配置
+    let app_state = app_handle.state::<AppState>();
+    let config = app_state.config.lock().await;
+    let current_config = config.clone();
+    drop(config);
+
+    // 获取活跃profile的模型信息
+    let model_display = if let Some(active_id) = &current_config.active_profile_id {
+        if let Some(profile) = current_config.profiles.iter().find(|p| &p.id == active_id) {
+            if profile.api_config.model.is_empty() {
+                "Not Selected".to_string()
+            } else {
+                profile.api_config.model.clone()
             }
+        } else {
+            "Not Selected".to_string()
         }
-    }
+    } else {
+        "Not Selected".to_string()
+    };
 
-    Err(format!("All 3 attempts failed. Last error: {}", last_error))
+    // 使用现有的托盘菜单更新逻辑
+    update_tray_menu(app_handle, Some(model_display), Some(current_config.sound_enabled)).await
 }
 
 #[tauri::command]
-async fn analyze_image(
-    image_data: String,
+async fn update_hotkeys(
+    app_handle: tauri::AppHandle,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    analyze_image_internal(image_data, state, None).await
+    global_hotkey: String,
+    switch_hotkey: String,
+    previous_hotkey: Option<String>,
+    rerun_hotkey: Option<String>,
+) -> Result<(), String> {
+    debug!("🔧 Updating hotkeys - Global: {}, Switch: {}, Previous: {:?}, Rerun: {:?}", global_hotkey, switch_hotkey, previous_hotkey, rerun_hotkey);
+
+    // 未提供时保留现有的上一个Profile热键和重新分析热键
+    let (previous_hotkey, rerun_hotkey) = {
+        let config = state.config.lock().await;
+        (
+            previous_hotkey.unwrap_or_else(|| config.switch_previous_profile_hotkey.clone()),
+            rerun_hotkey.unwrap_or_else(|| config.rerun_last_hotkey.clone()),
+        )
+    };
+
+    // 统一大小写和修饰键顺序后再校验、注册和保存
+    let global_hotkey = normalize_hotkey(&global_hotkey)?;
+    let switch_hotkey = normalize_hotkey(&switch_hotkey)?;
+    let normalize_optional = |hotkey: &str| if hotkey.trim().is_empty() {
+        Ok(String::new())
+    } else {
+        normalize_hotkey(hotkey)
+    };
+    let previous_hotkey = normalize_optional(&previous_hotkey)?;
+    let rerun_hotkey = normalize_optional(&rerun_hotkey)?;
+
+    // 在修改配置和注册之前检查冲突，保留之前可用的热键
+    validate_hotkey_set(&[
+        ("Global", global_hotkey.as_str()),
+        ("Switch", switch_hotkey.as_str()),
+        ("Previous profile", previous_hotkey.as_str()),
+        ("Rerun last capture", rerun_hotkey.as_str()),
+    ])?;
+
+    // 先注册（失败时会回滚到之前可用的热键），成功后再保存配置
+    // Use internal registration function (clone to avoid moving the originals)
+    let gh = global_hotkey.clone();
+    let sh = switch_hotkey.clone();
+    let ph = previous_hotkey.clone();
+    let rh = rerun_hotkey.clone();
+    register_hotkeys_internal(app_handle.clone(), gh, sh, ph, rh).await?;
+
+    // Update config atomically
+    state.update_and_save_config(|config| {
+        config.global_hotkey = global_hotkey.clone();
+        config.switch_profile_hotkey = switch_hotkey.clone();
+        config.switch_previous_profile_hotkey = previous_hotkey.clone();
+        config.rerun_last_hotkey = rerun_hotkey.clone();
+        debug!("   📝 Updated hotkeys in config");
+        Ok(())
+    }).await?;
+
+    // Update tray menu items text in-place
+    debug!("🔧 Updating tray menu hotkey labels in-place...");
+    if let Err(e) = update_hotkey_menu_text(&app_handle, &global_hotkey, &switch_hotkey).await {
+        warn!("⚠️ Failed to update hotkey labels: {}", e);
+    }
+
+    debug!("✅ Hotkeys updated and re-registered successfully - no restart required!");
+    Ok(())
 }
 
-#[tauri::command]
-async fn copy_to_clipboard(text: String) -> Result<(), String> {
-    let mut clipboard = Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?;
-    clipboard.set_text(text).map_err(|e| format!("Failed to copy to clipboard: {}", e))?;
-    println!("Text copied to clipboard");
+// 系统保留的组合键（macOS），注册后会与系统功能冲突
+const RESERVED_HOTKEYS: &[&str] = &[
+    "cmd+q",
+    "cmd+w",
+    "cmd+h",
+    "cmd+tab",
+    "cmd+space",
+    "cmd+shift+3",
+    "cmd+shift+4",
+    "cmd+shift+5",
+    "cmd+alt+escape",
+    "ctrl+cmd+q",
+];
+
+// 检查热键是否可解析、互不相同且不与系统保留组合键冲突。空字符串表示未启用
+fn validate_hotkey_set(hotkeys: &[(&str, &str)]) -> Result<(), String> {
+    let mut parsed: Vec<(&str, &str, Shortcut)> = Vec::new();
+
+    for &(label, hotkey) in hotkeys {
+        if hotkey.trim().is_empty() {
+            continue;
+        }
+
+        let shortcut = hotkey.parse::<Shortcut>()
+            .map_err(|e| format!("Invalid {} hotkey '{}': {}", label.to_lowercase(), hotkey, e))?;
+
+        if let Some((other_label, other_hotkey, _)) = parsed.iter().find(|(_, _, other)| other == &shortcut) {
+            return Err(format!(
+                "{} hotkey '{}' conflicts with {} hotkey '{}'",
+                label, hotkey, other_label.to_lowercase(), other_hotkey
+            ));
+        }
+
+        if let Some(reserved) = RESERVED_HOTKEYS.iter()
+            .find(|reserved| reserved.parse::<Shortcut>().map_or(false, |r| r == shortcut))
+        {
+            return Err(format!("{} hotkey '{}' is reserved by the system ({})", label, hotkey, reserved));
+        }
+
+        parsed.push((label, hotkey, shortcut));
+    }
+
     Ok(())
 }
 
-#[tauri::command]
-async fn show_system_dialog(title: String, message: String, dialog_type: String) -> Result<(), String> {
-    use std::process::Command;
+// 解析一组热键，任何一个无效都不会影响当前已注册的热键
+// 可选热键（上一个Profile、重新分析）为空字符串时不注册
+fn parse_optional_hotkey(hotkey: &str, label: &str) -> Result<Option<Shortcut>, String> {
+    if hotkey.trim().is_empty() {
+        return Ok(None);
+    }
+    hotkey.parse::<Shortcut>()
+        .map(Some)
+        .map_err(|e| format!("Invalid {} hotkey '{}': {}", label, hotkey, e))
+}
 
-    println!("Showing system dialog: {} - {}", title, message);
+fn parse_hotkey_set(global_hotkey: &str, switch_hotkey: &str, previous_hotkey: &str, rerun_hotkey: &str) -> Result<(Shortcut, Shortcut, Option<Shortcut>, Option<Shortcut>), String> {
+    let global_shortcut = global_hotkey.parse::<Shortcut>()
+        .map_err(|e| format!("Invalid global hotkey '{}': {}", global_hotkey, e))?;
 
-    // Determine the icon based on dialog type
-    let icon = match dialog_type.as_str() {
-        "error" => "stop",
-        "warning" => "caution", 
-        "info" => "note",
-        _ => "note",
-    };
+    let switch_shortcut = switch_hotkey.parse::<Shortcut>()
+        .map_err(|e| format!("Invalid switch hotkey '{}': {}", switch_hotkey, e))?;
 
-    // Use macOS osascript to show system dialog
-    let script = format!(
-        r#"display dialog "{}" with title "{}" with icon {} buttons {{"OK"}} default button "OK""#,
-        message.replace("\"", "\\\""),
-        title.replace("\"", "\\\""),
-        icon
-    );
+    let previous_shortcut = parse_optional_hotkey(previous_hotkey, "previous-profile")?;
+    let rerun_shortcut = parse_optional_hotkey(rerun_hotkey, "rerun-last-capture")?;
 
-    println!("AppleScript: {}", script);
+    Ok((global_shortcut, switch_shortcut, previous_shortcut, rerun_shortcut))
+}
 
-    let output = Command::new("osascript")
-        .arg("-e")
-        .arg(&script)
-        .output()
-        .map_err(|e| format!("Failed to show dialog: {}", e))?;
+fn register_shortcut_set(
+    app_handle: &tauri::AppHandle,
+    global_shortcut: Shortcut,
+    switch_shortcut: Shortcut,
+    previous_shortcut: Option<Shortcut>,
+    rerun_shortcut: Option<Shortcut>,
+) -> Result<(), String> {
+    // Register global shortcut
+    app_handle.global_shortcut().on_shortcut(global_shortcut, move |app, shortcut, event| {
+        if event.state == ShortcutState::Pressed {
+            info!("Global shortcut triggered: {}", shortcut);
+            let app_handle = app.app_handle().clone();
+            spawn_analysis_task(app.app_handle(), async move {
+                handle_global_hotkey(app_handle).await;
+            });
+        }
+    }).map_err(|e| format!("Failed to register global hotkey '{}': {}", global_shortcut, e))?;
+    
+    // Register switch shortcut  
+    app_handle.global_shortcut().on_shortcut(switch_shortcut, move |app, shortcut, event| {
+        if event.state == ShortcutState::Pressed {
+            info!("Switch shortcut triggered: {}", shortcut);
+            let app_handle = app.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                handle_switch_hotkey(app_handle).await;
+            });
+        }
+    }).map_err(|e| format!("Failed to register switch hotkey '{}': {}", switch_shortcut, e))?;
+
+    // Register previous-profile shortcut (optional)
+    if let Some(previous_shortcut) = previous_shortcut {
+        app_handle.global_shortcut().on_shortcut(previous_shortcut, move |app, shortcut, event| {
+            if event.state == ShortcutState::Pressed {
+                info!("Previous-profile shortcut triggered: {}", shortcut);
+                let app_handle = app.app_handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    handle_switch_previous_hotkey(app_handle).await;
+                });
+            }
+        }).map_err(|e| format!("Failed to register previous-profile hotkey '{}': {}", previous_shortcut, e))?;
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        println!("osascript error: {}", stderr);
-        return Err(format!("Failed to show system dialog: {}", stderr));
+    // Register rerun-last-capture shortcut (optional)
+    if let Some(rerun_shortcut) = rerun_shortcut {
+        app_handle.global_shortcut().on_shortcut(rerun_shortcut, move |app, shortcut, event| {
+            if event.state == ShortcutState::Pressed {
+                info!("Rerun shortcut triggered: {}", shortcut);
+                let app_handle = app.app_handle().clone();
+                spawn_analysis_task(app.app_handle(), async move {
+                    handle_rerun_hotkey(app_handle).await;
+                });
+            }
+        }).map_err(|e| format!("Failed to register rerun-last-capture hotkey '{}': {}", rerun_shortcut, e))?;
     }
 
-    println!("System dialog shown successfully");
     Ok(())
 }
 
-#[tauri::command]
-async fn play_system_sound() -> Result<(), String> {
-    use std::process::Command;
+// 内部热键注册函数，不包含托盘菜单更新
+// Registration is transactional: all shortcuts are parsed up front, and if any
+// registration fails the previously working set (current_*_hotkey) is restored.
+async fn register_hotkeys_internal(app_handle: tauri::AppHandle, global_hotkey: String, switch_hotkey: String, previous_hotkey: String, rerun_hotkey: String) -> Result<(), String> {
+    debug!("🔧 Registering hotkeys internally - Global: {}, Switch: {}, Previous: {}, Rerun: {}", global_hotkey, switch_hotkey, previous_hotkey, rerun_hotkey);
 
-    // Play macOS system sound (Glass)
-    let output = Command::new("afplay")
-        .arg("/System/Library/Sounds/Glass.aiff")
-        .output()
-        .map_err(|e| format!("Failed to play sound: {}", e))?;
+    // Parse new shortcuts before touching the current registration
+    let (global_shortcut, switch_shortcut, previous_shortcut, rerun_shortcut) = parse_hotkey_set(&global_hotkey, &switch_hotkey, &previous_hotkey, &rerun_hotkey)?;
 
-    if !output.status.success() {
-        return Err("Failed to play system sound".to_string());
+    let state = app_handle.state::<AppState>();
+
+    // 热键被临时停用时只记录新的组合键，重新启用时再注册
+    if !state.config.lock().await.hotkeys_enabled {
+        debug!("⏸️ Hotkeys are disabled, storing without registering");
+        *state.current_global_hotkey.lock().await = Some(global_hotkey);
+        *state.current_switch_hotkey.lock().await = Some(switch_hotkey);
+        *state.current_previous_hotkey.lock().await = Some(previous_hotkey);
+        *state.current_rerun_hotkey.lock().await = Some(rerun_hotkey);
+        return Ok(());
+    }
+    
+    // Unregister all current shortcuts
+    if let Err(e) = app_handle.global_shortcut().unregister_all() {
+        warn!("⚠️ Failed to unregister existing shortcuts: {}", e);
+    } else {
+        debug!("✅ Unregistered all existing shortcuts");
+    }
+
+    if let Err(e) = register_shortcut_set(&app_handle, global_shortcut, switch_shortcut, previous_shortcut, rerun_shortcut) {
+        error!("❌ {}, rolling back to previous hotkeys", e);
+        let _ = app_handle.global_shortcut().unregister_all();
+
+        let previous_set = (
+            state.current_global_hotkey.lock().await.clone(),
+            state.current_switch_hotkey.lock().await.clone(),
+            state.current_previous_hotkey.lock().await.clone().unwrap_or_default(),
+            state.current_rerun_hotkey.lock().await.clone().unwrap_or_default(),
+        );
+        if let (Some(old_global), Some(old_switch), old_previous, old_rerun) = previous_set {
+            let restored = parse_hotkey_set(&old_global, &old_switch, &old_previous, &old_rerun)
+                .and_then(|(g, s, p, r)| register_shortcut_set(&app_handle, g, s, p, r));
+            match restored {
+                Ok(()) => debug!("✅ Restored previous hotkeys: {} (global), {} (switch)", old_global, old_switch),
+                Err(restore_err) => error!("❌ Failed to restore previous hotkeys: {}", restore_err),
+            }
+        }
+
+        return Err(e);
     }
 
+    // Update current hotkey tracking
+    *state.current_global_hotkey.lock().await = Some(global_hotkey);
+    *state.current_switch_hotkey.lock().await = Some(switch_hotkey);
+    *state.current_previous_hotkey.lock().await = Some(previous_hotkey);
+    *state.current_rerun_hotkey.lock().await = Some(rerun_hotkey);
+
+    debug!("✅ Hotkeys registered successfully");
     Ok(())
 }
 
-#[tauri::command]
-async fn play_error_sound() -> Result<(), String> {
-    use std::process::Command;
+// 临时停用/重新启用全局热键：停用时注销所有快捷键但保留配置和current_*_hotkey，
+// 启用时按当前记录的组合键重新注册
+async fn toggle_hotkeys_enabled(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
 
-    // Play macOS system error sound (Basso)
-    let output = Command::new("afplay")
-        .arg("/System/Library/Sounds/Basso.aiff")
-        .output()
-        .map_err(|e| format!("Failed to play error sound: {}", e))?;
+    let mut enabled = false;
+    state.update_and_save_config(|config| {
+        config.hotkeys_enabled = !config.hotkeys_enabled;
+        enabled = config.hotkeys_enabled;
+        Ok(())
+    }).await?;
 
-    if !output.status.success() {
-        return Err("Failed to play error sound".to_string());
+    if enabled {
+        let (global_hotkey, switch_hotkey, previous_hotkey, rerun_hotkey) = {
+            let config = state.config.lock().await;
+            (
+                state.current_global_hotkey.lock().await.clone().unwrap_or_else(|| config.global_hotkey.clone()),
+                state.current_switch_hotkey.lock().await.clone().unwrap_or_else(|| config.switch_profile_hotkey.clone()),
+                state.current_previous_hotkey.lock().await.clone().unwrap_or_else(|| config.switch_previous_profile_hotkey.clone()),
+                state.current_rerun_hotkey.lock().await.clone().unwrap_or_else(|| config.rerun_last_hotkey.clone()),
+            )
+        };
+        if let Err(e) = register_hotkeys_internal(app_handle.clone(), global_hotkey, switch_hotkey, previous_hotkey, rerun_hotkey).await {
+            // 注册失败时保持停用状态，避免托盘显示已启用但热键无效
+            state.update_and_save_config(|config| {
+                config.hotkeys_enabled = false;
+                Ok(())
+            }).await?;
+            return Err(e);
+        }
+        info!("Global hotkeys enabled");
+    } else {
+        app_handle.global_shortcut().unregister_all()
+            .map_err(|e| format!("Failed to unregister hotkeys: {}", e))?;
+        info!("Global hotkeys disabled");
     }
 
-    Ok(())
-}
-
-#[allow(dead_code)]
-fn format_hotkey_for_display(hotkey: &str) -> String {
-    hotkey
-        .replace("cmd", "Cmd")
-        .replace("ctrl", "Ctrl")
-        .replace("alt", "Alt")
-        .replace("shift", "Shift")
-        .split('+')
-        .collect::<Vec<&str>>()
-        .join("+")
+    update_hotkeys_enabled_menu_text(&app_handle, enabled).await
 }
 
+// 保持向后兼容的单热键更新函数
 #[tauri::command]
-async fn update_tray_model(app_handle: tauri::AppHandle, model_name: String) -> Result<(), String> {
-    // Update model submenu title and check selection without rebuilding the tray
-    update_model_submenu_title(&app_handle, &model_name).await?;
-    update_model_menu_selection(&app_handle, &model_name).await?;
+async fn update_hotkey(app_handle: tauri::AppHandle, new_hotkey: String, state: State<'_, AppState>) -> Result<(), String> {
+    debug!("🔧 Updating global hotkey to: {}", new_hotkey);
+
+    let new_hotkey = normalize_hotkey(&new_hotkey)?;
+    let (switch_hotkey, previous_hotkey, rerun_hotkey) = {
+        let config = state.config.lock().await;
+        (config.switch_profile_hotkey.clone(), config.switch_previous_profile_hotkey.clone(), config.rerun_last_hotkey.clone())
+    };
+
+    validate_hotkey_set(&[
+        ("Global", new_hotkey.as_str()),
+        ("Switch", switch_hotkey.as_str()),
+        ("Previous profile", previous_hotkey.as_str()),
+        ("Rerun last capture", rerun_hotkey.as_str()),
+    ])?;
+
+    // Re-register the full set so the rollback logic also covers this path
+    register_hotkeys_internal(app_handle.clone(), new_hotkey.clone(), switch_hotkey, previous_hotkey, rerun_hotkey).await
+        .map_err(|e| format!("Failed to register new global hotkey '{}': {}", new_hotkey, e))?;
+
+    // Update config atomically
+    state.update_and_save_config(|config| {
+        config.global_hotkey = new_hotkey.clone();
+        debug!("   📝 Updated global hotkey in config");
+        Ok(())
+    }).await?;
+
+    debug!("✅ Global hotkey successfully updated to: {}", new_hotkey);
     Ok(())
 }
 
-async fn update_tray_menu(app_handle: tauri::AppHandle, model_name: Option<String>, sound_enabled: Option<bool>) -> Result<(), String> {
-    println!("🔄 [DEBUG] Updating tray menu in-place (no rebuild)...");
-    // Get current config
-    let app_state = app_handle.state::<AppState>();
-    let config = app_state.config.lock().await;
-    let current_config = config.clone();
-    drop(config);
+// 读取窗口当前的位置和大小，最小化时（尺寸为0）忽略
+fn current_window_state(window: &tauri::Window) -> Option<WindowState> {
+    let position = window.outer_position().ok()?;
+    let size = window.inner_size().ok()?;
+    if size.width == 0 || size.height == 0 || window.is_minimized().unwrap_or(false) {
+        return None;
+    }
+    Some(WindowState {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
 
-    // Update profile submenu title
-    if let Some(active_id) = &current_config.active_profile_id {
-        if let Some(profile) = current_config.profiles.iter().find(|p| &p.id == active_id) {
-            update_profile_submenu_title(&app_handle, &profile.name).await.ok();
+// 显示窗口前恢复上次保存的位置和大小
+fn restore_window_state(app_handle: &tauri::AppHandle, window: &tauri::WebviewWindow) {
+    let window_state = match app_handle.state::<AppState>().config.try_lock() {
+        Ok(config) => config.window_state,
+        Err(_) => None,
+    };
+
+    if let Some(window_state) = window_state {
+        if let Err(e) = window.set_size(tauri::PhysicalSize::new(window_state.width, window_state.height)) {
+            error!("Failed to restore window size: {}", e);
+        }
+        if let Err(e) = window.set_position(tauri::PhysicalPosition::new(window_state.x, window_state.y)) {
+            error!("Failed to restore window position: {}", e);
         }
     }
+}
 
-    // Update model submenu title and checked state
-    let model_display = if let Some(name) = model_name.clone() {
-        name
-    } else if let Some(active_id) = &current_config.active_profile_id {
-        if let Some(profile) = current_config.profiles.iter().find(|p| &p.id == active_id) {
-            if profile.api_config.model.is_empty() { "Not Selected".to_string() } else { profile.api_config.model.clone() }
-        } else { "Not Selected".to_string() }
-    } else { "Not Selected".to_string() };
-
-    update_model_submenu_title(&app_handle, &model_display).await.ok();
-    if let Some(name) = model_name {
-        update_model_menu_selection(&app_handle, &name).await.ok();
+// 启动分析任务并记录句柄，以便退出时取消
+fn spawn_analysis_task<F>(app_handle: &tauri::AppHandle, future: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    let task = tauri::async_runtime::spawn(future);
+    if let Some(state) = app_handle.try_state::<AppState>() {
+        let mut tasks = state.analysis_tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|t| !t.inner().is_finished());
+        tasks.push(task);
     }
+}
 
-    // Update hotkey display items
-    update_hotkey_menu_text(&app_handle, &current_config.global_hotkey, &current_config.switch_profile_hotkey).await.ok();
+async fn show_quit_confirmation(running: usize) -> Result<bool, String> {
+    use std::process::Command;
 
-    // Update sound menu item text
-    let sound_state = sound_enabled.unwrap_or(current_config.sound_enabled);
-    update_sound_menu_text(&app_handle, sound_state).await.ok();
+    let script = format!(
+        r#"display dialog "{} analysis still running. Quit anyway?" with title "MathImage" with icon caution buttons {{"Cancel", "Quit"}} default button "Quit" cancel button "Cancel""#,
+        running
+    );
 
-    Ok(())
+    let output = Command::new("osascript")
+        .arg("-e")
+        .arg(&script)
+        .output()
+        .map_err(|e| format!("Failed to show dialog: {}", e))?;
+
+    // 点击Cancel时osascript返回非零退出码
+    Ok(output.status.success())
 }
 
+// 优雅退出：取消进行中的分析、保存配置和模型缓存、注销全局快捷键
+async fn graceful_shutdown(app_handle: tauri::AppHandle) {
+    debug!("🛑 Shutting down...");
 
-fn create_tray_icon_with_menu(
-    app_handle: &tauri::AppHandle,
-    icon: tauri::image::Image<'_>,
-    menu: tauri::menu::Menu<tauri::Wry>,
-) -> Result<tauri::tray::TrayIcon, String> {
-    TrayIconBuilder::new()
-        .icon(icon)
-        .menu(&menu)
-        .show_menu_on_left_click(true)
-        .on_tray_icon_event(|_tray, event| {
-            // Only log important events, not every mouse move
-            match event {
-                tauri::tray::TrayIconEvent::Click { .. } => {
-                    println!("Tray icon clicked");
+    if let Some(state) = app_handle.try_state::<AppState>() {
+        let running = state.running_analysis_count();
+        if running > 0 {
+            match show_quit_confirmation(running).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("Quit cancelled by user");
+                    return;
                 }
-                _ => {} // Don't log move, enter, leave events
+                Err(e) => warn!("⚠️ Failed to show quit confirmation: {}", e),
             }
-        })
-        .on_menu_event({
-            let app_handle_clone = app_handle.clone();
-            move |app, event| {
-                println!("Tray menu event: {:?}", event.id());
-                match event.id().as_ref() {
-                    "settings" => {
-                        println!("Settings clicked - trying to show window");
-                        if let Some(webview_window) = app.get_webview_window("main") {
-                            let _ = webview_window.show();
-                            let _ = webview_window.set_focus();
-                            println!("Window shown successfully");
-                        } else {
-                            println!("Warning: No webview window named 'main' found");
-                        }
-                    }
-                    "load_models" => {
-                        println!("Load models clicked from tray");
-                        let app_handle = app.app_handle().clone();
-                        tauri::async_runtime::spawn(async move {
-                            if let Err(e) = refresh_models_in_tray(app_handle).await {
-                                println!("Failed to refresh models: {}", e);
-                            }
-                        });
-                    }
-                    "toggle_sound" => {
-                        println!("Toggle sound clicked");
-                        let app_handle = app.app_handle().clone();
-                        tauri::async_runtime::spawn(async move {
-                            if let Err(e) = toggle_sound_setting(app_handle).await {
-                                println!("Failed to toggle sound: {}", e);
-                            }
-                        });
-                    }
-                    "quit" => {
-                        println!("Quit clicked");
-                        std::process::exit(0);
-                    }
-                    _ => {
-                        // Handle profile selection
-                        if event.id().as_ref().starts_with("profile_") {
-                            let profile_id = event.id().as_ref().strip_prefix("profile_").unwrap().to_string();
-                            println!("Profile selected from tray: {}", profile_id);
+        }
 
-                            let app_handle = app_handle_clone.clone();
-                            tauri::async_runtime::spawn(async move {
-                                match select_profile_in_tray(app_handle, profile_id.clone()).await {
-                                    Ok(()) => println!("Successfully selected profile: {}", profile_id),
-                                    Err(e) => println!("Failed to select profile {}: {}", profile_id, e),
-                                }
-                            });
-                        }
-                        // Handle model selection
-                        else if event.id().as_ref().starts_with("model_") {
-                            let model_id = event.id().as_ref().strip_prefix("model_").unwrap().to_string();
-                            println!("Model selected from tray: {}", model_id);
+        // Cancel in-flight analyses
+        let tasks: Vec<_> = state.analysis_tasks.lock().unwrap_or_else(|e| e.into_inner()).drain(..).collect();
+        for task in tasks {
+            task.abort();
+        }
 
-                            let app_handle = app_handle_clone.clone();
-                            tauri::async_runtime::spawn(async move {
-                                match select_model_in_tray(app_handle, model_id.clone()).await {
-                                    Ok(()) => println!("Successfully selected model: {}", model_id),
-                                    Err(e) => println!("Failed to select model {}: {}", model_id, e),
-                                }
-                            });
-                        } else {
-                            println!("Unknown menu item: {:?}", event.id());
-                        }
-                    }
-                }
-            }
-        })
-        .build(app_handle)
-        .map_err(|e| format!("Failed to create tray icon: {}", e))
+        // Flush config and model cache
+        let config = state.config.lock().await.clone();
+        if let Err(e) = AppState::save_config_atomic(&config).await {
+            warn!("⚠️ Failed to save config on quit: {}", e);
+        }
+        let loaded_models = state.loaded_models.lock().await.clone();
+        if let Err(e) = AppState::save_loaded_models(&loaded_models) {
+            warn!("⚠️ Failed to save model cache on quit: {}", e);
+        }
+    }
+
+    if let Err(e) = app_handle.global_shortcut().unregister_all() {
+        warn!("⚠️ Failed to unregister shortcuts on quit: {}", e);
+    }
+
+    debug!("✅ Shutdown complete");
+    app_handle.exit(0);
 }
 
-async fn select_profile_in_tray(app_handle: tauri::AppHandle, profile_id: String) -> Result<(), String> {
-    println!("🔍 [DEBUG] Selecting profile from tray: {}", profile_id);
+// 热键处理函数
+async fn handle_global_hotkey(app_handle: tauri::AppHandle) {
+    // 结果和错误已经通过事件、输出模式和日志处理
+    let _ = run_global_hotkey(app_handle).await;
+}
 
-    let app_state = app_handle.state::<AppState>();
+// 热键的完整流程，返回本次截图的最终结果；远程触发直接使用返回值而不是监听全局事件
+async fn run_global_hotkey(app_handle: tauri::AppHandle) -> Result<String, String> {
+    info!("Handling global hotkey - taking screenshot and analyzing");
 
-    // Set the active profile
-    app_state.set_active_profile(profile_id.clone()).await?;
+    // 获取当前活跃的profile
+    let state = app_handle.try_state::<AppState>().ok_or("App state is not available")?;
 
-    // Update profile CheckMenuItem selection (radio button behavior)
-    update_profile_menu_selection(&app_handle, &profile_id).await?;
+    // 防抖：忽略与上次触发间隔过短的重复触发（按键抖动、长按）
+    let debounce = std::time::Duration::from_millis(state.config.lock().await.hotkey_debounce_ms);
+    {
+        let mut last_trigger = state.last_trigger.lock().await;
+        let now = std::time::Instant::now();
+        if let Some(previous) = *last_trigger {
+            if now.duration_since(previous) < debounce {
+                info!("Ignoring global hotkey trigger within {}ms debounce window", debounce.as_millis());
+                return Err(format!("Ignored: triggered again within {}ms", debounce.as_millis()));
+            }
+        }
+        *last_trigger = Some(now);
+    }
 
-    // Get the new active profile for notification
-    let active_profile = app_state.get_active_profile().await?;
+    let active_profile = state.get_active_profile().await.map_err(|e| {
+        error!("Failed to get active profile: {}", e);
+        e
+    })?;
+    info!("Using profile: {} ({})", active_profile.name, active_profile.id);
 
-    // Show profile switch notification
-    show_profile_switch_notification(&app_handle, &active_profile).await?;
+    if let Err(e) = record_profile_use(&state, &active_profile.id).await {
+        warn!("⚠️ Failed to record profile usage: {}", e);
+    }
 
-    // Update tray menu to reflect the new active profile name in submenu title
-    println!("🔧 [DEBUG] Updating profile submenu title...");
-    update_profile_submenu_title(&app_handle, &active_profile.name).await?;
+    // 根据profile的prompt模式处理（选中的预设优先）
+    match active_profile.effective_prompt_mode() {
+        PromptMode::Predefined(_) | PromptMode::Template(_) => {
+            // 不在这里固定prompt文本：分析时按最终使用的模型解析（备用Profile、自动选择的模型匹配各自的覆盖），
+            // 模板占位符也在那里替换
+            handle_screenshot_with_prompt(app_handle, None, active_profile.output_mode).await
+        }
+        PromptMode::UserInput => {
+            // 实现用户输入prompt的逻辑
+            info!("User input prompt mode - showing input dialog");
+            handle_screenshot_with_user_input(app_handle, active_profile.output_mode).await
+        }
+    }
+}
 
-    println!("✅ [DEBUG] Profile '{}' selected successfully from tray", active_profile.name);
-    Ok(())
+// 本地自动化接口（Stream Deck、shell脚本等）：只监听127.0.0.1，每个请求都要带 Authorization: Bearer <token>
+// 在启动时根据配置开启，修改开关或端口后需要重启应用
+#[derive(Clone)]
+struct RemoteServerState {
+    app_handle: tauri::AppHandle,
+    token: String,
 }
 
-async fn refresh_models_in_tray(app_handle: tauri::AppHandle) -> Result<(), String> {
-    println!("Loading models for tray menu update...");
-    
-    // Get current active profile's API settings
-    let app_state = app_handle.state::<AppState>();
-    let active_profile = app_state.get_active_profile().await?;
-    let api_key = active_profile.api_config.api_key.clone();
-    let base_url = active_profile.api_config.base_url.clone();
-    
-    if api_key.is_empty() || base_url.is_empty() {
-        return Err("API key and base URL must be configured first".to_string());
-    }
-    
-    // Get models using the same logic as get_models command
-    let url = format!("{}/models", base_url);
-    let response = app_state.http_client
-        .get(&url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .send()
-        .await
-        .map_err(|e| format!("Failed to fetch models: {}", e))?;
-    
-    if !response.status().is_success() {
-        return Err(format!("API error: Status {}", response.status()));
+#[derive(Debug, Serialize)]
+struct RemoteResponse {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl RemoteResponse {
+    fn error(status: axum::http::StatusCode, error: impl Into<String>) -> (axum::http::StatusCode, axum::Json<RemoteResponse>) {
+        (status, axum::Json(RemoteResponse { ok: false, result: None, profile: None, error: Some(error.into()) }))
     }
-    
-    let response_text = response.text().await
-        .map_err(|_| "Failed to read response".to_string())?;
-    
-    let json: serde_json::Value = serde_json::from_str(&response_text)
-        .map_err(|_| "Invalid JSON response".to_string())?;
-    
-    let models = if let Some(data) = json.get("data").and_then(|d| d.as_array()) {
-        data.iter()
-            .filter_map(|model| {
-                model.get("id").and_then(|i| i.as_str()).map(|s| s.to_string())
-            })
-            .collect::<Vec<String>>()
-    } else {
-        return Err("Invalid response format".to_string());
-    };
-    
-    // Store the models in app state and save to cache
-    let mut loaded_models = app_state.loaded_models.lock().await;
-    *loaded_models = models.clone();
-    drop(loaded_models);
-    
-    // Save models to persistent cache
-    if let Err(e) = AppState::save_loaded_models(&models) {
-        println!("Failed to save models to cache: {}", e);
+}
+
+// 远程触发的截图最多等待的分析时间（截图本身另按capture_timeout_secs计算）
+const REMOTE_CAPTURE_ANALYSIS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+fn generate_remote_server_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+// 固定时间比较，避免通过响应时间猜测token
+fn token_matches(provided: &str, expected: &str) -> bool {
+    provided.len() == expected.len()
+        && provided.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn remote_request_authorized(headers: &axum::http::HeaderMap, token: &str) -> bool {
+    headers.get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map_or(false, |provided| !token.is_empty() && token_matches(provided.trim(), token))
+}
+
+// POST /capture：与全局热键相同的截图+分析流程，等待结果后返回
+async fn remote_capture(
+    axum::extract::State(server): axum::extract::State<RemoteServerState>,
+    headers: axum::http::HeaderMap,
+) -> (axum::http::StatusCode, axum::Json<RemoteResponse>) {
+    if !remote_request_authorized(&headers, &server.token) {
+        return RemoteResponse::error(axum::http::StatusCode::UNAUTHORIZED, "Invalid or missing token");
     }
-    
-    println!("Successfully loaded {} models for tray (will update on next build or selection)", models.len());
 
-    Ok(())
+    let app_handle = server.app_handle;
+    let state = app_handle.state::<AppState>();
+    let capture_timeout = std::time::Duration::from_secs(state.config.lock().await.capture_timeout_secs);
+
+    // 每个请求有自己的oneshot，只拿本次触发的结果，不受同时进行的热键/重新分析影响
+    let (sender, receiver) = tokio::sync::oneshot::channel::<Result<String, String>>();
+
+    info!("🌐 Remote capture requested");
+    let task_handle = app_handle.clone();
+    spawn_analysis_task(&app_handle, async move {
+        let _ = sender.send(run_global_hotkey(task_handle).await);
+    });
+
+    let outcome = tokio::time::timeout(capture_timeout + REMOTE_CAPTURE_ANALYSIS_TIMEOUT, receiver).await;
+
+    let profile = state.get_active_profile().await.ok().map(|p| p.name);
+    match outcome {
+        Ok(Ok(Ok(result))) => (
+            axum::http::StatusCode::OK,
+            axum::Json(RemoteResponse { ok: true, result: Some(result), profile, error: None }),
+        ),
+        Ok(Ok(Err(error))) => RemoteResponse::error(axum::http::StatusCode::INTERNAL_SERVER_ERROR, error),
+        Ok(Err(_)) => RemoteResponse::error(axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Capture finished without a result"),
+        Err(_) => RemoteResponse::error(axum::http::StatusCode::GATEWAY_TIMEOUT, "Timed out waiting for the capture result"),
+    }
 }
 
-#[allow(dead_code)]
-async fn select_model_in_tray(app_handle: tauri::AppHandle, model_id: String) -> Result<(), String> {
-    println!("🔍 [DEBUG] Selecting model from tray: {}", model_id);
-    
+// POST /switch-profile：切换到下一个Profile，返回切换后的Profile名称
+async fn remote_switch_profile(
+    axum::extract::State(server): axum::extract::State<RemoteServerState>,
+    headers: axum::http::HeaderMap,
+) -> (axum::http::StatusCode, axum::Json<RemoteResponse>) {
+    if !remote_request_authorized(&headers, &server.token) {
+        return RemoteResponse::error(axum::http::StatusCode::UNAUTHORIZED, "Invalid or missing token");
+    }
+
+    info!("🌐 Remote profile switch requested");
+    if let Err(e) = switch_to_next_profile(server.app_handle.clone()).await {
+        return RemoteResponse::error(axum::http::StatusCode::INTERNAL_SERVER_ERROR, e);
+    }
+
+    let profile = server.app_handle.state::<AppState>().get_active_profile().await.ok().map(|p| p.name);
+    (
+        axum::http::StatusCode::OK,
+        axum::Json(RemoteResponse { ok: true, result: None, profile, error: None }),
+    )
+}
+
+async fn start_remote_server(app_handle: tauri::AppHandle) -> Result<(), String> {
     let state = app_handle.state::<AppState>();
-    
-    // Update active profile with selected model using atomic save
-    state.update_and_save_config(|config| {
-        // Clone active profile ID to avoid borrow conflicts
-        let active_id = config.active_profile_id.clone()
-            .ok_or("No active profile")?;
 
-        // Find and update the profile
-        let profile = config.profiles.iter_mut()
-            .find(|p| p.id == active_id)
-            .ok_or("Active profile not found")?;
+    // 第一次开启时生成token并保存，用户在设置中查看
+    let mut token = state.config.lock().await.remote_server_token.clone();
+    if token.is_empty() {
+        token = generate_remote_server_token();
+        let new_token = token.clone();
+        state.update_and_save_config(|config| {
+            config.remote_server_token = new_token;
+            Ok(())
+        }).await?;
+    }
+    let port = state.config.lock().await.remote_server_port;
 
-        profile.api_config.model = model_id.clone();
-        println!("   📝 Updated model to: {}", model_id);
-        Ok(())
-    }).await?;
-    
-    println!("✓ [DEBUG] Model '{}' selected and saved to config", model_id);
-    
-    // Debug CheckMenuItem references availability
-    {
-        let model_check_items = state.model_check_items.lock().await;
-        println!("🔍 [DEBUG] CheckMenuItem storage status:");
-        println!("   - Total stored references: {}", model_check_items.len());
-        
-        if model_check_items.is_empty() {
-            println!("❌ [DEBUG] No CheckMenuItem references found!");
-            println!("   This means dynamic updates won't work - menu updates will be visible after app restart");
-            println!("   The references may not have been stored yet or storage failed");
-            return Ok(());
+    let router = axum::Router::new()
+        .route("/capture", axum::routing::post(remote_capture))
+        .route("/switch-profile", axum::routing::post(remote_switch_profile))
+        .with_state(RemoteServerState { app_handle: app_handle.clone(), token });
+
+    let listener = tokio::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, port)).await
+        .map_err(|e| format!("Failed to bind remote server to 127.0.0.1:{}: {}", port, e))?;
+    info!("🌐 Remote trigger server listening on http://127.0.0.1:{}", port);
+
+    axum::serve(listener, router).await
+        .map_err(|e| format!("Remote server stopped: {}", e))
+}
+
+async fn handle_switch_hotkey(app_handle: tauri::AppHandle) {
+    info!("Handling switch hotkey - switching to next profile");
+
+    match switch_to_next_profile(app_handle).await {
+        Ok(()) => {
+            info!("Profile switched successfully");
         }
-        
-        println!("   - Available model IDs: {:?}", model_check_items.keys().collect::<Vec<_>>());
-        println!("   - Target model ID: '{}'", model_id);
-        
-        // Verify target model exists in our references
-        if !model_check_items.contains_key(&model_id) {
-            println!("⚠️  [DEBUG] Target model '{}' not found in CheckMenuItem references!", model_id);
-            println!("   This could indicate a mismatch between loaded models and stored references");
+        Err(e) => {
+            error!("Failed to switch profile: {}", e);
         }
-        
-        println!("📝 [DEBUG] Implementing RadioButton behavior - updating {} CheckMenuItem states", model_check_items.len());
-        
-        let mut success_count = 0;
-        let mut failure_count = 0;
-        
-        for (item_model_id, check_item) in model_check_items.iter() {
-            let should_be_checked = item_model_id == &model_id;
-            
-            println!("   🔄 Processing '{}': setting checked={}", item_model_id, should_be_checked);
-            
-            // Use the dynamic update API
-            match check_item.set_checked(should_be_checked) {
-                Ok(()) => {
-                    success_count += 1;
-                    println!("      ✓ Successfully updated '{}' to checked={}", item_model_id, should_be_checked);
-                }
-                Err(e) => {
-                    failure_count += 1;
-                    println!("      ✗ Failed to update '{}': {}", item_model_id, e);
-                }
-            }
+    }
+}
+
+async fn handle_switch_previous_hotkey(app_handle: tauri::AppHandle) {
+    info!("Handling previous-profile hotkey - switching to previous profile");
+
+    match switch_to_previous_profile(app_handle).await {
+        Ok(()) => {
+            info!("Profile switched successfully");
         }
-        
-        println!("📊 [DEBUG] RadioButton update summary:");
-        println!("   - Successful updates: {}", success_count);
-        println!("   - Failed updates: {}", failure_count);
-        println!("   - Total processed: {}", model_check_items.len());
-        
-        if failure_count > 0 {
-            println!("⚠️  [DEBUG] Some CheckMenuItem updates failed - dynamic updates may not be fully working");
-        } else {
-            println!("✅ [DEBUG] All CheckMenuItem updates completed successfully");
+        Err(e) => {
+            error!("Failed to switch profile: {}", e);
         }
     }
-    
-    // Update the submenu title to reflect the new selection
-    println!("🔄 [DEBUG] Updating submenu title to show selected model...");
-    {
-        match state.model_submenu.try_lock() {
-            Ok(submenu_ref) => {
-                if let Some(submenu) = &*submenu_ref {
-                    let new_title = format!("Model: {}", model_id);
-                    println!("   📝 Attempting to update submenu title to: '{}'", new_title);
-                    
-                    match submenu.set_text(&new_title) {
-                        Ok(()) => {
-                            println!("   ✅ Successfully updated submenu title to '{}'", new_title);
-                        }
-                        Err(e) => {
-                            println!("   ❌ Failed to update submenu title: {}", e);
-                        }
-                    }
-                } else {
-                    println!("   ⚠️  No submenu reference available for title update");
-                }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RerunResultEvent {
+    profile: String,
+    model: String,
+    result: String,
+}
+
+// 按配置中的顺序取after_id之后的下一个Profile（循环）；找不到after_id时从第一个开始
+fn next_profile_after<'a>(profiles: &'a [Profile], after_id: Option<&str>) -> Option<&'a Profile> {
+    let next_index = after_id
+        .and_then(|id| profiles.iter().position(|p| p.id == id))
+        .map_or(0, |index| (index + 1) % profiles.len());
+    profiles.get(next_index)
+}
+
+// 用下一个Profile重新分析最近一次截图（不切换活跃Profile），连续按下依次轮换，方便对比不同模型。
+// 结果前加上 [Profile · 模型] 标注后复制到剪贴板
+async fn handle_rerun_hotkey(app_handle: tauri::AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let image_data = match state.last_capture.lock().await.clone() {
+        Some(image_data) => image_data,
+        None => {
+            warn!("No previous capture to re-run");
+            let _ = app_handle.emit("analysis_error", "No previous capture to re-run".to_string());
+            return;
+        }
+    };
+
+    let previous_rerun = state.rerun_profile_id.lock().await.clone();
+    let profile = {
+        let config = state.config.lock().await;
+        let after_id = previous_rerun.or_else(|| config.active_profile_id.clone());
+        next_profile_after(&config.profiles, after_id.as_deref()).cloned()
+    };
+    let Some(profile) = profile else {
+        error!("No profiles available to re-run the last capture");
+        return;
+    };
+    *state.rerun_profile_id.lock().await = Some(profile.id.clone());
+
+    info!("🔁 Re-running last capture with profile '{}' ({})", profile.name, profile.api_config.model);
+    let _queue_guard = state.analysis_queue.lock().await;
+    let clipboard_guard = state.config.lock().await.stream_to_clipboard
+        .then(|| ClipboardRestoreGuard::new(state.partial_clipboard_text.clone()));
+    set_tray_status(&app_handle, Some("⏳"));
+    let result = analyze_image_with_profile(vec![image_data], state.clone(), None, Some(app_handle.clone()), profile.clone()).await;
+    set_tray_status(&app_handle, None);
+
+    match result {
+        Ok(result) => {
+            if let Some(guard) = clipboard_guard {
+                guard.disarm();
             }
-            Err(e) => {
-                println!("   ❌ Failed to acquire submenu lock for title update: {}", e);
+            let result = apply_output_transforms(&profile, &result);
+            let labeled = format!("[{} · {}]\n\n{}", profile.name, profile.api_config.model, result);
+            if let Err(e) = copy_to_clipboard(labeled).await {
+                error!("Failed to copy to clipboard: {}", e);
+            }
+
+            if state.config.lock().await.sound_enabled {
+                if let Err(e) = play_system_sound(state.clone()).await {
+                    error!("Failed to play sound: {}", e);
+                }
             }
+            record_last_status(&app_handle, None).await;
+            let _ = app_handle.emit("rerun_result", RerunResultEvent {
+                profile: profile.name.clone(),
+                model: profile.api_config.model.clone(),
+                result,
+            });
+        }
+        Err(e) => {
+            error!("Re-run with profile '{}' failed: {}", profile.name, e);
+            record_last_status(&app_handle, Some(sanitize_error(&e))).await;
+            let debug_mode = state.config.lock().await.debug_mode;
+            let _ = app_handle.emit("analysis_error", format!("{}: {}", profile.name, user_facing_error(&e, debug_mode)));
         }
     }
-    
-    println!("✅ [DEBUG] Model '{}' selection process completed", model_id);
-    Ok(())
 }
 
-#[allow(dead_code)]
-async fn toggle_sound_setting(app_handle: tauri::AppHandle) -> Result<(), String> {
-    println!("🔧 [DEBUG] Toggling sound setting...");
-    
-    let state = app_handle.state::<AppState>();
-    
-    state.update_and_save_config(|config| {
-        config.sound_enabled = !config.sound_enabled;
-        let new_sound_state = config.sound_enabled;
-        println!("   📝 Sound setting toggled to: {}", new_sound_state);
-        Ok(())
-    }).await?;
-
-    // Read new state and update tray menu item text
+// 把识别结果POST到webhook，非2xx响应视为失败
+async fn post_result_to_webhook(
+    app_handle: &tauri::AppHandle,
+    url: &str,
+    headers: &std::collections::HashMap<String, String>,
+    result: &str,
+) -> Result<(), String> {
     let state = app_handle.state::<AppState>();
-    let enabled = {
-        let cfg = state.config.lock().await;
-        cfg.sound_enabled
+    let profile = state.get_active_profile().await?;
+    let payload = WebhookPayload {
+        result,
+        model: &profile.api_config.model,
+        profile: &profile.name,
+        timestamp: chrono::Local::now().to_rfc3339(),
     };
-    if let Err(e) = update_sound_menu_text(&app_handle, enabled).await {
-        println!("⚠️ [WARNING] Failed to update sound menu text: {}", e);
+
+    let mut request = state.http_client.post(url).json(&payload);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
     }
 
-    println!("✅ [DEBUG] Sound setting updated successfully");
-    Ok(())
-}
+    let response = request.send().await
+        .map_err(|e| format!("Webhook request failed: {}", e))?;
 
-#[tauri::command]
-async fn get_loaded_models(state: State<'_, AppState>) -> Result<Vec<String>, String> {
-    let loaded_models = state.loaded_models.lock().await;
-    Ok(loaded_models.clone())
-}
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Webhook returned status {}: {}", status, body));
+    }
 
-#[tauri::command]
-async fn refresh_tray_models(app_handle: tauri::AppHandle) -> Result<(), String> {
-    refresh_models_in_tray(app_handle).await
+    info!("📤 Posted result to webhook ({})", status);
+    Ok(())
 }
 
-#[tauri::command]
-async fn refresh_tray_menu(app_handle: tauri::AppHandle) -> Result<(), String> {
-    // 刷新整个托盘菜单，包括Profile列表
-    println!("Refreshing tray menu with updated profiles");
-
-    // 获取当前配置
-    let app_state = app_handle.state::<AppState>();
-    let config = app_state.config.lock().await;
-    let current_config = config.clone();
-    drop(config);
+async fn capture_with_mode(app_handle: &tauri::AppHandle, capture_mode: CaptureMode, capture_timeout: std::time::Duration) -> Result<String, String> {
+    match capture_mode {
+        CaptureMode::Region => take_interactive_region_screenshot(capture_timeout).await,
+        CaptureMode::Window => take_interactive_window_screenshot(capture_timeout).await,
+        CaptureMode::Overlay => match take_overlay_screenshot(app_handle, capture_timeout).await {
+            Err(e) if e != "Screenshot was cancelled" => {
+                warn!("Overlay region picker failed, falling back to native capture: {}", e);
+                take_interactive_region_screenshot(capture_timeout).await
+            }
+            result => result,
+        },
+    }
+}
 
-    // 获取活跃profile的模型信息
-    let model_display = if let Some(active_id) = &current_config.active_profile_id {
-        if let Some(profile) = current_config.profiles.iter().find(|p| &p.id == active_id) {
-            if profile.api_config.model.is_empty() {
-                "Not Selected".to_string()
-            } else {
-                profile.api_config.model.clone()
+// 多区域截图最多的区域数
+const MAX_MULTI_REGIONS: usize = 8;
+
+// 连续截取多个区域，直到用户取消（Esc）或达到MAX_MULTI_REGIONS；一个都没有截取时按取消处理
+async fn capture_multiple_regions(app_handle: &tauri::AppHandle, capture_mode: CaptureMode, capture_timeout: std::time::Duration) -> Result<Vec<String>, String> {
+    let mut images = Vec::new();
+    while images.len() < MAX_MULTI_REGIONS {
+        let _ = app_handle.emit("capture_pending", ());
+        let status = format!("📷{}", images.len() + 1);
+        set_tray_status(app_handle, Some(status.as_str()));
+        let result = capture_with_mode(app_handle, capture_mode, capture_timeout).await;
+        set_tray_status(app_handle, None);
+
+        match result {
+            Ok(image_data) => {
+                info!("📷 Captured region {} for multi-region request", images.len() + 1);
+                images.push(image_data);
             }
-        } else {
-            "Not Selected".to_string()
+            Err(e) if e == "Screenshot was cancelled" => break,
+            Err(e) => return Err(e),
         }
-    } else {
-        "Not Selected".to_string()
-    };
+    }
 
-    // 使用现有的托盘菜单更新逻辑
-    update_tray_menu(app_handle, Some(model_display), Some(current_config.sound_enabled)).await
+    if images.is_empty() {
+        return Err("Screenshot was cancelled".to_string());
+    }
+    Ok(images)
 }
 
+// 截取多个区域（如题目和配图在屏幕上分开的情况），作为同一条消息中的多张图片发送，返回一个结果
 #[tauri::command]
-async fn update_hotkeys(app_handle: tauri::AppHandle, state: State<'_, AppState>, global_hotkey: String, switch_hotkey: String) -> Result<(), String> {
-    println!("🔧 [DEBUG] Updating hotkeys - Global: {}, Switch: {}", global_hotkey, switch_hotkey);
+async fn take_multi_region(app_handle: tauri::AppHandle, state: State<'_, AppState>, prompt: Option<String>) -> Result<String, String> {
+    let active_profile = state.get_active_profile().await?;
+    let capture_timeout = std::time::Duration::from_secs(state.config.lock().await.capture_timeout_secs);
 
-    // Update config atomically
-    state.update_and_save_config(|config| {
-        config.global_hotkey = global_hotkey.clone();
-        config.switch_profile_hotkey = switch_hotkey.clone();
-        println!("   📝 Updated hotkeys in config");
-        Ok(())
-    }).await?;
+    let _ = app_handle.emit("capture_started", ());
+    let captures = match capture_multiple_regions(&app_handle, active_profile.capture_mode, capture_timeout).await {
+        Ok(captures) => captures,
+        Err(e) => {
+            error!("Screenshot error: {}", e);
+            let _ = app_handle.emit("screenshot_error", e.clone());
+            return Err(e);
+        }
+    };
+    info!("Analyzing {} regions in one request", captures.len());
 
-    // Update current hotkey tracking
-    {
-        let mut current_global = state.current_global_hotkey.lock().await;
-        *current_global = Some(global_hotkey.clone());
+    let mut images = Vec::with_capacity(captures.len());
+    for image_data in captures {
+        if let Some(info) = image_data_url_info(&image_data) {
+            let _ = app_handle.emit("capture_finished", info);
+        }
+        archive_capture(&state, &image_data).await;
+        images.push(preprocess_image_data_url(&image_data, &active_profile.preprocess).unwrap_or_else(|e| {
+            warn!("Image preprocessing failed, sending original: {}", e);
+            image_data
+        }));
     }
-    {
-        let mut current_switch = state.current_switch_hotkey.lock().await;
-        *current_switch = Some(switch_hotkey.clone());
+
+    // 重新分析只支持单张截图，缓存第一个区域
+    *state.last_capture.lock().await = images.first().cloned();
+    *state.rerun_profile_id.lock().await = None;
+
+    // 与单张截图共用分析队列
+    let analysis_queue = state.analysis_queue.clone();
+    let queued_captures = state.queued_captures.clone();
+    let depth = queued_captures.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+    let _ = app_handle.emit("capture_queue_changed", CaptureQueueEvent { depth });
+    if depth > 1 {
+        info!("Analysis in progress, capture queued (depth {})", depth);
     }
+    let _queue_guard = analysis_queue.lock().await;
+
+    let analysis_started = std::time::Instant::now();
+    let debug_mode = state.config.lock().await.debug_mode;
+    let outcome = match analyze_images_with_prompt(images, state, prompt, Some(app_handle.clone())).await {
+        Ok(result) => {
+            let result = apply_output_transforms(&active_profile, &result);
+            record_last_status(&app_handle, None).await;
+            let _ = app_handle.emit("analysis_complete", AnalysisCompleteEvent {
+                chars: result.chars().count(),
+                duration_ms: analysis_started.elapsed().as_millis() as u64,
+            });
+            let _ = app_handle.emit("analysis_result", result.clone());
+            Ok(result)
+        }
+        Err(e) => {
+            error!("Analysis error: {}", e);
+            record_last_status(&app_handle, Some(sanitize_error(&e))).await;
+            let message = user_facing_error(&e, debug_mode);
+            let _ = app_handle.emit("analysis_error", message.clone());
+            Err(message)
+        }
+    };
 
-    // Use internal registration function (clone to avoid moving the originals)
-    let gh = global_hotkey.clone();
-    let sh = switch_hotkey.clone();
-    register_hotkeys_internal(app_handle.clone(), gh, sh).await?;
+    let depth = queued_captures.fetch_sub(1, std::sync::atomic::Ordering::SeqCst) - 1;
+    let _ = app_handle.emit("capture_queue_changed", CaptureQueueEvent { depth });
+    outcome
+}
 
-    // Update tray menu items text in-place
-    println!("🔧 [DEBUG] Updating tray menu hotkey labels in-place...");
-    if let Err(e) = update_hotkey_menu_text(&app_handle, &global_hotkey, &switch_hotkey).await {
-        println!("⚠️ [WARNING] Failed to update hotkey labels: {}", e);
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewDecision {
+    Send,
+    Recapture,
+    Cancel,
+}
 
-    println!("✅ [DEBUG] Hotkeys updated and re-registered successfully - no restart required!");
+const CAPTURE_PREVIEW_LABEL: &str = "capture_preview";
+
+// 预览窗口加载后通过该命令获取待确认的截图
+#[tauri::command]
+async fn get_capture_preview(state: State<'_, AppState>) -> Result<String, String> {
+    state.capture_preview.lock().await.as_ref()
+        .map(|(image_data, _)| image_data.clone())
+        .ok_or_else(|| "No capture waiting for preview".to_string())
+}
+
+#[tauri::command]
+async fn submit_capture_preview(state: State<'_, AppState>, decision: PreviewDecision) -> Result<(), String> {
+    let (_, sender) = state.capture_preview.lock().await.take()
+        .ok_or("No capture waiting for preview")?;
+    let _ = sender.send(decision);
     Ok(())
 }
 
-// 内部热键注册函数，不包含托盘菜单更新
-async fn register_hotkeys_internal(app_handle: tauri::AppHandle, global_hotkey: String, switch_hotkey: String) -> Result<(), String> {
-    println!("🔧 [DEBUG] Registering hotkeys internally - Global: {}, Switch: {}", global_hotkey, switch_hotkey);
-    
-    // Unregister all current shortcuts
-    if let Err(e) = app_handle.global_shortcut().unregister_all() {
-        println!("⚠️ [WARNING] Failed to unregister existing shortcuts: {}", e);
-    } else {
-        println!("✅ [DEBUG] Unregistered all existing shortcuts");
+// 显示截图预览窗口并等待用户选择；窗口被直接关闭时视为Cancel
+async fn confirm_capture_preview(app_handle: &tauri::AppHandle, image_data: &str) -> Result<PreviewDecision, String> {
+    if let Some(existing) = app_handle.get_webview_window(CAPTURE_PREVIEW_LABEL) {
+        let _ = existing.destroy();
     }
-    
-    // Parse and register new shortcuts
-    let global_shortcut = global_hotkey.parse::<tauri_plugin_global_shortcut::Shortcut>()
-        .map_err(|e| format!("Invalid global hotkey '{}': {}", global_hotkey, e))?;
-    
-    let switch_shortcut = switch_hotkey.parse::<tauri_plugin_global_shortcut::Shortcut>()
-        .map_err(|e| format!("Invalid switch hotkey '{}': {}", switch_hotkey, e))?;
-    
-    // Register global shortcut
-    app_handle.global_shortcut().on_shortcut(global_shortcut.clone(), move |app, shortcut, event| {
-        if event.state == ShortcutState::Pressed {
-            println!("Global shortcut triggered: {}", shortcut);
-            let app_handle = app.app_handle().clone();
-            tauri::async_runtime::spawn(async move {
-                handle_global_hotkey(app_handle).await;
-            });
+
+    let state = app_handle.state::<AppState>();
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    *state.capture_preview.lock().await = Some((image_data.to_string(), sender));
+
+    let window = tauri::WebviewWindowBuilder::new(app_handle, CAPTURE_PREVIEW_LABEL, tauri::WebviewUrl::App("preview.html".into()))
+        .title("MathImage Preview")
+        .inner_size(480.0, 400.0)
+        .center()
+        .always_on_top(true)
+        .focused(true)
+        .build();
+    let window = match window {
+        Ok(window) => window,
+        Err(e) => {
+            state.capture_preview.lock().await.take();
+            return Err(format!("Failed to open capture preview: {}", e));
         }
-    }).map_err(|e| format!("Failed to register global hotkey '{}': {}", global_hotkey, e))?;
-    
-    // Register switch shortcut  
-    app_handle.global_shortcut().on_shortcut(switch_shortcut.clone(), move |app, shortcut, event| {
-        if event.state == ShortcutState::Pressed {
-            println!("Switch shortcut triggered: {}", shortcut);
-            let app_handle = app.app_handle().clone();
-            tauri::async_runtime::spawn(async move {
-                handle_switch_hotkey(app_handle).await;
-            });
+    };
+
+    // 点标题栏关闭按钮时发送端仍留在AppState中，receiver不会结束，需要单独监听这个窗口的销毁
+    let (closed_sender, closed_receiver) = tokio::sync::oneshot::channel::<()>();
+    let closed_sender = std::sync::Mutex::new(Some(closed_sender));
+    window.on_window_event(move |event| {
+        if let tauri::WindowEvent::Destroyed = event {
+            if let Some(sender) = closed_sender.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                let _ = sender.send(());
+            }
         }
-    }).map_err(|e| format!("Failed to register switch hotkey '{}': {}", switch_hotkey, e))?;
+    });
 
-    println!("✅ [DEBUG] Hotkeys registered successfully");
-    Ok(())
-}
+    let decision = tokio::select! {
+        decision = receiver => decision.unwrap_or(PreviewDecision::Cancel),
+        _ = closed_receiver => PreviewDecision::Cancel,
+    };
+    let _ = window.destroy();
+    state.capture_preview.lock().await.take();
 
-// 保持向后兼容的单热键更新函数
-#[tauri::command]
-async fn update_hotkey(app_handle: tauri::AppHandle, new_hotkey: String, state: State<'_, AppState>) -> Result<(), String> {
-    println!("🔧 [DEBUG] Updating global hotkey to: {}", new_hotkey);
+    debug!("🖼️ Capture preview decision: {:?}", decision);
+    Ok(decision)
+}
 
-    // Parse the new hotkey
-    let shortcut: Shortcut = new_hotkey.parse()
-        .map_err(|e| format!("Invalid hotkey format '{}': {}", new_hotkey, e))?;
+// 返回最终结果或错误；事件照常发出。prompt为None时使用分析时活跃Profile的prompt
+async fn handle_screenshot_with_prompt(app_handle: tauri::AppHandle, prompt: Option<String>, output_mode: OutputMode) -> Result<String, String> {
+    let _ = app_handle.emit("capture_started", ());
+
+    // screencapture启动到出现选区遮罩之间有延迟，先给出反馈
+    let (capture_timeout, capture_mode) = match app_handle.try_state::<AppState>() {
+        Some(state) => (
+            std::time::Duration::from_secs(state.config.lock().await.capture_timeout_secs),
+            state.get_active_profile().await.map(|p| p.capture_mode).unwrap_or_default(),
+        ),
+        None => (std::time::Duration::from_secs(default_capture_timeout_secs()), CaptureMode::Region),
+    };
+    let preview_before_send = match app_handle.try_state::<AppState>() {
+        Some(state) => state.config.lock().await.preview_before_send,
+        None => false,
+    };
 
-    // Get current global hotkey and unregister it
-    let current_hotkey = {
-        let current_hotkey_lock = state.current_global_hotkey.lock().await;
-        current_hotkey_lock.clone()
+    let capture_result = loop {
+        let _ = app_handle.emit("capture_pending", ());
+        set_tray_status(&app_handle, Some("📷"));
+        let result = capture_with_mode(&app_handle, capture_mode, capture_timeout).await;
+        set_tray_status(&app_handle, None);
+
+        // 预览确认：Recapture重新截图，Cancel按用户取消处理
+        match result {
+            Ok(image_data) if preview_before_send => match confirm_capture_preview(&app_handle, &image_data).await {
+                Ok(PreviewDecision::Send) => break Ok(image_data),
+                Ok(PreviewDecision::Recapture) => continue,
+                Ok(PreviewDecision::Cancel) => break Err("Screenshot was cancelled".to_string()),
+                Err(e) => {
+                    warn!("Capture preview failed, sending without preview: {}", e);
+                    break Ok(image_data);
+                }
+            },
+            result => break result,
+        }
     };
 
-    if let Some(current) = current_hotkey {
-        println!("Unregistering current global hotkey: {}", current);
-        if let Ok(current_shortcut) = current.parse::<Shortcut>() {
-            if let Err(e) = app_handle.global_shortcut().unregister(current_shortcut) {
-                println!("Warning: Failed to unregister current global hotkey '{}': {}", current, e);
+    match capture_result {
+        Ok(image_data) => {
+            if let Some(info) = image_data_url_info(&image_data) {
+                let _ = app_handle.emit("capture_finished", info);
             }
-        }
-    }
-
-    // Register new hotkey
-    if let Err(e) = app_handle.global_shortcut().register(shortcut) {
-        return Err(format!("Failed to register new global hotkey '{}': {}", new_hotkey, e));
-    }
 
-    // Update stored current hotkey
-    {
-        let mut current_hotkey_lock = state.current_global_hotkey.lock().await;
-        *current_hotkey_lock = Some(new_hotkey.clone());
-    }
+            // 复制图片+文本时使用未经预处理的原始截图
+            let original_image = matches!(output_mode, OutputMode::ClipboardImageText).then(|| image_data.clone());
+            let state = app_handle.try_state::<AppState>().ok_or("App state is not available")?;
+            archive_capture(&state, &image_data).await;
+
+            // 应用Profile的图像预处理
+            let image_data = match state.get_active_profile().await {
+                Ok(profile) => preprocess_image_data_url(&image_data, &profile.preprocess).unwrap_or_else(|e| {
+                    warn!("Image preprocessing failed, sending original: {}", e);
+                    image_data
+                }),
+                Err(_) => image_data,
+            };
 
-    // Update config atomically
-    state.update_and_save_config(|config| {
-        config.global_hotkey = new_hotkey.clone();
-        println!("   📝 Updated global hotkey in config");
-        Ok(())
-    }).await?;
+            // 缓存截图以便用不同的prompt重新分析
+            *state.last_capture.lock().await = Some(image_data.clone());
+            *state.rerun_profile_id.lock().await = None;
+
+            // 排队等待前面的分析完成，避免多个请求同时进行
+            let analysis_queue = state.analysis_queue.clone();
+            let queued_captures = state.queued_captures.clone();
+            let depth = queued_captures.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            let _ = app_handle.emit("capture_queue_changed", CaptureQueueEvent { depth });
+            if depth > 1 {
+                info!("Analysis in progress, capture queued (depth {})", depth);
+            }
+            let _queue_guard = analysis_queue.lock().await;
 
-    println!("✅ [DEBUG] Global hotkey successfully updated to: {}", new_hotkey);
-    Ok(())
-}
+            // 只有流式写入剪贴板时才会在成功前改动剪贴板，此时先保存原内容
+            let clipboard_guard = (state.config.lock().await.stream_to_clipboard && matches!(output_mode, OutputMode::Clipboard | OutputMode::ClipboardToast))
+                .then(|| ClipboardRestoreGuard::new(state.partial_clipboard_text.clone()));
 
-// 热键处理函数
-async fn handle_global_hotkey(app_handle: tauri::AppHandle) {
-    println!("Handling global hotkey - taking screenshot and analyzing");
+            let analysis_started = std::time::Instant::now();
 
-    // 获取当前活跃的profile
-    if let Some(state) = app_handle.try_state::<AppState>() {
-        match state.get_active_profile().await {
-            Ok(active_profile) => {
-                println!("Using profile: {} ({})", active_profile.name, active_profile.id);
-
-                // 根据profile的prompt模式处理
-                match active_profile.prompt_mode {
-                    PromptMode::Predefined(prompt) => {
-                        // 使用预定义prompt进行截图和分析
-                        handle_screenshot_with_prompt(app_handle, prompt, active_profile.output_mode).await;
+            // 传递自定义prompt；大图按Profile设置分块分析
+            let outcome = match analyze_image_tiled(image_data.clone(), state, prompt.clone(), Some(app_handle.clone())).await {
+                Ok(result) => {
+                    info!("Analysis result: {}", result);
+                    if let Some(guard) = clipboard_guard {
+                        guard.disarm();
                     }
-                    PromptMode::UserInput => {
-                        // 实现用户输入prompt的逻辑
-                        println!("User input prompt mode - showing input dialog");
-                        handle_screenshot_with_user_input(app_handle, active_profile.output_mode).await;
-                    }
-                }
-            }
-            Err(e) => {
-                println!("Failed to get active profile: {}", e);
-            }
-        }
-    }
-}
 
-async fn handle_switch_hotkey(app_handle: tauri::AppHandle) {
-    println!("Handling switch hotkey - switching to next profile");
+                    // 应用profile的输出变换链
+                    let result = match app_handle.state::<AppState>().get_active_profile().await {
+                        Ok(profile) => apply_output_transforms(&profile, &result),
+                        Err(_) => result,
+                    };
 
-    match switch_to_next_profile(app_handle).await {
-        Ok(()) => {
-            println!("Profile switched successfully");
-        }
-        Err(e) => {
-            println!("Failed to switch profile: {}", e);
-        }
-    }
-}
+                    // ClipboardToast自己显示通知，不再重复显示成功通知
+                    let toast_shown = matches!(output_mode, OutputMode::ClipboardToast);
 
-async fn handle_screenshot_with_prompt(app_handle: tauri::AppHandle, prompt: String, output_mode: OutputMode) {
-    match take_interactive_screenshot().await {
-        Ok(image_data) => {
-            if let Some(state) = app_handle.try_state::<AppState>() {
-                // 使用新的analyze_image_with_prompt函数，传递自定义prompt
-                match analyze_image_with_prompt(image_data, state, Some(prompt), Some(app_handle.clone())).await {
-                    Ok(result) => {
-                        println!("Analysis result: {}", result);
-
-                        // 根据output_mode处理结果
-                        match output_mode {
-                            OutputMode::Clipboard => {
+                    // 根据output_mode处理结果
+                    let result = match output_mode {
+                        OutputMode::Clipboard => {
+                            if let Err(e) = copy_to_clipboard(result.clone()).await {
+                                error!("Failed to copy to clipboard: {}", e);
+                            }
+                            result
+                        }
+                        OutputMode::Dialog => {
+                            // 显示系统对话框
+                            if let Err(e) = show_system_dialog(
+                                "MathImage Analysis Result".to_string(),
+                                result.clone(),
+                                "info".to_string()
+                            ).await {
+                                error!("Failed to show system dialog: {}", e);
+                            }
+                            result
+                        }
+                        OutputMode::Type => {
+                            let (delay_ms, max_chars) = {
+                                let config = app_handle.state::<AppState>().config.lock().await;
+                                (config.typing_delay_ms, config.typing_max_chars)
+                            };
+
+                            // 过长的结果逐字输入太慢，改用剪贴板
+                            if result.chars().count() > max_chars {
+                                info!("Result exceeds {} chars, copying to clipboard instead of typing", max_chars);
+                                if let Err(e) = copy_to_clipboard(result.clone()).await {
+                                    error!("Failed to copy to clipboard: {}", e);
+                                }
+                            } else {
+                                // 等待截图遮罩关闭、目标窗口重新获得焦点
+                                tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+                                if let Err(e) = type_text(result.clone()).await {
+                                    warn!("Failed to type result, falling back to clipboard: {}", e);
+                                    if let Err(e) = copy_to_clipboard(result.clone()).await {
+                                        error!("Failed to copy to clipboard: {}", e);
+                                    }
+                                }
+                            }
+                            result
+                        }
+                        OutputMode::ActionDialog => {
+                            // Retry / Edit prompt 可能产生新的结果
+                            run_result_action_dialog(&app_handle, &image_data, prompt, result).await
+                        }
+                        OutputMode::ClipboardAppend => {
+                            let separator = app_handle.state::<AppState>().config.lock().await.clipboard_append_separator.clone();
+                            if let Err(e) = append_to_clipboard(result.clone(), &separator).await {
+                                error!("Failed to append to clipboard: {}", e);
+                            }
+                            result
+                        }
+                        OutputMode::ClipboardImageText => {
+                            let original_image = original_image.as_deref().unwrap_or(&image_data);
+                            if let Err(e) = copy_image_and_text_to_clipboard(original_image, result.clone()).await {
+                                error!("Failed to copy to clipboard: {}", e);
+                            }
+                            result
+                        }
+                        OutputMode::ClipboardToast => {
+                            match copy_to_clipboard(result.clone()).await {
+                                Ok(()) => {
+                                    if let Err(e) = show_copied_notification(&app_handle, &result) {
+                                        error!("{}", e);
+                                    }
+                                }
+                                Err(e) => error!("Failed to copy to clipboard: {}", e),
+                            }
+                            result
+                        }
+                        OutputMode::File => {
+                            let written = match app_handle.state::<AppState>().get_active_profile().await {
+                                Ok(profile) => write_result_to_file(&profile, &result),
+                                Err(e) => Err(e),
+                            };
+                            if let Err(e) = written {
+                                warn!("Failed to write result file, falling back to clipboard: {}", e);
                                 if let Err(e) = copy_to_clipboard(result.clone()).await {
-                                    println!("Failed to copy to clipboard: {}", e);
+                                    error!("Failed to copy to clipboard: {}", e);
                                 }
                             }
-                            OutputMode::Dialog => {
-                                // 显示系统对话框
-                                if let Err(e) = show_system_dialog(
-                                    "MathImage Analysis Result".to_string(),
-                                    result.clone(),
-                                    "info".to_string()
-                                ).await {
-                                    println!("Failed to show system dialog: {}", e);
+                            result
+                        }
+                        OutputMode::Webhook { url, headers, fallback_to_clipboard } => {
+                            if let Err(e) = post_result_to_webhook(&app_handle, &url, &headers, &result).await {
+                                error!("Failed to post result to webhook: {}", e);
+                                if fallback_to_clipboard {
+                                    if let Err(e) = copy_to_clipboard(result.clone()).await {
+                                        error!("Failed to copy to clipboard: {}", e);
+                                    }
                                 }
                             }
+                            result
                         }
+                    };
 
-                        // 播放成功音效
-                        if let Some(state) = app_handle.try_state::<AppState>() {
+                    // 成功提示：系统通知和音效
+                    if let Some(state) = app_handle.try_state::<AppState>() {
+                        let (sound_enabled, notify_on_success) = {
                             let config = state.config.lock().await;
-                            if config.sound_enabled {
-                                if let Err(e) = play_system_sound().await {
-                                    println!("Failed to play sound: {}", e);
-                                }
+                            (config.sound_enabled, config.notify_on_success)
+                        };
+
+                        if notify_on_success && !toast_shown {
+                            let model = state.get_active_profile().await
+                                .map(|p| p.api_config.model)
+                                .unwrap_or_default();
+                            if let Err(e) = show_success_notification(&app_handle, &model, &result) {
+                                error!("{}", e);
                             }
                         }
 
-                        // 发送事件到前端
-                        let _ = app_handle.emit("analysis_result", result);
+                        if sound_enabled {
+                            if let Err(e) = play_system_sound(state).await {
+                                error!("Failed to play sound: {}", e);
+                            }
+                        }
+                    }
+
+                    record_last_status(&app_handle, None).await;
+
+                    // 发送事件到前端
+                    let _ = app_handle.emit("analysis_complete", AnalysisCompleteEvent {
+                        chars: result.chars().count(),
+                        duration_ms: analysis_started.elapsed().as_millis() as u64,
+                    });
+                    let _ = app_handle.emit("analysis_result", result.clone());
+                    Ok(result)
+                }
+                Err(e) => {
+                    error!("Analysis error: {}", e);
+                    drop(clipboard_guard);
+                    record_last_status(&app_handle, Some(sanitize_error(&e))).await;
+                    let debug_mode = app_handle.state::<AppState>().config.lock().await.debug_mode;
+                    let message = user_facing_error(&e, debug_mode);
+                    let _ = app_handle.emit("analysis_error", message.clone());
+                    Err(message)
+                }
+            };
+
+            let depth = queued_captures.fetch_sub(1, std::sync::atomic::Ordering::SeqCst) - 1;
+            let _ = app_handle.emit("capture_queue_changed", CaptureQueueEvent { depth });
+            outcome
+        }
+        Err(e) => {
+            error!("Screenshot error: {}", e);
+            let _ = app_handle.emit("screenshot_error", e.clone());
+            Err(e)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ResultDialogAction {
+    Copy,
+    Retry,
+    EditPrompt,
+}
+
+async fn show_result_action_dialog(result: &str) -> Result<ResultDialogAction, String> {
+    use std::process::Command;
+
+    // Use macOS osascript - display dialog supports up to three buttons
+    let script = format!(
+        r#"display dialog "{}" with title "MathImage Analysis Result" with icon note buttons {{"Edit prompt", "Retry", "Copy"}} default button "Copy""#,
+        result.replace("\\", "\\\\").replace("\"", "\\\"")
+    );
+
+    let output = Command::new("osascript")
+        .arg("-e")
+        .arg(&script)
+        .output()
+        .map_err(|e| format!("Failed to show result dialog: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to show result dialog: {}", stderr));
+    }
+
+    // AppleScript returns "button returned:Copy"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.contains("button returned:Retry") {
+        Ok(ResultDialogAction::Retry)
+    } else if stdout.contains("button returned:Edit prompt") {
+        Ok(ResultDialogAction::EditPrompt)
+    } else {
+        Ok(ResultDialogAction::Copy)
+    }
+}
+
+// 结果对话框循环：Retry / Edit prompt 在同一张截图上重新分析，返回最终结果
+async fn run_result_action_dialog(app_handle: &tauri::AppHandle, image_data: &str, mut prompt: Option<String>, mut result: String) -> String {
+    loop {
+        let action = match show_result_action_dialog(&result).await {
+            Ok(action) => action,
+            Err(e) => {
+                // 退回到简单的OK对话框
+                warn!("{}, falling back to simple dialog", e);
+                if let Err(e) = show_system_dialog("MathImage Analysis Result".to_string(), result.clone(), "info".to_string()).await {
+                    error!("Failed to show system dialog: {}", e);
+                }
+                return result;
+            }
+        };
+
+        match action {
+            ResultDialogAction::Copy => {
+                if let Err(e) = copy_to_clipboard(result.clone()).await {
+                    error!("Failed to copy to clipboard: {}", e);
+                }
+                return result;
+            }
+            ResultDialogAction::Retry => {
+                info!("Retrying analysis on the same screenshot");
+            }
+            ResultDialogAction::EditPrompt => {
+                // 没有自定义prompt时以Profile当前的prompt作为编辑起点
+                let current_prompt = match &prompt {
+                    Some(prompt) => prompt.clone(),
+                    None => match app_handle.state::<AppState>().get_active_profile().await.map(|p| p.effective_prompt_mode()) {
+                        Ok(PromptMode::Predefined(text)) | Ok(PromptMode::Template(text)) => text,
+                        _ => String::new(),
+                    },
+                };
+                match show_input_dialog(app_handle.clone(), "Edit the prompt:".to_string(), current_prompt).await {
+                    Ok(new_prompt) if !new_prompt.trim().is_empty() => {
+                        info!("Re-analyzing with edited prompt: {}", new_prompt);
+                        prompt = Some(new_prompt);
                     }
+                    Ok(_) => continue,
                     Err(e) => {
-                        println!("Analysis error: {}", e);
-                        let _ = app_handle.emit("analysis_error", sanitize_error(&e));
+                        info!("Prompt edit cancelled: {}", e);
+                        continue;
                     }
                 }
             }
         }
-        Err(e) => {
-            println!("Screenshot error: {}", e);
-            let _ = app_handle.emit("screenshot_error", e);
+
+        match analyze_image_tiled(image_data.to_string(), app_handle.state::<AppState>(), prompt.clone(), Some(app_handle.clone())).await {
+            Ok(new_result) => {
+                result = match app_handle.state::<AppState>().get_active_profile().await {
+                    Ok(profile) => apply_output_transforms(&profile, &new_result),
+                    Err(_) => new_result,
+                };
+            }
+            Err(e) => {
+                error!("Re-analysis error: {}", e);
+                let debug_mode = app_handle.state::<AppState>().config.lock().await.debug_mode;
+                if let Err(e) = show_system_dialog("MathImage Error".to_string(), user_facing_error(&e, debug_mode), "error".to_string()).await {
+                    error!("Failed to show system dialog: {}", e);
+                }
+            }
         }
     }
 }
 
 async fn show_input_dialog(_app_handle: tauri::AppHandle, title: String, default_text: String) -> Result<String, String> {
     use std::process::Command;
-    println!("Showing input dialog: {}", title);
+    info!("Showing input dialog: {}", title);
     
     // Use macOS osascript to show text input dialog
     let script = format!(
@@ -1945,43 +8527,48 @@ async fn show_input_dialog(_app_handle: tauri::AppHandle, title: String, default
     }
 }
 
-async fn handle_screenshot_with_user_input(app_handle: tauri::AppHandle, output_mode: OutputMode) {
+async fn handle_screenshot_with_user_input(app_handle: tauri::AppHandle, output_mode: OutputMode) -> Result<String, String> {
     // 首先显示输入对话框获取用户自定义prompt
     match show_input_dialog(app_handle.clone(), "Enter your prompt:".to_string(), "请输入分析图片的提示词...".to_string()).await {
         Ok(user_prompt) => {
             if !user_prompt.trim().is_empty() {
-                println!("User provided prompt: {}", user_prompt);
+                info!("User provided prompt: {}", user_prompt);
                 // 使用用户输入的prompt处理截图
-                handle_screenshot_with_prompt(app_handle, user_prompt, output_mode).await;
+                handle_screenshot_with_prompt(app_handle, Some(user_prompt), output_mode).await
             } else {
-                println!("User cancelled or provided empty prompt");
+                info!("User cancelled or provided empty prompt");
+                Err("No prompt provided".to_string())
             }
         }
         Err(e) => {
-            println!("Failed to get user input: {}", e);
+            error!("Failed to get user input: {}", e);
+            Err(e)
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
+    init_logging();
     let app_state = AppState::new();
     
     // Get initial hotkeys for plugin setup
     let (global_hotkey, switch_hotkey) = {
         let config = app_state.config.lock().await;
-        println!("Loading global hotkey from config: {}", config.global_hotkey);
-        println!("Loading switch hotkey from config: {}", config.switch_profile_hotkey);
+        apply_log_level(&config.log_level);
+        info!("Loading global hotkey from config: {}", config.global_hotkey);
+        info!("Loading switch hotkey from config: {}", config.switch_profile_hotkey);
         (config.global_hotkey.clone(), config.switch_profile_hotkey.clone())
     };
 
-    println!("Registering global shortcuts: {} (global), {} (switch)", global_hotkey, switch_hotkey);
+    info!("Registering global shortcuts: {} (global), {} (switch)", global_hotkey, switch_hotkey);
 
     tauri::Builder::default()
         .plugin(
             tauri_plugin_global_shortcut::Builder::new()
                 .build(),
         )
+        .plugin(tauri_plugin_notification::init())
         .manage(app_state)
         .invoke_handler(tauri::generate_handler![
             get_config,
@@ -1991,12 +8578,38 @@ async fn main() {
             update_profile_config,
             delete_profile,
             set_active_profile,
+            reorder_profiles,
+            rename_profile,
+            open_config_dir,
+            reset_all_data,
+            submit_region_selection,
+            get_capture_preview,
+            submit_capture_preview,
+            export_profiles,
+            import_profiles,
             // 其他功能
             get_models,
+            test_connection,
             get_loaded_models,
+            clear_model_cache,
+            remove_cached_model,
+            clear_analysis_cache,
+            get_endpoint_health,
+            get_profile_stats,
+            get_status,
+            set_active_model,
             take_interactive_screenshot,
             take_screenshot_region,
+            take_window_screenshot,
+            list_screens,
             analyze_image,
+            analyze_clipboard_image,
+            take_multi_region,
+            reanalyze_last,
+            get_recent_logs,
+            analyze_folder,
+            preview_output_transforms,
+            preview_prompt,
             copy_to_clipboard,
             update_tray_model,
             play_system_sound,
@@ -2008,10 +8621,34 @@ async fn main() {
             update_hotkeys
         ])
         .on_window_event(|webview_window, event| match event {
+            // 只处理设置窗口，区域选择和截图预览窗口由各自的函数管理
+            _ if webview_window.label() != "main" => {}
             tauri::WindowEvent::CloseRequested { api, .. } => {
                 // Hide window instead of closing
                 webview_window.hide().unwrap();
                 api.prevent_close();
+
+                // 隐藏时把窗口几何信息写入配置
+                let app_handle = webview_window.app_handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Some(state) = app_handle.try_state::<AppState>() {
+                        let config = state.config.lock().await.clone();
+                        if let Err(e) = AppState::save_config_atomic(&config).await {
+                            error!("Failed to save window state: {}", e);
+                        }
+                    }
+                });
+            }
+            tauri::WindowEvent::Moved(_) | tauri::WindowEvent::Resized(_) => {
+                // 只更新内存中的配置，避免拖动时频繁写盘
+                if let Some(window_state) = current_window_state(webview_window) {
+                    let app_handle = webview_window.app_handle().clone();
+                    tauri::async_runtime::spawn(async move {
+                        if let Some(state) = app_handle.try_state::<AppState>() {
+                            state.config.lock().await.window_state = Some(window_state);
+                        }
+                    });
+                }
             }
             _ => {}
         })
@@ -2026,6 +8663,16 @@ async fn main() {
                 }
             };
 
+            // 本地HTTP触发接口（可选）
+            if initial_config.remote_server_enabled {
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = start_remote_server(app_handle).await {
+                        error!("❌ {}", e);
+                    }
+                });
+            }
+
             // Initialize hotkey registration
             let app_handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
@@ -2033,31 +8680,39 @@ async fn main() {
                     let config = state.config.lock().await;
                     let global_hotkey = config.global_hotkey.clone();
                     let switch_hotkey = config.switch_profile_hotkey.clone();
+                    let previous_hotkey = config.switch_previous_profile_hotkey.clone();
+                    let rerun_hotkey = config.rerun_last_hotkey.clone();
                     drop(config);
                     
-                    println!("🔧 [DEBUG] Registering initial hotkeys: {} (global), {} (switch)", global_hotkey, switch_hotkey);
+                    debug!("🔧 Registering initial hotkeys: {} (global), {} (switch), {} (previous), {} (rerun)", global_hotkey, switch_hotkey, previous_hotkey, rerun_hotkey);
                     
                     // 使用内部热键注册函数，避免触发托盘菜单更新
-                    if let Err(e) = register_hotkeys_internal(app_handle.clone(), global_hotkey, switch_hotkey).await {
-                        eprintln!("❌ [ERROR] Failed to register initial hotkeys: {}", e);
+                    if let Err(e) = register_hotkeys_internal(app_handle.clone(), global_hotkey, switch_hotkey, previous_hotkey, rerun_hotkey).await {
+                        error!("❌ Failed to register initial hotkeys: {}", e);
                     } else {
-                        println!("✅ [DEBUG] Initial hotkeys registered successfully");
+                        debug!("✅ Initial hotkeys registered successfully");
                     }
                 }
             });
 
-            // Try to get pre-loaded models from app state
+            // Try to get pre-loaded models for the active profile's base_url from app state
             let loaded_models = {
+                let active_base_url = initial_config.profiles.iter()
+                    .find(|p| Some(&p.id) == initial_config.active_profile_id.as_ref())
+                    .or_else(|| initial_config.profiles.first())
+                    .map(|p| p.api_config.base_url.clone())
+                    .unwrap_or_default();
                 match app_state.loaded_models.try_lock() {
-                    Ok(models) => models.clone(),
+                    Ok(models) => models.get(&active_base_url).cloned().unwrap_or_default(),
                     Err(_) => Vec::new()
                 }
             };
 
-            println!("Creating tray menu with {} pre-loaded models", loaded_models.len());
+            info!("Creating tray menu with {} pre-loaded models", loaded_models.len());
 
             // Create comprehensive tray menu with models if available
             let settings_item = MenuItemBuilder::new("Settings").id("settings").build(app)?;
+            let open_config_dir_item = MenuItemBuilder::new("Open Config Folder").id("open_config_dir").build(app)?;
 
             // Get active profile for display
             let active_profile = initial_config.profiles.iter()
@@ -2065,17 +8720,7 @@ async fn main() {
                 .cloned()
                 .unwrap_or_else(|| initial_config.profiles.first().cloned().unwrap_or_else(|| {
                     // Create a default profile if none exists
-                    Profile {
-                        id: uuid::Uuid::new_v4().to_string(),
-                        name: "默认配置".to_string(),
-                        api_config: ApiConfig {
-                            base_url: "http://210.126.8.197:11434/v1".to_string(),
-                            api_key: "".to_string(),
-                            model: "".to_string(),
-                        },
-                        prompt_mode: PromptMode::Predefined("识别公式和文字，返回使用pandoc语法的markdown排版内容。公式请用katex语法包裹，文字内容不要丢失。只返回内容不需要其他解释。".to_string()),
-                        output_mode: OutputMode::Clipboard,
-                    }
+                    Profile::new("默认配置".to_string())
                 }));
 
             // Profile selection submenu
@@ -2088,7 +8733,7 @@ async fn main() {
             for profile in &initial_config.profiles {
                 let is_current = Some(&profile.id) == initial_config.active_profile_id.as_ref();
 
-                println!("🔍 [DEBUG] Creating Profile CheckMenuItem for '{}', checked={}", profile.name, is_current);
+                debug!("🔍 Creating Profile CheckMenuItem for '{}', checked={}", profile.name, is_current);
 
                 let profile_item = CheckMenuItemBuilder::new(&profile.name)
                     .id(&format!("profile_{}", profile.id))
@@ -2097,36 +8742,15 @@ async fn main() {
 
                 // Store the CheckMenuItem reference
                 profile_check_items_for_storage.insert(profile.id.clone(), profile_item.clone());
-                println!("   📝 Stored Profile CheckMenuItem reference for '{}'", profile.name);
+                debug!("   📝 Stored Profile CheckMenuItem reference for '{}'", profile.name);
 
                 profile_submenu_builder = profile_submenu_builder.item(&profile_item);
             }
 
             let profile_submenu = profile_submenu_builder.build()?;
 
-            // Store the profile submenu reference for dynamic updates
-            println!("🔄 [DEBUG] Storing profile submenu reference for title updates...");
-            match app_state.profile_submenu.try_lock() {
-                Ok(mut submenu_ref) => {
-                    *submenu_ref = Some(profile_submenu.clone());
-                    println!("✅ [DEBUG] Profile submenu reference stored successfully");
-                }
-                Err(e) => {
-                    println!("❌ [DEBUG] Failed to store profile submenu reference: {}", e);
-                }
-            }
-
-            // Store profile CheckMenuItem references
-            println!("🔄 [DEBUG] Storing Profile CheckMenuItem references...");
-            match app_state.profile_check_items.try_lock() {
-                Ok(mut profile_check_items) => {
-                    *profile_check_items = profile_check_items_for_storage;
-                    println!("✅ [DEBUG] Profile CheckMenuItem references stored successfully");
-                }
-                Err(e) => {
-                    println!("❌ [DEBUG] Failed to store Profile CheckMenuItem references: {}", e);
-                }
-            }
+            // Only show models allowed by the profile's lists and filter
+            let loaded_models = active_profile.tray_models(&loaded_models);
 
             // Model selection submenu - use active profile's model
             let model_display = if active_profile.api_config.model.is_empty() {
@@ -2136,85 +8760,17 @@ async fn main() {
             };
 
             // Create model submenu with load action and available models
-            let mut model_submenu_builder = SubmenuBuilder::new(app, &format!("Model: {}", model_display));
-            
-            // Always add "Load Models" option first
-            let load_models_item = MenuItemBuilder::new("Load Models").id("load_models").build(app)?;
-            model_submenu_builder = model_submenu_builder.item(&load_models_item);
-            
-            // If we have loaded models, add them to the menu
-            if !loaded_models.is_empty() {
-                model_submenu_builder = model_submenu_builder.separator();
-                
-                // Store CheckMenuItem references directly during creation
-                let mut check_items_for_storage = std::collections::HashMap::new();
-                
-                // If we have loaded models, add them as CheckMenuItems
-                for model_id in &loaded_models {
-                    let is_current = model_id == &active_profile.api_config.model;
-                    
-                    println!("🔍 [DEBUG] Creating CheckMenuItem for model '{}', checked={}", model_id, is_current);
-                    
-                    let model_item = CheckMenuItemBuilder::new(model_id)
-                        .id(&format!("model_{}", model_id))
-                        .checked(is_current)
-                        .build(app)?;
-                    
-                    // Store the CheckMenuItem reference immediately
-                    check_items_for_storage.insert(model_id.clone(), model_item.clone());
-                    println!("   📝 Stored CheckMenuItem reference for '{}'", model_id);
-                    
-                    model_submenu_builder = model_submenu_builder.item(&model_item);
-                }
-                
-                println!("✓ [DEBUG] Added {} models to tray menu with CheckMenuItem support", loaded_models.len());
-                println!("📦 [DEBUG] Prepared {} CheckMenuItem references for storage", check_items_for_storage.len());
-                
-                // Store references immediately without async delay
-                println!("🔄 [DEBUG] Storing CheckMenuItem references immediately...");
-                {
-                    let storage_count = check_items_for_storage.len();
-                    
-                    // Use try_lock to avoid blocking in async context
-                    match app_state.model_check_items.try_lock() {
-                        Ok(mut model_check_items) => {
-                            println!("📋 [DEBUG] Before storage - current references count: {}", model_check_items.len());
-                            *model_check_items = check_items_for_storage;
-                            println!("✅ [DEBUG] After storage - new references count: {}", model_check_items.len());
-                            
-                            println!("🎉 [DEBUG] CheckMenuItem references stored successfully for dynamic updates!");
-                            println!("   - Expected count: {}", storage_count);
-                            println!("   - Actual count: {}", model_check_items.len());
-                            
-                            if model_check_items.len() != storage_count {
-                                println!("⚠️  [DEBUG] Count mismatch detected in CheckMenuItem storage!");
-                            }
-                            
-                            // Debug list stored model IDs
-                            let stored_ids: Vec<_> = model_check_items.keys().collect();
-                            println!("📑 [DEBUG] Stored model IDs: {:?}", stored_ids);
-                        }
-                        Err(e) => {
-                            println!("❌ [DEBUG] Failed to acquire lock for CheckMenuItem storage: {}", e);
-                            println!("   CheckMenuItem references will not be available for dynamic updates");
-                        }
-                    }
-                }
-            }
-            
-            let model_submenu = model_submenu_builder.build()?;
-            
-            // Store the model submenu reference for dynamic updates
-            println!("🔄 [DEBUG] Storing model submenu reference for title updates...");
-            match app_state.model_submenu.try_lock() {
-                Ok(mut submenu_ref) => {
-                    *submenu_ref = Some(model_submenu.clone());
-                    println!("✅ [DEBUG] Model submenu reference stored successfully");
-                }
-                Err(e) => {
-                    println!("❌ [DEBUG] Failed to store model submenu reference: {}", e);
-                }
+            let model_submenu = SubmenuBuilder::new(app, &format!("Model: {}", model_display)).build()?;
+            let check_items_for_storage = fill_model_submenu(app.handle(), &model_submenu, &active_profile, &loaded_models)?;
+            debug!("✓ Added {} models to tray menu with CheckMenuItem support", loaded_models.len());
+
+            // Prompt preset submenu for the active profile
+            let preset_items = build_preset_check_items(app.handle(), &active_profile)?;
+            let mut preset_submenu_builder = SubmenuBuilder::new(app, &format!("Prompt: {}", active_profile.active_preset_name()));
+            for (_, preset_item) in &preset_items {
+                preset_submenu_builder = preset_submenu_builder.item(preset_item);
             }
+            let preset_submenu = preset_submenu_builder.build()?;
 
             // Hotkey display - show both global and switch hotkeys
             let formatted_global_hotkey = format_hotkey_for_display(&initial_config.global_hotkey);
@@ -2236,17 +8792,31 @@ async fn main() {
                 .id("toggle_sound")
                 .build(app)?;
 
+            let hotkeys_text = if initial_config.hotkeys_enabled { "Enabled" } else { "Disabled" };
+            let hotkeys_item = MenuItemBuilder::new(&format!("Hotkeys: {}", hotkeys_text))
+                .id("toggle_hotkeys")
+                .build(app)?;
+
+            let last_status_item = MenuItemBuilder::new(&format_last_status(None, std::time::Instant::now()))
+                .id("last_status_info")
+                .enabled(false)
+                .build(app)?;
+
             let quit_item = MenuItemBuilder::new("Quit").id("quit").build(app)?;
 
             // Build comprehensive menu
             let menu = MenuBuilder::new(app)
                 .item(&profile_submenu)
                 .item(&model_submenu)
+                .item(&preset_submenu)
                 .item(&global_hotkey_item)
                 .item(&switch_hotkey_item)
                 .item(&sound_item)
+                .item(&hotkeys_item)
+                .item(&last_status_item)
                 .separator()
                 .item(&settings_item)
+                .item(&open_config_dir_item)
                 .separator()
                 .item(&quit_item)
                 .build()?;
@@ -2261,29 +8831,414 @@ async fn main() {
             let icon = tauri::image::Image::new_owned(icon.into_raw(), width, height);
 
             // Before creating tray, store references to items we want to update dynamically
-            {
-                let app_state = app.state::<AppState>();
-                if let Ok(mut g) = app_state.global_hotkey_item.try_lock() { *g = Some(global_hotkey_item.clone()); }
-                if let Ok(mut s) = app_state.switch_hotkey_item.try_lock() { *s = Some(switch_hotkey_item.clone()); }
-                if let Ok(mut snd) = app_state.sound_item.try_lock() { *snd = Some(sound_item.clone()); }
-                if let Ok(mut p) = app_state.profile_submenu.try_lock() { *p = Some(profile_submenu.clone()); }
-                if let Ok(mut m) = app_state.model_submenu.try_lock() { *m = Some(model_submenu.clone()); };
+            if let Err(e) = app_state.store_menu_refs(TrayMenuRefs {
+                profile_submenu: profile_submenu.clone(),
+                profile_check_items: profile_check_items_for_storage,
+                model_submenu: model_submenu.clone(),
+                model_check_items: check_items_for_storage,
+                preset_submenu: preset_submenu.clone(),
+                preset_check_items: preset_items.into_iter().collect(),
+                global_hotkey_item: global_hotkey_item.clone(),
+                switch_hotkey_item: switch_hotkey_item.clone(),
+                sound_item: sound_item.clone(),
+                hotkeys_item: hotkeys_item.clone(),
+                last_status_item: last_status_item.clone(),
+            }) {
+                error!("❌ {}", e);
             }
 
             // Create tray using the helper function
             let _tray = create_tray_icon_with_menu(&app.handle(), icon, menu)
                 .map_err(|e| {
-                    eprintln!("Failed to create tray icon: {}", e);
+                    error!("Failed to create tray icon: {}", e);
                     format!("Failed to create tray icon: {}", e)
                 })?;
 
             // Store the tray icon in app state for dynamic menu updates
             // Note: Skip storing in setup due to async limitations
-            println!("Tray icon created successfully with {} models", loaded_models.len());
+            info!("Tray icon created successfully with {} models", loaded_models.len());
 
-            println!("Comprehensive tray menu created successfully");
+            info!("Comprehensive tray menu created successfully");
             Ok(())
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sse_events(chunks: &[&[u8]]) -> Vec<String> {
+        let mut parser = SseParser::default();
+        let mut events: Vec<String> = chunks.iter().flat_map(|chunk| parser.push(chunk)).collect();
+        events.extend(parser.finish());
+        events
+    }
+
+    #[test]
+    fn sse_crlf_split_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"data: a\r").is_empty());
+        // 拆开的 \r\n 不能被当成两个换行，否则会提前分发 "a"
+        assert_eq!(parser.push(b"\ndata: b\r\n\r\n"), vec!["a\nb".to_string()]);
+        assert!(parser.finish().is_empty());
+    }
+
+    #[test]
+    fn sse_multibyte_utf8_split_across_chunks() {
+        let bytes = "data: 你好\n\n".as_bytes();
+        // "你" 占3个字节，在第二个字节处拆开
+        assert_eq!(sse_events(&[&bytes[..7], &bytes[7..]]), vec!["你好".to_string()]);
+    }
+
+    #[test]
+    fn sse_comment_lines_are_ignored() {
+        assert_eq!(
+            sse_events(&[b": keep-alive\n\n", b"data: x\n", b": ping\n\n"]),
+            vec!["x".to_string()]
+        );
+    }
+
+    #[test]
+    fn sse_multiline_data_joined_with_newline() {
+        assert_eq!(sse_events(&[b"data: first\ndata: second\n\n"]), vec!["first\nsecond".to_string()]);
+    }
+
+    #[test]
+    fn sse_finish_flushes_trailing_event() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"data: tail").is_empty());
+        assert_eq!(parser.finish(), vec!["tail".to_string()]);
+
+        assert_eq!(sse_events(&[b"data: tail\r"]), vec!["tail".to_string()]);
+    }
+
+    #[test]
+    fn sse_done_marker() {
+        assert_eq!(
+            sse_events(&[b"data: {\"a\":1}\n\ndata: [DONE]\n\n"]),
+            vec!["{\"a\":1}".to_string(), "[DONE]".to_string()]
+        );
+        // 不发送空行分隔的服务商：完整JSON后的data行开始新事件
+        assert_eq!(
+            sse_events(&[b"data: {\"a\":1}\ndata: [DONE]\n"]),
+            vec!["{\"a\":1}".to_string(), "[DONE]".to_string()]
+        );
+    }
+
+    fn delta(content: &str, reasoning: &str) -> SseDelta {
+        SseDelta { content: content.to_string(), reasoning: reasoning.to_string() }
+    }
+
+    #[test]
+    fn sse_delta_reads_content_and_reasoning() {
+        assert_eq!(
+            sse_delta(r#"{"choices":[{"delta":{"content":"x = 1"}}]}"#),
+            Some(delta("x = 1", ""))
+        );
+        assert_eq!(
+            sse_delta(r#"{"choices":[{"delta":{"reasoning_content":"thinking"}}]}"#),
+            Some(delta("", "thinking"))
+        );
+        assert_eq!(
+            sse_delta(r#"{"choices":[{"delta":{"reasoning":"thinking"}}]}"#),
+            Some(delta("", "thinking"))
+        );
+        assert_eq!(
+            sse_delta(r#"{"choices":[{"delta":{"content":"x = 1","reasoning_content":"thinking"}}]}"#),
+            Some(delta("x = 1", "thinking"))
+        );
+        assert_eq!(sse_delta("[DONE]"), None);
+    }
+
+    #[test]
+    fn combine_reasoning_content_only() {
+        for include_reasoning in [true, false] {
+            assert_eq!(combine_reasoning(String::new(), "answer".to_string(), include_reasoning), Ok("answer".to_string()));
+        }
+    }
+
+    #[test]
+    fn combine_reasoning_reasoning_only_falls_back_to_reasoning() {
+        for include_reasoning in [true, false] {
+            assert_eq!(combine_reasoning(" thinking\n".to_string(), String::new(), include_reasoning), Ok("thinking".to_string()));
+        }
+    }
+
+    #[test]
+    fn combine_reasoning_both() {
+        assert_eq!(
+            combine_reasoning("thinking".to_string(), "answer".to_string(), true),
+            Ok("<think>\nthinking\n</think>\n\nanswer".to_string())
+        );
+        assert_eq!(combine_reasoning("thinking".to_string(), "answer".to_string(), false), Ok("answer".to_string()));
+    }
+
+    #[test]
+    fn combine_reasoning_empty_is_error() {
+        assert!(combine_reasoning(String::new(), String::new(), true).is_err());
+        assert!(combine_reasoning(String::new(), "  ".to_string(), false).is_err());
+    }
+
+    #[test]
+    fn post_step_variants() {
+        assert_eq!(apply_post_step(&PostStep::StripCodeFences, "```latex\nx^2\n```"), "x^2");
+        assert_eq!(apply_post_step(&PostStep::ExtractLatex, "The answer is $x^2$."), "x^2");
+        // 没有公式时原样保留
+        assert_eq!(apply_post_step(&PostStep::ExtractLatex, "no math here"), "no math here");
+        assert_eq!(apply_post_step(&PostStep::LatexDelimiters(DelimiterStyle::Paren), "a $x$ b"), "a \\(x\\) b");
+        assert_eq!(apply_post_step(&PostStep::Trim, "  x \n"), "x");
+    }
+
+    #[test]
+    fn post_steps_apply_in_order() {
+        let text = "```\nThe answer is $x$\n```";
+        let mut profile = Profile::new("test".to_string());
+
+        // 代码块中的公式不会被提取，先去掉代码块才能提取到公式
+        profile.post_processing = vec![PostStep::ExtractLatex, PostStep::StripCodeFences];
+        assert_eq!(apply_output_transforms(&profile, text), "The answer is $x$");
+
+        profile.post_processing = vec![PostStep::StripCodeFences, PostStep::ExtractLatex];
+        assert_eq!(apply_output_transforms(&profile, text), "x");
+    }
+
+    #[test]
+    fn effective_post_steps_from_legacy_flags() {
+        let mut profile = Profile::new("test".to_string());
+        assert!(profile.effective_post_steps().is_empty());
+
+        profile.strip_code_fences = true;
+        profile.latex_delimiters = DelimiterStyle::Paren;
+        assert_eq!(
+            profile.effective_post_steps(),
+            vec![PostStep::StripCodeFences, PostStep::LatexDelimiters(DelimiterStyle::Paren)]
+        );
+
+        // 旧逻辑中提取公式后不再改写定界符
+        profile.extract_latex_only = true;
+        assert_eq!(profile.effective_post_steps(), vec![PostStep::StripCodeFences, PostStep::ExtractLatex]);
+
+        profile.strip_code_fences = false;
+        assert_eq!(profile.effective_post_steps(), vec![PostStep::ExtractLatex]);
+
+        // 显式配置的post_processing优先于旧选项
+        profile.post_processing = vec![PostStep::Trim];
+        assert_eq!(profile.effective_post_steps(), vec![PostStep::Trim]);
+    }
+
+    #[test]
+    fn orient_image_all_orientations() {
+        // 2x3的图片，每个像素的R/G通道记录原始坐标
+        let source = image::RgbaImage::from_fn(2, 3, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+
+        // 输出坐标 (x, y) 对应的原图坐标
+        let cases: [(u32, (u32, u32), fn(u32, u32) -> (u32, u32)); 8] = [
+            (1, (2, 3), |x, y| (x, y)),
+            (2, (2, 3), |x, y| (1 - x, y)),
+            (3, (2, 3), |x, y| (1 - x, 2 - y)),
+            (4, (2, 3), |x, y| (x, 2 - y)),
+            (5, (3, 2), |x, y| (y, x)),
+            (6, (3, 2), |x, y| (y, 2 - x)),
+            (7, (3, 2), |x, y| (1 - y, 2 - x)),
+            (8, (3, 2), |x, y| (1 - y, x)),
+        ];
+
+        for (orientation, dimensions, source_of) in cases {
+            let oriented = orient_image(image::DynamicImage::ImageRgba8(source.clone()), orientation).to_rgba8();
+            assert_eq!(oriented.dimensions(), dimensions, "orientation {}", orientation);
+
+            let (width, height) = dimensions;
+            for (x, y) in [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)] {
+                let (source_x, source_y) = source_of(x, y);
+                assert_eq!(
+                    oriented.get_pixel(x, y),
+                    source.get_pixel(source_x, source_y),
+                    "orientation {} at ({}, {})", orientation, x, y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn apply_exif_orientation_without_exif_keeps_original() {
+        let mut buffer = Vec::new();
+        image::DynamicImage::ImageRgba8(image::RgbaImage::new(2, 3))
+            .write_to(&mut std::io::Cursor::new(&mut buffer), image::ImageOutputFormat::Png)
+            .unwrap();
+        assert_eq!(apply_exif_orientation(&buffer), Ok(None));
+    }
+
+    #[test]
+    fn extract_latex_each_delimiter() {
+        assert_eq!(extract_latex("inline $a + b$ text"), Some("a + b".to_string()));
+        assert_eq!(extract_latex("display $$\n\\int x\\,dx\n$$"), Some("\\int x\\,dx".to_string()));
+        assert_eq!(extract_latex("paren \\(c^2\\) text"), Some("c^2".to_string()));
+        assert_eq!(extract_latex("bracket \\[d_1\\] text"), Some("d_1".to_string()));
+    }
+
+    #[test]
+    fn extract_latex_multiple_blocks() {
+        assert_eq!(
+            extract_latex("First $a$, then $$b$$ and finally \\(c\\)."),
+            Some("a\nb\nc".to_string())
+        );
+    }
+
+    #[test]
+    fn extract_latex_skips_escaped_dollar() {
+        assert_eq!(extract_latex("costs \\$5, where $x = 5$"), Some("x = 5".to_string()));
+        assert_eq!(extract_latex("costs \\$5 and \\$10"), None);
+    }
+
+    #[test]
+    fn extract_latex_without_math() {
+        assert_eq!(extract_latex("Just some plain text."), None);
+        assert_eq!(extract_latex(""), None);
+    }
+
+    #[test]
+    fn normalize_latex_delimiters_each_style() {
+        let text = "a $x$ b $$y$$";
+        assert_eq!(normalize_latex_delimiters(text, DelimiterStyle::Keep), text);
+        assert_eq!(normalize_latex_delimiters(text, DelimiterStyle::Dollar), "a $x$ b $$y$$");
+        assert_eq!(normalize_latex_delimiters(text, DelimiterStyle::Paren), "a \\(x\\) b \\[y\\]");
+        assert_eq!(normalize_latex_delimiters(text, DelimiterStyle::Katex), "a $x$ b $$\ny\n$$");
+        assert_eq!(normalize_latex_delimiters("\\(x\\) and \\[y\\]", DelimiterStyle::Dollar), "$x$ and $$y$$");
+    }
+
+    #[test]
+    fn normalize_latex_delimiters_nested_braces() {
+        // 花括号里的 $ 和 \) 不会提前结束公式
+        assert_eq!(
+            normalize_latex_delimiters("$\\text{a $b$}$", DelimiterStyle::Paren),
+            "\\(\\text{a $b$}\\)"
+        );
+        assert_eq!(
+            normalize_latex_delimiters("\\(\\frac{a}{b\\)}\\)", DelimiterStyle::Dollar),
+            "$\\frac{a}{b\\)}$"
+        );
+    }
+
+    #[test]
+    fn normalize_latex_delimiters_escaped_dollar() {
+        assert_eq!(
+            normalize_latex_delimiters("price \\$5 and $x$", DelimiterStyle::Paren),
+            "price \\$5 and \\(x\\)"
+        );
+    }
+
+    #[test]
+    fn normalize_latex_delimiters_currency() {
+        let text = "It costs $5 and $10 in total.";
+        assert_eq!(normalize_latex_delimiters(text, DelimiterStyle::Paren), text);
+    }
+
+    #[test]
+    fn normalize_latex_delimiters_leaves_code_untouched() {
+        assert_eq!(
+            normalize_latex_delimiters("`$x$` and $y$", DelimiterStyle::Paren),
+            "`$x$` and \\(y\\)"
+        );
+        let block = "```\n$$z$$\n```";
+        assert_eq!(normalize_latex_delimiters(block, DelimiterStyle::Paren), block);
+    }
+
+    #[test]
+    fn find_math_end_tracks_brace_depth() {
+        let chars: Vec<char> = "a{$}b$c".chars().collect();
+        assert_eq!(find_math_end(&chars, 0, &['$']), Some(5));
+        let chars: Vec<char> = "a\\$b$".chars().collect();
+        assert_eq!(find_math_end(&chars, 0, &['$']), Some(4));
+        let chars: Vec<char> = "{unclosed $".chars().collect();
+        assert_eq!(find_math_end(&chars, 0, &['$']), None);
+    }
+
+    #[test]
+    fn strip_code_fences_full_fence() {
+        assert_eq!(strip_code_fences("```\nx = 1\ny = 2\n```"), "x = 1\ny = 2");
+    }
+
+    #[test]
+    fn strip_code_fences_with_language_tag() {
+        assert_eq!(strip_code_fences("```latex\n\\frac{a}{b}\n```"), "\\frac{a}{b}");
+    }
+
+    #[test]
+    fn strip_code_fences_opening_only() {
+        // 输出被截断，只有开头的fence
+        assert_eq!(strip_code_fences("```markdown\nx = 1"), "x = 1");
+    }
+
+    #[test]
+    fn strip_code_fences_closing_only() {
+        let text = "x = 1\n```";
+        assert_eq!(strip_code_fences(text), text);
+    }
+
+    #[test]
+    fn strip_code_fences_keeps_inner_fences() {
+        let text = "```\na\n```\nsome text\n```\nb\n```";
+        assert_eq!(strip_code_fences(text), text);
+    }
+
+    #[test]
+    fn strip_code_fences_surrounding_whitespace() {
+        assert_eq!(strip_code_fences("  \n```python\nprint(1)\n```\n  "), "print(1)");
+    }
+
+    #[test]
+    fn normalize_hotkey_orders_and_lowercases() {
+        assert_eq!(normalize_hotkey("Shift+Cmd+m"), Ok("cmd+shift+m".to_string()));
+        assert_eq!(normalize_hotkey(" shift + alt + ctrl + cmd + K "), Ok("cmd+ctrl+alt+shift+k".to_string()));
+    }
+
+    #[test]
+    fn normalize_hotkey_folds_aliases() {
+        assert_eq!(normalize_hotkey("Command+Option+Control+K"), Ok("cmd+ctrl+alt+k".to_string()));
+        assert_eq!(normalize_hotkey("Super+Meta+A"), Ok("cmd+a".to_string()));
+        assert_eq!(normalize_hotkey("CommandOrControl+Shift+1"), Ok("cmdorctrl+shift+1".to_string()));
+    }
+
+    #[test]
+    fn normalize_hotkey_rejects_invalid() {
+        assert!(normalize_hotkey("Ctrl+Foo").is_err());
+        assert!(normalize_hotkey("Ctrl+Shift").is_err());
+        assert!(normalize_hotkey("Ctrl+A+B").is_err());
+        assert!(normalize_hotkey("Ctrl++A").is_err());
+        assert!(normalize_hotkey("").is_err());
+    }
+
+    #[test]
+    fn normalize_hotkey_is_idempotent() {
+        for hotkey in ["Shift+Cmd+m", "Option+Control+Space", "CmdOrCtrl+Alt+F5", "meta+shift+Digit1"] {
+            let normalized = normalize_hotkey(hotkey).unwrap();
+            assert_eq!(normalize_hotkey(&normalized), Ok(normalized.clone()), "{}", hotkey);
+        }
+    }
+
+    #[test]
+    fn format_hotkey_macos() {
+        assert_eq!(format_hotkey_for_platform("shift+cmd+m", true), "⇧⌘M");
+        assert_eq!(format_hotkey_for_platform("Cmd+Shift+Alt+Ctrl+KeyK", true), "⌃⌥⇧⌘K");
+        assert_eq!(format_hotkey_for_platform("option+a", true), "⌥A");
+        assert_eq!(format_hotkey_for_platform("super+meta+x", true), "⌘X");
+        assert_eq!(format_hotkey_for_platform("CmdOrCtrl+F5", true), "⌘F5");
+    }
+
+    #[test]
+    fn format_hotkey_other_platforms() {
+        assert_eq!(format_hotkey_for_platform("shift+cmd+m", false), "Shift+Win+M");
+        assert_eq!(format_hotkey_for_platform("Cmd+Shift+Alt+Ctrl+k", false), "Ctrl+Alt+Shift+Win+K");
+        assert_eq!(format_hotkey_for_platform("option+a", false), "Alt+A");
+        assert_eq!(format_hotkey_for_platform("meta+Digit1", false), "Win+1");
+        assert_eq!(format_hotkey_for_platform("CmdOrCtrl+F5", false), "Ctrl+F5");
+    }
+
+    #[test]
+    fn format_hotkey_mixed_case() {
+        assert_eq!(format_hotkey_for_platform("sHiFt+CMD+m", true), "⇧⌘M");
+        assert_eq!(format_hotkey_for_platform("ALT+SPACE", false), "Alt+Space");
+    }
+}